@@ -0,0 +1,8 @@
+-- Tags assigned to the files of the collection
+
+CREATE TABLE IF NOT EXISTS tags
+(
+    file_id         GUID            NOT NULL REFERENCES files(id),
+    tag             VARCHAR(100)    NOT NULL,
+    PRIMARY KEY (file_id, tag)
+);
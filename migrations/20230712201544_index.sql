@@ -0,0 +1,7 @@
+-- Store what the indexer finds out about the files
+
+ALTER TABLE files ADD COLUMN mimetype VARCHAR(100);
+ALTER TABLE files ADD COLUMN size INTEGER;
+ALTER TABLE files ADD COLUMN mtime INTEGER;
+
+CREATE UNIQUE INDEX IF NOT EXISTS files_relative_path ON files (relative_path);
@@ -0,0 +1,17 @@
+-- Deleted files and folders are kept in the trash until they are purged
+
+CREATE TABLE IF NOT EXISTS trash
+(
+    id              GUID            PRIMARY KEY NOT NULL,
+    relative_path   VARCHAR(500)    NOT NULL,
+    path            VARCHAR(1500)   NOT NULL,
+    is_dir          BOOLEAN         NOT NULL,
+    deleted_at      INTEGER         NOT NULL
+);
+
+-- Trashed files keep their original path and their tags
+ALTER TABLE files ADD COLUMN trash_id GUID REFERENCES trash(id);
+
+DROP INDEX IF EXISTS files_relative_path;
+CREATE UNIQUE INDEX files_relative_path ON files (relative_path) WHERE trash_id IS NULL;
+CREATE INDEX files_trash_id ON files (trash_id);
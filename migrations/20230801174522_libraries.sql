@@ -0,0 +1,8 @@
+-- Files and trash entries belong to a library. Existing entries are assigned
+-- to a library at startup, see `index::adopt_unassigned`.
+
+ALTER TABLE files ADD COLUMN library VARCHAR(100) NOT NULL DEFAULT '';
+ALTER TABLE trash ADD COLUMN library VARCHAR(100) NOT NULL DEFAULT '';
+
+DROP INDEX IF EXISTS files_relative_path;
+CREATE UNIQUE INDEX files_relative_path ON files (library, relative_path) WHERE trash_id IS NULL;
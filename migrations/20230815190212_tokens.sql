@@ -0,0 +1,13 @@
+-- Bearer tokens of the API, only the hash of the secret is stored
+
+CREATE TABLE IF NOT EXISTS tokens (
+  id GUID PRIMARY KEY NOT NULL,
+  name VARCHAR(100) NOT NULL,
+  hash VARCHAR(64) NOT NULL,
+  scopes VARCHAR(100) NOT NULL,
+  created_at INTEGER NOT NULL,
+  expires_at INTEGER
+);
+
+CREATE UNIQUE INDEX tokens_name ON tokens (name);
+CREATE UNIQUE INDEX tokens_hash ON tokens (hash);
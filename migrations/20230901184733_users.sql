@@ -0,0 +1,27 @@
+-- User accounts logging in with a password, and their sessions. Only the
+-- argon2 hash of the passwords and the hash of the session secrets are stored
+
+CREATE TABLE IF NOT EXISTS users (
+  id GUID PRIMARY KEY NOT NULL,
+  name VARCHAR(100) NOT NULL,
+  password_hash VARCHAR(200) NOT NULL,
+  role VARCHAR(20) NOT NULL,
+  created_at INTEGER NOT NULL
+);
+
+CREATE UNIQUE INDEX users_name ON users (name);
+
+CREATE TABLE IF NOT EXISTS sessions (
+  id VARCHAR(64) PRIMARY KEY NOT NULL,
+  user_id GUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  csrf_token VARCHAR(64) NOT NULL,
+  created_at INTEGER NOT NULL,
+  expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX sessions_user_id ON sessions (user_id);
+
+-- Who added a tag: the name of a user, or `token:<name>` for a token
+
+ALTER TABLE tags ADD COLUMN created_by VARCHAR(110);
+ALTER TABLE tags ADD COLUMN created_at INTEGER;
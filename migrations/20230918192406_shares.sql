@@ -0,0 +1,22 @@
+-- Links sharing a folder or a file of a library without an account, and
+-- the key signing them. Only the argon2 hash of the passwords is stored
+
+CREATE TABLE IF NOT EXISTS shares (
+  id GUID PRIMARY KEY NOT NULL,
+  library VARCHAR(100) NOT NULL,
+  path TEXT NOT NULL,
+  password_hash VARCHAR(200),
+  sizes VARCHAR(100) NOT NULL,
+  created_by VARCHAR(110),
+  created_at INTEGER NOT NULL,
+  expires_at INTEGER NOT NULL,
+  revoked_at INTEGER,
+  access_count INTEGER NOT NULL DEFAULT 0,
+  last_accessed_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS signing_keys (
+  name VARCHAR(50) PRIMARY KEY NOT NULL,
+  secret VARCHAR(100) NOT NULL,
+  created_at INTEGER NOT NULL
+);
@@ -0,0 +1,33 @@
+-- Store the relative paths percent-encoded like the paths of the urls,
+-- so that filenames which aren't valid UTF-8 are kept apart.
+-- Paths which have already been stored lossily are replaced by the next
+-- indexing.
+
+CREATE TEMPORARY TABLE encoded_paths AS
+WITH RECURSIVE chars (id, rest, encoded) AS (
+    SELECT id, relative_path, '' FROM files
+    UNION ALL
+    SELECT
+        id,
+        substr(rest, 2),
+        encoded || CASE
+            WHEN substr(rest, 1, 1) GLOB '[A-Za-z0-9]' OR substr(rest, 1, 1) IN ('-', '.', '_', '~', '/')
+                THEN substr(rest, 1, 1)
+            ELSE '%' || substr(hex(substr(rest, 1, 1)), 1, 2)
+                || CASE WHEN length(hex(substr(rest, 1, 1))) > 2 THEN '%' || substr(hex(substr(rest, 1, 1)), 3, 2) ELSE '' END
+                || CASE WHEN length(hex(substr(rest, 1, 1))) > 4 THEN '%' || substr(hex(substr(rest, 1, 1)), 5, 2) ELSE '' END
+                || CASE WHEN length(hex(substr(rest, 1, 1))) > 6 THEN '%' || substr(hex(substr(rest, 1, 1)), 7, 2) ELSE '' END
+        END
+    FROM chars
+    WHERE rest <> ''
+)
+SELECT id, encoded FROM chars WHERE rest = '';
+
+CREATE UNIQUE INDEX encoded_paths_id ON encoded_paths (id);
+
+UPDATE files SET relative_path = (SELECT encoded FROM encoded_paths WHERE encoded_paths.id = files.id);
+
+DROP TABLE encoded_paths;
+
+-- The trash already stores the encoded path
+UPDATE trash SET relative_path = path;
@@ -0,0 +1,5 @@
+-- The key signing the share links is created once, instead of on their
+-- first use
+
+INSERT OR IGNORE INTO signing_keys (name, secret, created_at)
+VALUES ('shares', lower(hex(randomblob(32))), CAST(strftime('%s', 'now') AS INTEGER));
@@ -0,0 +1,4 @@
+-- The files the indexer doesn't find anymore are kept with their tags,
+-- e.g. while a disk is unmounted, until they are found again or purged
+
+ALTER TABLE files ADD COLUMN missing_since INTEGER;
@@ -0,0 +1,5 @@
+-- The capture date of the images, from their EXIF data, so that the
+-- listings can be sorted by date without reading every image.
+-- NULL until the file is indexed again, 0 if it has no capture date
+
+ALTER TABLE files ADD COLUMN taken_at INTEGER;
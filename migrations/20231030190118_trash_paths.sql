@@ -0,0 +1,2 @@
+-- The relative path of the trash entries is the same as their encoded path
+ALTER TABLE trash DROP COLUMN relative_path;
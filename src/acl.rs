@@ -0,0 +1,192 @@
+use crate::{auth::Principal, users::Role, AccessRule};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Component, Path, PathBuf}
+};
+
+/// The subject of the rules applying to everyone, including the anonymous
+/// requests if authentication isn't required.
+pub const EVERYONE: &str = "*";
+
+/// What an access rule allows beneath its path. Every permission includes
+/// the previous ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// The files and folders are hidden: they aren't listed, served,
+    /// annotated nor exported.
+    None,
+
+    /// Browsing and downloading.
+    Read,
+
+    /// Adding and removing tags.
+    Annotate,
+
+    /// Modifying the content: uploads, moves, deletions and the trash.
+    Write
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::None => "none",
+            Permission::Read => "read",
+            Permission::Annotate => "annotate",
+            Permission::Write => "write"
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<Role> for Permission {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::Viewer => Permission::Read,
+            Role::Annotator => Permission::Annotate,
+            Role::Admin => Permission::Write
+        }
+    }
+}
+
+/// What a request may do in a library, according to the access rules of
+/// the library applying to the user or the token making the request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Access {
+    /// The paths of the rules applying to the request, relative to the root
+    /// folder, with their permissions, in the order of the configuration.
+    rules: Vec<(PathBuf, Permission)>,
+
+    /// The permission of the role of the principal, which the rules never
+    /// exceed.
+    max: Permission
+}
+
+impl Default for Access {
+    /// The access of the requests when there is no rule: anything.
+    fn default() -> Self {
+        Self {
+            rules: vec![],
+            max: Permission::Write
+        }
+    }
+}
+
+impl Access {
+    /// Selects the rules among `rules` applying to `principal`, or to the
+    /// anonymous requests if `None`.
+    ///
+    /// The subjects of a rule are names of users, `token:<name>` for tokens,
+    /// `@<group>` for the groups configured in `groups` and for the groups
+    /// named after the roles (e.g. `@annotator`), and `*` for everyone.
+    pub fn new(rules: &[AccessRule], groups: &BTreeMap<String, Vec<String>>, principal: Option<&Principal>) -> Self {
+        let author = principal.map(Principal::author);
+        let role = principal.map(Principal::role);
+        let matches = |subject: &str| {
+            if subject == EVERYONE {
+                return true;
+            }
+            let Some(author) = &author else {
+                return false;
+            };
+            match subject.strip_prefix('@') {
+                Some(group) => role.is_some_and(|role| role.as_str() == group)
+                    || groups.get(group).is_some_and(|members| members.contains(author)),
+                None => subject == author
+            }
+        };
+
+        Self {
+            rules: rules.iter()
+                .filter(|rule| rule.subjects.iter().any(|subject| matches(subject)))
+                .map(|rule| (rule_path(&rule.path), rule.permission))
+                .collect(),
+            max: role.map(Permission::from).unwrap_or(Permission::Write)
+        }
+    }
+
+    /// Returns the permission on `relative`, a path relative to the root
+    /// folder: the permission of the last rule whose path contains
+    /// `relative`, capped by the role of the principal.
+    pub fn permission(&self, relative: &Path) -> Permission {
+        let relative = normalize(relative);
+        self.rules.iter()
+            .rev()
+            .find(|(path, _)| relative.starts_with(path))
+            .map(|(_, permission)| *permission)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    /// Checks whether `permission` is granted on `relative`.
+    pub fn allows(&self, relative: &Path, permission: Permission) -> bool {
+        self.permission(relative) >= permission
+    }
+
+    /// Checks whether `permission` is granted on `relative` and on
+    /// everything beneath it, e.g. before copying or moving a whole folder.
+    pub fn allows_all(&self, relative: &Path, permission: Permission) -> bool {
+        let relative = normalize(relative);
+        self.allows(&relative, permission) && self.rules.iter()
+            .filter(|(path, _)| path.starts_with(&relative))
+            .all(|(path, _)| self.allows(path, permission))
+    }
+
+    /// Checks whether `relative` is hidden: nothing is granted on it, nor
+    /// on anything beneath it. A folder holding a folder granted by a later
+    /// rule isn't hidden, so that it can be browsed, but everything else in
+    /// it is.
+    pub fn is_hidden(&self, relative: &Path) -> bool {
+        let relative = normalize(relative);
+        self.permission(&relative) == Permission::None && !self.rules.iter()
+            .filter(|(path, _)| path.starts_with(&relative))
+            .any(|(path, _)| self.permission(path) > Permission::None)
+    }
+}
+
+/// Checks that `rules` can be applied: their paths are relative to the root
+/// folder and their groups are configured in `groups` or named after a role.
+pub fn validate(rules: &[AccessRule], groups: &BTreeMap<String, Vec<String>>) -> anyhow::Result<()> {
+    for rule in rules {
+        let is_relative = Path::new(rule.path.trim_matches('/')).components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !is_relative {
+            anyhow::bail!("Invalid access rule path {:?}, expected a path relative to the root folder", rule.path);
+        }
+        if rule.subjects.is_empty() {
+            anyhow::bail!("The access rule of {:?} has no subject", rule.path);
+        }
+        for group in rule.subjects.iter().filter_map(|subject| subject.strip_prefix('@')) {
+            if !groups.contains_key(group) && group.parse::<Role>().is_err() {
+                anyhow::bail!("Unknown group {group:?} in the access rule of {:?}", rule.path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the normal components of `relative`, so that it can be compared
+/// with the paths of the rules.
+fn normalize(relative: &Path) -> PathBuf {
+    relative.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// The path of a rule without its leading and trailing slashes, so that
+/// `/family/` and `family` are the same.
+fn rule_path(path: &str) -> PathBuf {
+    PathBuf::from(path.trim_matches('/'))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,298 @@
+use super::{Access, Permission};
+use crate::{
+    auth::{self, Principal, Scope, Token},
+    index,
+    test_utils::{bearer, call, library_conf, make_pool, make_root, read_text},
+    users::{Role, User},
+    AccessRule,
+    AppConf,
+    AppState,
+    AuthConf,
+    LibraryConf,
+    DEFAULT_LIBRARY
+};
+
+use axum::{
+    http::{Method, StatusCode},
+    response::Response,
+    Router
+};
+use rstest::*;
+use serde_json::json;
+use std::{collections::BTreeMap, fs, path::Path};
+
+fn rule(path: &str, subjects: &[&str], permission: Permission) -> AccessRule {
+    AccessRule {
+        path: path.to_string(),
+        subjects: subjects.iter().map(|subject| subject.to_string()).collect(),
+        permission
+    }
+}
+
+fn user(name: &str, role: Role) -> Principal {
+    Principal::User(User {
+        id: name.to_string(),
+        name: name.to_string(),
+        role,
+        created_at: 0
+    })
+}
+
+fn token(name: &str, scopes: &[Scope]) -> Principal {
+    Principal::Token(Token {
+        id: name.to_string(),
+        name: name.to_string(),
+        scopes: scopes.to_vec(),
+        created_at: 0,
+        expires_at: None
+    })
+}
+
+fn rules() -> (Vec<AccessRule>, BTreeMap<String, Vec<String>>) {
+    let rules = vec![
+        rule("/family/", &["@annotator"], Permission::None),
+        rule("family", &["@family"], Permission::Write),
+        rule("medical", &["*"], Permission::None),
+        rule("medical/alice", &["alice"], Permission::Read),
+        rule("archive", &["*"], Permission::Read),
+        rule("", &["token:scanner"], Permission::Annotate)
+    ];
+    let groups = BTreeMap::from([("family".to_string(), vec!["alice".to_string(), "token:backup".to_string()])]);
+    (rules, groups)
+}
+
+#[rstest]
+// the rules are capped by the role
+#[case(user("bob", Role::Viewer), "photos/cat.jpg", Permission::Read)]
+#[case(user("bob", Role::Admin), "photos/cat.jpg", Permission::Write)]
+#[case(user("bob", Role::Admin), "archive/2001/cat.jpg", Permission::Read)]
+// the last matching rule applies
+#[case(user("bob", Role::Annotator), "family/cat.jpg", Permission::None)]
+#[case(user("alice", Role::Annotator), "family/cat.jpg", Permission::Annotate)]
+#[case(user("alice", Role::Admin), "family", Permission::Write)]
+#[case(token("backup", &[Scope::Admin]), "family/cat.jpg", Permission::Write)]
+#[case(token("backup", &[Scope::Annotate]), "family/cat.jpg", Permission::Annotate)]
+#[case(user("bob", Role::Admin), "familyalbum", Permission::Write)]
+// a later rule can grant a folder beneath a hidden folder
+#[case(user("bob", Role::Admin), "medical/alice/scan.pdf", Permission::None)]
+#[case(user("alice", Role::Admin), "medical/alice/scan.pdf", Permission::Read)]
+#[case(user("alice", Role::Admin), "medical/bob", Permission::None)]
+#[case(token("scanner", &[Scope::Admin]), "photos", Permission::Annotate)]
+// even if a previous rule is more specific
+#[case(token("scanner", &[Scope::Admin]), "archive", Permission::Annotate)]
+fn permission_test(#[case] principal: Principal, #[case] path: &str, #[case] expected: Permission) {
+    let (rules, groups) = rules();
+    let access = Access::new(&rules, &groups, Some(&principal));
+    assert_eq!(access.permission(Path::new(path)), expected);
+}
+
+#[rstest]
+#[case(Some(user("bob", Role::Annotator)), &["family", "family/cat.jpg", "medical", "medical/alice"], &["", "photos"])]
+// a folder holding a granted folder can be browsed
+#[case(Some(user("alice", Role::Annotator)), &["medical/bob", "medical/cat.jpg"], &["family", "medical", "medical/alice"])]
+// the anonymous requests only match the rules for everyone
+#[case(None, &["medical", "medical/alice"], &["family"])]
+fn is_hidden_test(#[case] principal: Option<Principal>, #[case] hidden: &[&str], #[case] visible: &[&str]) {
+    let (rules, groups) = rules();
+    let access = Access::new(&rules, &groups, principal.as_ref());
+    for path in hidden {
+        assert!(access.is_hidden(Path::new(path)), "{path}");
+    }
+    for path in visible {
+        assert!(!access.is_hidden(Path::new(path)), "{path}");
+    }
+
+    assert!(!Access::default().is_hidden(Path::new("")));
+}
+
+#[test]
+fn allows_all_test() {
+    // the rules beneath a folder apply to the operations on the whole folder
+    let (rules, groups) = rules();
+    let access = Access::new(&rules, &groups, Some(&user("bob", Role::Admin)));
+
+    assert!(access.allows_all(Path::new("photos"), Permission::Write));
+    assert!(access.allows_all(Path::new("archive/2001"), Permission::Read));
+    assert!(!access.allows_all(Path::new("archive"), Permission::Write));
+    assert!(!access.allows_all(Path::new(""), Permission::Read));
+    assert!(!access.allows_all(Path::new("/medical/"), Permission::Read));
+}
+
+#[rstest]
+#[case(rule("family", &["@annotator"], Permission::None), true)]
+#[case(rule("family", &["@family"], Permission::None), true)]
+#[case(rule("family", &["@friends"], Permission::None), false)]
+#[case(rule("family", &[], Permission::None), false)]
+#[case(rule("../family", &["*"], Permission::None), false)]
+fn validate_test(#[case] rule: AccessRule, #[case] valid: bool) {
+    let (_, groups) = rules();
+    assert_eq!(super::validate(&[rule], &groups).is_ok(), valid);
+}
+
+/// Makes the application requiring authentication, with a library holding
+/// `family` and `holidays` folders. The family folder is hidden from the
+/// annotators, and tagged. The `holidays/private` folder is hidden from
+/// everyone.
+async fn make_app(name: &str) -> (Router, String, String, std::path::PathBuf) {
+    make_app_with(name, vec![
+        rule("family", &["@annotator"], Permission::None),
+        rule("holidays/private", &["*"], Permission::None)
+    ]).await
+}
+
+/// Makes the application of [`make_app`], with the access rules `access`.
+async fn make_app_with(name: &str, access: Vec<AccessRule>) -> (Router, String, String, std::path::PathBuf) {
+    let root = make_root(&format!("acl-{name}"));
+    for folder in ["family", "holidays", "holidays/private"] {
+        fs::create_dir_all(root.join(folder)).unwrap();
+        fs::copy("data/penguins.jpg", root.join(folder).join("penguins.jpg")).unwrap();
+    }
+
+    let pool = make_pool().await;
+    let library = LibraryConf {
+        access,
+        ..library_conf(&root)
+    };
+    let exclusions = library.exclusions().unwrap();
+    index::index_folder(&pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    sqlx::query("INSERT INTO tags (file_id, tag) SELECT id, 'grandma' FROM files WHERE relative_path LIKE 'family/%'")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (_, annotator) = auth::create(&pool, "annotator", &[Scope::Annotate], None).await.unwrap();
+    let (_, admin) = auth::create(&pool, "admin", &[Scope::Admin], None).await.unwrap();
+    let conf = AppConf {
+        auth: AuthConf::default(),
+        libraries: [(DEFAULT_LIBRARY.to_string(), library)].into(),
+        ..Default::default()
+    };
+
+    (crate::app(AppState::new(conf, pool)), annotator, admin, root)
+}
+
+async fn send(app: &Router, method: Method, uri: &str, secret: &str, body: Option<serde_json::Value>) -> Response {
+    call(app, method, uri, bearer(secret), body).await
+}
+
+#[tokio::test]
+async fn hidden_folder_test() {
+    // a hidden folder doesn't leak through any route
+    let (app, annotator, admin, root) = make_app("hidden").await;
+    let data = format!("/data/{DEFAULT_LIBRARY}");
+    let tags = format!("/tags/{DEFAULT_LIBRARY}");
+
+    for uri in [data.clone(), format!("{data}?depth=3"), format!("{data}?recursive=flat"), format!("{data}?tag=grandma")] {
+        let mut response = send(&app, Method::GET, &uri, &annotator, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!read_text(&mut response).await.contains("family"), "{uri}");
+
+        let mut response = send(&app, Method::GET, &uri, &admin, None).await;
+        assert!(read_text(&mut response).await.contains("family"), "{uri}");
+    }
+
+    let response = send(&app, Method::GET, &format!("{data}/family/penguins.jpg"), &annotator, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(&app, Method::GET, &format!("{tags}/family"), &annotator, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let mut response = send(&app, Method::GET, &tags, &annotator, None).await;
+    assert_eq!(read_text(&mut response).await, "[]");
+
+    // tagging the whole library doesn't reach the hidden files
+    let body = json!({ "tags": ["penguins"] });
+    let mut response = send(&app, Method::POST, &tags, &annotator, Some(body)).await;
+    assert_eq!(read_text(&mut response).await, r#"["penguins"]"#);
+    let mut response = send(&app, Method::GET, &format!("{tags}/family"), &admin, None).await;
+    assert_eq!(read_text(&mut response).await, r#"["grandma"]"#);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn granted_subfolder_test() {
+    // hiding everything then granting a folder only shows that folder and
+    // the folders leading to it
+    let (app, annotator, _, root) = make_app_with("granted", vec![
+        rule("", &["@annotator"], Permission::None),
+        rule("holidays/private", &["@annotator"], Permission::Annotate)
+    ]).await;
+    let data = format!("/data/{DEFAULT_LIBRARY}");
+
+    let mut response = send(&app, Method::GET, "/data", &annotator, None).await;
+    assert!(read_text(&mut response).await.contains(DEFAULT_LIBRARY));
+
+    for (uri, expected) in [(data.clone(), "holidays"), (format!("{data}/holidays"), "private")] {
+        let mut response = send(&app, Method::GET, &uri, &annotator, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        let listing = read_text(&mut response).await;
+        assert!(listing.contains(expected), "{uri}");
+        assert!(!listing.contains("family") && !listing.contains("penguins"), "{uri}");
+    }
+
+    for path in ["family", "family/penguins.jpg", "holidays/penguins.jpg"] {
+        let response = send(&app, Method::GET, &format!("{data}/{path}"), &annotator, None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+    }
+    let response = send(&app, Method::GET, &format!("{data}/holidays/private/penguins.jpg"), &annotator, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = json!({ "tags": ["penguins"] });
+    let uri = format!("/tags/{DEFAULT_LIBRARY}/holidays/private/penguins.jpg");
+    let response = send(&app, Method::POST, &uri, &annotator, Some(body.clone())).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let uri = format!("/tags/{DEFAULT_LIBRARY}/holidays");
+    let mut response = send(&app, Method::POST, &uri, &annotator, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", read_text(&mut response).await);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn hidden_subfolder_test() {
+    // a folder containing a hidden folder can't be copied, moved, renamed
+    // nor deleted, its other content can
+    let (app, _, admin, root) = make_app("subfolder").await;
+    let ops = format!("/ops/{DEFAULT_LIBRARY}");
+
+    for (op, body) in [
+        ("copy", json!({ "from": "holidays", "to": "copy" })),
+        ("move", json!({ "from": "holidays", "to": "moved" })),
+        ("rename", json!({ "path": "holidays", "name": "renamed" }))
+    ] {
+        let response = send(&app, Method::POST, &format!("{ops}/{op}"), &admin, Some(body)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{op}");
+    }
+    let uri = format!("/data/{DEFAULT_LIBRARY}/holidays?permanent=true");
+    let response = send(&app, Method::DELETE, &uri, &admin, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(root.join("holidays/private/penguins.jpg").exists());
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+
+    let body = json!({ "from": "holidays/penguins.jpg", "to": "penguins.jpg" });
+    let response = send(&app, Method::POST, &format!("{ops}/copy"), &admin, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(root.join("penguins.jpg").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinked_hidden_folder_test() {
+    // a hidden folder isn't served through a symbolic link to it
+    let (app, annotator, admin, root) = make_app("symlink").await;
+    std::os::unix::fs::symlink("../family", root.join("holidays/family")).unwrap();
+    std::os::unix::fs::symlink("../family/penguins.jpg", root.join("holidays/grandma.jpg")).unwrap();
+    let data = format!("/data/{DEFAULT_LIBRARY}/holidays");
+
+    for uri in [format!("{data}/family"), format!("{data}/family/penguins.jpg"), format!("{data}/grandma.jpg")] {
+        let response = send(&app, Method::GET, &uri, &annotator, None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+
+        let response = send(&app, Method::GET, &uri, &admin, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,62 @@
+use crate::{acl::Access, api::error::ApiError, auth::Principal, AppState, Library};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, MatchedPath, OriginalUri},
+    http::{request::Parts, StatusCode}
+};
+use percent_encoding::percent_decode_str;
+use std::sync::Arc;
+
+/// The name of the route parameter selecting the library
+/// (e.g. `/data/:library/*subpath`).
+pub const LIBRARY_PARAM: &str = ":library";
+
+/// Extracts the library addressed by the `:library` parameter of the route.
+///
+/// Like [`SubPath`](super::subpath::SubPath) it is extracted from the raw
+/// URI, so that it doesn't depend on the rest of the path being valid UTF-8.
+/// The extraction fails with a `404` if the library isn't configured.
+/// The access rules of the library applying to the [`Principal`] of the
+/// request, if any, are selected.
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Library {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let name = route_param(parts, LIBRARY_PARAM)
+            .ok_or_else(|| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_msg("Missing library in route".to_string())
+            )?;
+
+        let mut library = state.conf.library(&name)
+            .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND)
+                .with_msg(format!("library {name} doesn't exist"))
+            )?;
+        let principal = parts.extensions.get::<Principal>();
+        library.access = Access::new(&library.conf.access, &state.conf.auth.groups, principal);
+
+        Ok(library)
+    }
+}
+
+/// Returns the decoded value of the parameter `param` of the route (e.g.
+/// `:library`), taken from the raw URI, or `None` if the route doesn't have
+/// it. Invalid UTF-8 sequences are replaced.
+pub fn route_param(parts: &Parts, param: &str) -> Option<String> {
+    let position = parts.extensions.get::<MatchedPath>()?
+        .as_str()
+        .split('/')
+        .position(|segment| segment == param)?;
+
+    let uri = parts.extensions.get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| parts.uri.clone());
+    let value = uri.path()
+        .split('/')
+        .nth(position)
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+        .unwrap_or_default();
+
+    Some(value)
+}
@@ -0,0 +1,53 @@
+use crate::AppState;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::request::Parts
+};
+use std::{convert::Infallible, sync::Arc};
+
+/// The header set by reverse proxies to the path prefix they strip
+/// from the requests (e.g. `/fotos`).
+pub const FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/// The path prefix of the urls generated for the client, without trailing
+/// slash (e.g. `/fotos`, or empty if the application is served at the root).
+///
+/// It is taken from the `X-Forwarded-Prefix` header if the request has one,
+/// and defaults to the configured `base_path` otherwise. An invalid header
+/// is ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Prefix(pub String);
+
+impl Prefix {
+    /// Returns the url of `path`, an absolute path of the application
+    /// (e.g. `/data/photos`), as seen by the client.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.0)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Prefix {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        let forwarded = parts.headers.get(FORWARDED_PREFIX)
+            .and_then(|prefix| prefix.to_str().ok())
+            .map(|prefix| prefix.trim().trim_end_matches('/'))
+            .filter(|prefix| prefix.is_empty() || is_valid(prefix));
+
+        Ok(Self(forwarded.unwrap_or(&state.conf.base_path).to_string()))
+    }
+}
+
+/// Checks whether `prefix` is a valid, non-empty path prefix: it starts
+/// with `/`, doesn't end with `/` and only contains characters allowed
+/// in the path of an url.
+pub fn is_valid(prefix: &str) -> bool {
+    prefix.starts_with('/')
+        && !prefix.ends_with('/')
+        && !prefix.contains("//")
+        && prefix.bytes().all(|c| c.is_ascii_graphic() && !b"?#<>\"\\^`{|}".contains(&c))
+}
@@ -0,0 +1,158 @@
+use crate::api::error::ApiError;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, MatchedPath, OriginalUri},
+    http::{request::Parts, StatusCode}
+};
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf}
+};
+
+/// The characters that are percent-encoded in the encoded form of a path.
+/// Only the unreserved characters of RFC 3986 are kept as they are.
+const ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The path of a resource, relative to the root folder, as captured by the
+/// wildcard of the route (e.g. `/data/*subpath`).
+///
+/// Unlike `axum::extract::Path<String>` it is extracted from the raw,
+/// percent-encoded URI, therefore it also supports filenames which are not
+/// valid UTF-8, as long as the client sends them back in the encoded form
+/// returned by [`encode_name`].
+///
+/// The extraction fails with a `400` if the path tries to escape the root
+/// folder (e.g. with `..`). If the route has no wildcard, the path is empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubPath(pub PathBuf);
+
+impl SubPath {
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Checks whether the path points to the root folder.
+    pub fn is_root(&self) -> bool {
+        self.0.as_os_str().is_empty()
+    }
+
+    /// The path as a string, with `/` as separator.
+    /// Invalid UTF-8 sequences are replaced, so this is only meant
+    /// for display purposes.
+    pub fn to_string_lossy(&self) -> String {
+        self.0.to_string_lossy().to_string()
+    }
+
+    /// Parses a percent-encoded path.
+    pub fn from_encoded(encoded: &str) -> Result<Self, ApiError> {
+        let mut path = PathBuf::new();
+        for segment in encoded.split('/').filter(|segment| !segment.is_empty()) {
+            path.push(decode_name(segment));
+        }
+
+        let is_relative = path.components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if is_relative {
+            Ok(Self(path))
+        } else {
+            Err(ApiError::new(StatusCode::BAD_REQUEST)
+                .with_msg("Invalid path".to_string()))
+        }
+    }
+}
+
+impl From<&str> for SubPath {
+    fn from(path: &str) -> Self {
+        Self(PathBuf::from(path))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SubPath
+where
+    S: Send + Sync
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let matched = match parts.extensions.get::<MatchedPath>() {
+            Some(matched) => matched.as_str().to_string(),
+            None => return Ok(Self::default())
+        };
+
+        // The position of the wildcard in the route
+        let position = match matched.split('/').position(|segment| segment.starts_with('*')) {
+            Some(position) => position,
+            None => return Ok(Self::default())
+        };
+
+        // Both the matched path and the original uri contain the prefix
+        // of nested routers, unlike `parts.uri`.
+        let uri = parts.extensions.get::<OriginalUri>()
+            .map(|uri| uri.0.clone())
+            .unwrap_or_else(|| parts.uri.clone());
+        let encoded = uri.path()
+            .split('/')
+            .skip(position)
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Self::from_encoded(&encoded)
+    }
+}
+
+/// Returns the lossless, percent-encoded form of the filename `name`.
+/// It is safe to be used as a segment of an url.
+pub fn encode_name(name: &OsStr) -> String {
+    percent_encode(&name_bytes(name), ENCODE_SET).collect()
+}
+
+/// Returns the encoded form of `path`, using `/` as separator.
+pub fn encode_path(path: &Path) -> String {
+    path.iter()
+        .map(encode_name)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The inverse of [`encode_name`].
+pub fn decode_name(encoded: &str) -> OsString {
+    let bytes: Vec<u8> = percent_decode_str(encoded).collect();
+    name_from_bytes(bytes)
+}
+
+/// The inverse of [`encode_path`].
+pub fn decode_path(encoded: &str) -> PathBuf {
+    encoded.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(decode_name)
+        .collect()
+}
+
+#[cfg(unix)]
+fn name_bytes(name: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: &OsStr) -> Vec<u8> {
+    name.to_string_lossy().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn name_from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn name_from_bytes(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).to_string())
+}
@@ -0,0 +1,388 @@
+use crate::{
+    api::error::ApiError,
+    users::{self, Role, User},
+    AppState
+};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response}
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH}
+};
+use uuid::Uuid;
+
+/// The prefix of the secrets of the tokens, which makes them easy to spot
+/// e.g. in a leaked configuration file.
+pub const TOKEN_PREFIX: &str = "fotos_";
+
+/// The cookie holding the secret of the session of a user.
+pub const SESSION_COOKIE: &str = "fotos_session";
+
+/// The header repeating the CSRF token of the session, needed by the
+/// requests of a user which modify anything.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// What a token allows. Every scope includes the previous ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Browsing and downloading the libraries, the tags and the trash.
+    Read,
+
+    /// Adding and removing tags.
+    Annotate,
+
+    /// Modifying the content of the libraries and managing the tokens.
+    Admin
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Annotate => "annotate",
+            Scope::Admin => "admin"
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Scope::Read),
+            "annotate" => Ok(Scope::Annotate),
+            "admin" => Ok(Scope::Admin),
+            _ => anyhow::bail!("Unknown scope {s:?}, expected read, annotate or admin")
+        }
+    }
+}
+
+/// A token of the API. The secret is only known when it is created.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+
+    /// When the token has been created, in seconds since the Unix epoch.
+    pub created_at: i64,
+
+    /// When the token expires, in seconds since the Unix epoch.
+    /// `None` if it doesn't expire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>
+}
+
+type TokenRow = (String, String, String, i64, Option<i64>);
+
+impl Token {
+    fn from_row(row: TokenRow) -> Self {
+        let (id, name, scopes, created_at, expires_at) = row;
+        Self {
+            id,
+            name,
+            scopes: scopes.split(',').filter_map(|scope| scope.parse().ok()).collect(),
+            created_at,
+            expires_at
+        }
+    }
+
+    /// Checks whether the token allows what `scope` allows.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|granted| *granted >= scope)
+    }
+}
+
+/// Creates the token `name` with `scopes`, expiring at `expires_at`
+/// if set. Returns the token and its secret, which isn't stored.
+pub async fn create(
+    pool: &SqlitePool,
+    name: &str,
+    scopes: &[Scope],
+    expires_at: Option<i64>
+) -> anyhow::Result<(Token, String)> {
+    check(name, scopes)?;
+
+    let mut scopes = scopes.to_vec();
+    scopes.sort();
+    scopes.dedup();
+    let token = Token {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        scopes,
+        created_at: now(),
+        expires_at
+    };
+    let secret = generate_secret();
+
+    let joined: Vec<&str> = token.scopes.iter().map(Scope::as_str).collect();
+    sqlx::query(
+            "INSERT INTO tokens (id, name, hash, scopes, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&token.id)
+        .bind(&token.name)
+        .bind(hash(&secret))
+        .bind(joined.join(","))
+        .bind(token.created_at)
+        .bind(token.expires_at)
+        .execute(pool)
+        .await?;
+
+    Ok((token, secret))
+}
+
+/// Checks that a token named `name` with `scopes` can be created.
+pub fn check(name: &str, scopes: &[Scope]) -> anyhow::Result<()> {
+    if name.trim().is_empty() || name.len() > 100 {
+        anyhow::bail!("The name of a token must have between 1 and 100 characters");
+    }
+    if scopes.is_empty() {
+        anyhow::bail!("A token needs at least one scope");
+    }
+
+    Ok(())
+}
+
+/// Lists the tokens, including the expired ones, sorted by name.
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<Token>> {
+    let rows: Vec<TokenRow> = sqlx::query_as(
+            "SELECT id, name, scopes, created_at, expires_at FROM tokens ORDER BY name"
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(Token::from_row).collect())
+}
+
+/// Returns the token with the id or the name `key`, or `None` if it
+/// doesn't exist.
+pub async fn get(pool: &SqlitePool, key: &str) -> anyhow::Result<Option<Token>> {
+    let row: Option<TokenRow> = sqlx::query_as(
+            "SELECT id, name, scopes, created_at, expires_at FROM tokens WHERE id = ?1 OR name = ?1"
+        )
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(Token::from_row))
+}
+
+/// Revokes the token `id`. Returns whether it existed.
+pub async fn revoke(pool: &SqlitePool, id: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query("DELETE FROM tokens WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the token whose secret is `secret`, or `None` if there is none
+/// or if it has expired.
+pub async fn verify(pool: &SqlitePool, secret: &str) -> anyhow::Result<Option<Token>> {
+    let row: Option<TokenRow> = sqlx::query_as(
+            "SELECT id, name, scopes, created_at, expires_at FROM tokens
+            WHERE hash = ? AND (expires_at IS NULL OR expires_at > ?)"
+        )
+        .bind(hash(secret))
+        .bind(now())
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(Token::from_row))
+}
+
+/// Who made an authorized request: a token or a logged in user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Principal {
+    Token(Token),
+    User(User)
+}
+
+impl Principal {
+    /// Checks whether the principal allows what `scope` allows.
+    pub fn allows(&self, scope: Scope) -> bool {
+        match self {
+            Principal::Token(token) => token.allows(scope),
+            Principal::User(user) => user.allows(scope)
+        }
+    }
+
+    /// The role of the user, or the role matching the widest scope of the
+    /// token.
+    pub fn role(&self) -> Role {
+        match self {
+            Principal::Token(token) => token.scopes.iter().max().copied().map(Role::from).unwrap_or(Role::Viewer),
+            Principal::User(user) => user.role
+        }
+    }
+
+    /// The name the records made by the principal are attributed to: the
+    /// name of the user, or `token:<name>` for a token.
+    pub fn author(&self) -> String {
+        match self {
+            Principal::Token(token) => format!("token:{}", token.name),
+            Principal::User(user) => user.name.clone()
+        }
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Principal::Token(token) => write!(f, "token {}", token.name),
+            Principal::User(user) => write!(f, "user {} ({})", user.name, user.role)
+        }
+    }
+}
+
+/// Returns the scope needed for the request `method` on `path`, relative
+/// to the base path, or `None` if the request is public.
+///
+/// Reading needs [`Scope::Read`], changing the tags [`Scope::Annotate`],
+/// anything else, as well as managing the tokens, the users and the share
+/// links, [`Scope::Admin`]. Logging in and opening a share link are public.
+pub fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let first = segments.next().unwrap_or_default();
+    let is_read = method == Method::GET || method == Method::HEAD;
+    let scope = match first {
+        "session" if method == Method::POST && segments.next().is_none() => return None,
+        "session" => Scope::Read,
+        "share" => return None,
+        "tokens" | "users" | "shares" => Scope::Admin,
+        _ if is_read => Scope::Read,
+        "tags" => Scope::Annotate,
+        _ => Scope::Admin
+    };
+
+    Some(scope)
+}
+
+/// Authorizes the requests with the scope needed for the request, see
+/// [`required_scope`]. The requests carry either a bearer token in the
+/// `Authorization` header or the session cookie of a user, whose role
+/// grants the scope. The requests of a user modifying anything also need
+/// the CSRF token of the session in the `X-CSRF-Token` header.
+///
+/// Responds with `401 Unauthorized` if the token or the session is missing,
+/// unknown or expired, and with `403 Forbidden` if it doesn't have the
+/// needed scope or if the CSRF token doesn't match. The [`Principal`], and
+/// the [`users::Session`] of a user, are added to the extensions of the
+/// authorized requests.
+pub async fn authorize<B>(
+    State(state): State<Arc<AppState>>,
+    mut request: Request<B>,
+    next: Next<B>
+) -> Response {
+    let Some(scope) = required_scope(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let bearer = request.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|secret| secret.trim().to_string());
+    let principal = if let Some(secret) = bearer {
+        match verify(&state.pool, &secret).await {
+            Ok(Some(token)) => Principal::Token(token),
+            Ok(None) => return unauthorized("Invalid or expired token"),
+            Err(err) => return ApiError::from(err).into_response()
+        }
+    } else if let Some(secret) = session_secret(request.headers()) {
+        let session = match users::find_session(&state.pool, &secret).await {
+            Ok(Some(session)) => session,
+            Ok(None) => return unauthorized("Invalid or expired session"),
+            Err(err) => return ApiError::from(err).into_response()
+        };
+        let is_safe = [Method::GET, Method::HEAD, Method::OPTIONS].contains(request.method());
+        let csrf_token = request.headers().get(CSRF_HEADER).map(|value| value.as_bytes());
+        if !is_safe && !csrf_token.is_some_and(|token| constant_time_eq(token, session.csrf_token.as_bytes())) {
+            let msg = "Missing or invalid CSRF token".to_string();
+            return ApiError::new(StatusCode::FORBIDDEN).with_msg(msg).into_response();
+        }
+        let user = session.user.clone();
+        request.extensions_mut().insert(session);
+        Principal::User(user)
+    } else {
+        return unauthorized("Missing bearer token or session");
+    };
+
+    if !principal.allows(scope) {
+        let msg = format!("{principal} doesn't have the {scope} scope");
+        return ApiError::new(StatusCode::FORBIDDEN).with_msg(msg).into_response();
+    }
+
+    request.extensions_mut().insert(principal);
+    next.run(request).await
+}
+
+/// Returns the secret in the session cookie of the request, if any.
+pub fn session_secret(headers: &HeaderMap) -> Option<String> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// Returns the value of the cookie `name` of the request, if any.
+pub fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.get_all(header::COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Compares `a` and `b` in a time which doesn't depend on where they differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn unauthorized(msg: &str) -> Response {
+    let mut response = ApiError::new(StatusCode::UNAUTHORIZED)
+        .with_msg(msg.to_string())
+        .into_response();
+    response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// Generates a secret with 244 random bits, from two version 4 uuids.
+pub(crate) fn generate_secret() -> String {
+    let mut bytes = Uuid::new_v4().as_bytes().to_vec();
+    bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+    format!("{TOKEN_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// The secrets are random, so a fast hash is enough to protect them.
+pub(crate) fn hash(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// The current time, in seconds since the Unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,170 @@
+use super::{Scope, CSRF_HEADER, SESSION_COOKIE};
+use crate::{
+    test_utils::{bearer, call, make_pool},
+    users::{self, Role},
+    AppConf,
+    AppState
+};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router
+};
+use rstest::*;
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn token_test() {
+    // tokens are found by their secret until they expire or are revoked
+    let pool = make_pool().await;
+    let (token, secret) = super::create(&pool, "frontend", &[Scope::Read, Scope::Annotate, Scope::Read], None).await.unwrap();
+    let (expired, expired_secret) = super::create(&pool, "backup", &[Scope::Read], Some(super::now() - 1)).await.unwrap();
+
+    assert!(secret.starts_with(super::TOKEN_PREFIX));
+    assert_eq!(token.scopes, vec![Scope::Read, Scope::Annotate]);
+    assert_eq!(super::verify(&pool, &secret).await.unwrap(), Some(token.clone()));
+    assert_eq!(super::verify(&pool, &expired_secret).await.unwrap(), None);
+    assert_eq!(super::verify(&pool, "fotos_guessed").await.unwrap(), None);
+    assert_eq!(super::list(&pool).await.unwrap(), vec![expired, token.clone()]);
+    assert_eq!(super::get(&pool, "frontend").await.unwrap(), Some(token.clone()));
+
+    // the secret itself isn't stored
+    let stored: Vec<(String,)> = sqlx::query_as("SELECT hash FROM tokens").fetch_all(&pool).await.unwrap();
+    assert!(stored.iter().all(|(hash,)| hash != &secret));
+
+    assert!(super::revoke(&pool, &token.id).await.unwrap());
+    assert!(!super::revoke(&pool, &token.id).await.unwrap());
+    assert_eq!(super::verify(&pool, &secret).await.unwrap(), None);
+
+    assert!(super::create(&pool, "", &[Scope::Read], None).await.is_err());
+    assert!(super::create(&pool, "empty", &[], None).await.is_err());
+    assert!(super::create(&pool, "backup", &[Scope::Admin], None).await.is_err());
+}
+
+#[rstest]
+#[case(Method::GET, "/data/photos/penguins.jpg", Some(Scope::Read))]
+#[case(Method::HEAD, "/tags/photos", Some(Scope::Read))]
+#[case(Method::GET, "/trash/photos", Some(Scope::Read))]
+#[case(Method::POST, "/tags/photos/folder", Some(Scope::Annotate))]
+#[case(Method::DELETE, "/tags/photos/folder", Some(Scope::Annotate))]
+#[case(Method::PUT, "/data/photos/upload.jpg", Some(Scope::Admin))]
+#[case(Method::POST, "/ops/photos/move", Some(Scope::Admin))]
+#[case(Method::GET, "/tokens", Some(Scope::Admin))]
+#[case(Method::GET, "/users", Some(Scope::Admin))]
+#[case(Method::POST, "/session", None)]
+#[case(Method::GET, "/session", Some(Scope::Read))]
+#[case(Method::DELETE, "/session", Some(Scope::Read))]
+#[case(Method::POST, "/session/other", Some(Scope::Read))]
+#[case(Method::GET, "/share/id/signature/cat.jpg", None)]
+#[case(Method::GET, "/shares/photos", Some(Scope::Admin))]
+#[case(Method::POST, "/shares/photos", Some(Scope::Admin))]
+fn required_scope_test(#[case] method: Method, #[case] path: &str, #[case] expected: Option<Scope>) {
+    assert_eq!(super::required_scope(&method, path), expected);
+}
+
+async fn make_app(pool: SqlitePool, base_path: &str) -> Router {
+    let conf = AppConf {
+        base_path: base_path.to_string(),
+        ..Default::default()
+    };
+    crate::app(AppState::new(conf, pool))
+}
+
+#[tokio::test]
+async fn authorize_test() {
+    // the requests need a token, with the scope needed by the request
+    let pool = make_pool().await;
+    let (_, reader) = super::create(&pool, "reader", &[Scope::Read], None).await.unwrap();
+    let (_, annotator) = super::create(&pool, "annotator", &[Scope::Annotate], None).await.unwrap();
+    let (_, admin) = super::create(&pool, "admin", &[Scope::Admin], None).await.unwrap();
+    let app = make_app(pool, "").await;
+
+    let response = call(&app, Method::GET, "/data", None, None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+
+    let response = call(&app, Method::GET, "/data", bearer("fotos_guessed"), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = call(&app, Method::GET, "/data", bearer(&reader), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call(&app, Method::DELETE, "/tags/photos", bearer(&reader), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // the request is authorized, but has no body
+    let response = call(&app, Method::DELETE, "/tags/photos", bearer(&annotator), None).await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let response = call(&app, Method::GET, "/tokens", bearer(&annotator), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = call(&app, Method::GET, "/tokens", bearer(&admin), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // unknown routes aren't found, regardless of the token
+    let response = call(&app, Method::GET, "/unknown", None, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn authorize_base_path_test() {
+    // the scope is needed for the route, not for the base path
+    let pool = make_pool().await;
+    let (_, reader) = super::create(&pool, "reader", &[Scope::Read], None).await.unwrap();
+    let app = make_app(pool, "/tokens").await;
+
+    let response = call(&app, Method::GET, "/tokens/data", bearer(&reader), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call(&app, Method::GET, "/tokens/tokens", bearer(&reader), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn authorize_session_test() {
+    // the requests of a user need the session cookie, the role needed by
+    // the request, and the CSRF token if they modify anything
+    let pool = make_pool().await;
+    let viewer = users::create(&pool, "viewer", "viewer password", Role::Viewer).await.unwrap();
+    let annotator = users::create(&pool, "annotator", "annotator password", Role::Annotator).await.unwrap();
+    let (viewer, viewer_secret) = users::open_session(&pool, &viewer, 60).await.unwrap();
+    let (annotator, annotator_secret) = users::open_session(&pool, &annotator, 60).await.unwrap();
+    let (_, expired_secret) = users::open_session(&pool, &annotator.user, -1).await.unwrap();
+    let app = make_app(pool, "").await;
+
+    let send = |method: Method, uri: &str, secret: &str, csrf_token: Option<&str>| {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, format!("theme=dark; {SESSION_COOKIE}={secret}"));
+        if let Some(csrf_token) = csrf_token {
+            request = request.header(CSRF_HEADER, csrf_token);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let response = send(Method::GET, "/data", &viewer_secret, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(Method::GET, "/data", &expired_secret, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = send(Method::GET, "/data", "fotos_guessed", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = send(Method::DELETE, "/tags/photos", &viewer_secret, Some(&viewer.csrf_token)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = send(Method::DELETE, "/tags/photos", &annotator_secret, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(Method::DELETE, "/tags/photos", &annotator_secret, Some(&viewer.csrf_token)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // the request is authorized, but has no body
+    let response = send(Method::DELETE, "/tags/photos", &annotator_secret, Some(&annotator.csrf_token)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let response = send(Method::GET, "/users", &annotator_secret, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,193 @@
+use crate::CacheConf;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+use tokio::fs;
+use uuid::Uuid;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The length of a key: a hex SHA-256 digest.
+const KEY_LEN: usize = 64;
+
+/// What happened while pruning the cache.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PruneStats {
+    /// The images that have been removed.
+    pub removed: usize,
+
+    /// The size of the removed images, in bytes.
+    pub freed: u64,
+
+    /// The images left in the cache.
+    pub kept: usize,
+
+    /// The size of the images left in the cache, in bytes.
+    pub size: u64
+}
+
+/// Returns the key of the rendition `rendition` (e.g. the resizing
+/// parameters) of the file `filepath`.
+///
+/// The key depends on the size and modification time of the file, so that
+/// the renditions of a modified file aren't served anymore.
+pub async fn key(filepath: &Path, rendition: &str) -> anyhow::Result<String> {
+    let metadata = fs::metadata(filepath).await?;
+    let mtime = metadata.modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_nanos();
+    let fullpath = fs::canonicalize(filepath).await?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(fullpath.as_os_str().as_encoded_bytes());
+    hasher.update(format!("\0{}\0{}\0{}", metadata.len(), mtime, rendition));
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns the cached rendition `key`, or `None` if it isn't cached or the
+/// cache is disabled.
+pub async fn get(conf: &CacheConf, key: &str) -> Option<Vec<u8>> {
+    let location = location(conf, key)?;
+    let bytes = fs::read(&location).await.ok()?;
+
+    // The modification time tells which images have been used recently
+    let _ = touch(location).await;
+    Some(bytes)
+}
+
+/// Stores the rendition `key` in the cache, if it is enabled.
+pub async fn put(conf: &CacheConf, key: &str, bytes: &[u8]) -> anyhow::Result<()> {
+    let Some(location) = location(conf, key) else {
+        return Ok(());
+    };
+    if let Some(parent) = location.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    // Readers never see a partially written image
+    let partial = location.with_extension(format!("partial-{}", Uuid::new_v4()));
+    fs::write(&partial, bytes).await?;
+    if let Err(err) = fs::rename(&partial, &location).await {
+        let _ = fs::remove_file(&partial).await;
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+/// Removes the images that haven't been used for more than `max_age`,
+/// then the least recently used images until the cache is no larger than
+/// `max_size` bytes. `None` doesn't limit the age or the size.
+pub async fn prune(conf: &CacheConf, max_size: Option<u64>, max_age: Option<Duration>) -> anyhow::Result<PruneStats> {
+    let mut stats = PruneStats::default();
+    let Some(dir) = &conf.dir else {
+        return Ok(stats);
+    };
+
+    let mut images = match list(Path::new(dir)).await {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(stats),
+        result => result?
+    };
+    images.sort_by_key(|(_, _, mtime)| *mtime);
+
+    let now = SystemTime::now();
+    let mut size: u64 = images.iter().map(|(_, len, _)| len).sum();
+    for (path, len, mtime) in images {
+        let expired = max_age
+            .map(|max_age| now.duration_since(mtime).unwrap_or_default() > max_age)
+            .unwrap_or(false);
+        let too_large = max_size.map(|max_size| size > max_size).unwrap_or(false);
+        if !expired && !too_large {
+            stats.kept += 1;
+            continue;
+        }
+
+        match fs::remove_file(&path).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => ()
+        }
+        size -= len;
+        stats.removed += 1;
+        stats.freed += len;
+    }
+
+    stats.size = size;
+    Ok(stats)
+}
+
+/// Prunes the cache according to its configuration.
+pub async fn prune_configured(conf: &CacheConf) -> anyhow::Result<PruneStats> {
+    let max_size = (conf.max_size_mb > 0).then(|| conf.max_size_mb * 1024 * 1024);
+    let max_age = (conf.max_age_days > 0)
+        .then(|| Duration::from_secs(conf.max_age_days * SECONDS_PER_DAY));
+    prune(conf, max_size, max_age).await
+}
+
+/// Where the rendition `key` is stored: `<dir>/<first two digits>/<key>`.
+fn location(conf: &CacheConf, key: &str) -> Option<PathBuf> {
+    let dir = conf.dir.as_ref()?;
+    Some(Path::new(dir).join(&key[..2]).join(key))
+}
+
+/// Sets the modification time of `path` to now.
+async fn touch(path: PathBuf) -> io::Result<()> {
+    tokio::task::spawn_blocking(move || {
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(SystemTime::now())
+    }).await?
+}
+
+/// Whether `name` is a key, or a partially written image of a key.
+fn is_key(name: &str) -> bool {
+    let (key, suffix) = name.split_at(name.len().min(KEY_LEN));
+    key.len() == KEY_LEN
+        && key.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+        && (suffix.is_empty() || suffix.starts_with(".partial-"))
+}
+
+/// Lists the images of the cache folder `dir` with their size and
+/// modification time. Anything not stored by `put` is left out, so that
+/// pruning a misconfigured folder can't remove foreign files.
+async fn list(dir: &Path) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut images = vec![];
+    let mut folders = fs::read_dir(dir).await?;
+    while let Some(folder) = folders.next_entry().await? {
+        let prefix = folder.file_name();
+        let Some(prefix) = prefix.to_str() else {
+            continue;
+        };
+        let is_prefix = prefix.len() == 2
+            && prefix.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'));
+        if !is_prefix || !folder.file_type().await?.is_dir() {
+            continue;
+        }
+
+        let mut entries = fs::read_dir(folder.path()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let belongs = name.to_str()
+                .map(|name| is_key(name) && name.starts_with(prefix))
+                .unwrap_or(false);
+            if !belongs {
+                continue;
+            }
+
+            let metadata = fs::symlink_metadata(entry.path()).await?;
+            if metadata.is_file() {
+                images.push((entry.path(), metadata.len(), metadata.modified()?));
+            }
+        }
+    }
+
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests;
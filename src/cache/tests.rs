@@ -0,0 +1,121 @@
+use crate::CacheConf;
+
+use std::{env, fs, path::PathBuf, time::{Duration, SystemTime}};
+
+fn make_conf(name: &str) -> (CacheConf, PathBuf) {
+    let base = env::temp_dir().join(format!("fotos-cache-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+    fs::create_dir_all(&base).unwrap();
+    let conf = CacheConf {
+        dir: Some(base.join("cache").to_string_lossy().to_string()),
+        ..Default::default()
+    };
+    (conf, base)
+}
+
+/// A key starting with `prefix`.
+fn make_key(prefix: &str) -> String {
+    format!("{prefix:0<64}")
+}
+
+#[tokio::test]
+async fn get_put_test() {
+    // renditions are cached per file and parameters, and not served
+    // anymore once the file changes
+    let (conf, base) = make_conf("get");
+    let filepath = base.join("penguins.jpg");
+    fs::write(&filepath, b"penguins").unwrap();
+
+    let small = super::key(&filepath, "w=100").await.unwrap();
+    let large = super::key(&filepath, "w=200").await.unwrap();
+    assert_ne!(small, large);
+    assert_eq!(super::get(&conf, &small).await, None);
+
+    super::put(&conf, &small, b"small").await.unwrap();
+    assert_eq!(super::get(&conf, &small).await, Some(b"small".to_vec()));
+    assert_eq!(super::get(&conf, &large).await, None);
+
+    fs::write(&filepath, b"more penguins").unwrap();
+    assert_ne!(super::key(&filepath, "w=100").await.unwrap(), small);
+
+    // nothing is stored when the cache is disabled
+    let disabled = CacheConf { dir: None, ..Default::default() };
+    super::put(&disabled, &large, b"large").await.unwrap();
+    assert_eq!(super::get(&disabled, &small).await, None);
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[tokio::test]
+async fn prune_test() {
+    // expired images are removed, then the least recently used ones
+    // until the cache fits
+    let (conf, base) = make_conf("prune");
+    let now = SystemTime::now();
+    for (prefix, days) in [("aa01", 40), ("bb02", 3), ("cc03", 2), ("dd04", 1)] {
+        let key = make_key(prefix);
+        super::put(&conf, &key, &[0; 100]).await.unwrap();
+        let location = super::location(&conf, &key).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(location)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(days * 24 * 60 * 60))
+            .unwrap();
+    }
+
+    let stats = super::prune(&conf, Some(250), Some(Duration::from_secs(30 * 24 * 60 * 60))).await.unwrap();
+    assert_eq!(stats, super::PruneStats {
+        removed: 2,
+        freed: 200,
+        kept: 2,
+        size: 200
+    });
+    assert_eq!(super::get(&conf, &make_key("bb02")).await, None);
+    assert!(super::get(&conf, &make_key("cc03")).await.is_some());
+
+    // a missing cache folder is empty
+    fs::remove_dir_all(base.join("cache")).unwrap();
+    assert_eq!(super::prune_configured(&conf).await.unwrap(), super::PruneStats::default());
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[tokio::test]
+async fn prune_foreign_test() {
+    // only the images and the partially written ones are removed, even
+    // when the cache folder is shared with other files
+    let (conf, base) = make_conf("foreign");
+    let cache = base.join("cache");
+    let key = make_key("ee05");
+    super::put(&conf, &key, &[0; 100]).await.unwrap();
+    let partial = cache.join("ee").join(format!("{key}.partial-1234"));
+    fs::write(&partial, [0; 10]).unwrap();
+
+    let foreign = [
+        cache.join("ee").join("notes.txt"),
+        cache.join("ee").join(make_key("ff06")),
+        cache.join("ee").join(format!("{key}.jpg")),
+        cache.join("photos").join(make_key("0a07")),
+        cache.join("AB").join(make_key("ab08"))
+    ];
+    for path in &foreign {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, [0; 1000]).unwrap();
+    }
+
+    let stats = super::prune(&conf, Some(0), None).await.unwrap();
+    assert_eq!(stats, super::PruneStats {
+        removed: 2,
+        freed: 110,
+        kept: 0,
+        size: 0
+    });
+    assert!(!partial.exists());
+    assert_eq!(super::get(&conf, &key).await, None);
+    for path in &foreign {
+        assert!(path.exists(), "{path:?} was removed");
+    }
+
+    fs::remove_dir_all(&base).unwrap();
+}
@@ -0,0 +1,194 @@
+use crate::{AppConf, DEFAULT_LIBRARY};
+
+use std::{fs, path::Path};
+use toml::Value;
+
+/// The prefix of the environment variables overriding the configuration.
+pub const ENV_PREFIX: &str = "FOTOS_";
+
+/// The environment variable specifying the configuration file,
+/// it doesn't override any field.
+pub const CONFIG_ENV: &str = "FOTOS_CONFIG";
+
+/// The fields of the library which were top-level fields of the
+/// configuration before it could serve several libraries.
+const LEGACY_LIBRARY_KEYS: &[&str] = &["root", "read_only", "exclude", "trash", "trash_retention_days"];
+
+/// Loads the configuration from the file `path` and applies the overrides.
+///
+/// The precedence is, from the lowest to the highest:
+///
+/// 1. The default values of the fields.
+/// 2. The configuration file.
+/// 3. The `FOTOS_*` environment variables in `env`, where `__` separates the
+///    keys of nested fields: `FOTOS_DATABASE__URL` overrides `database.url`,
+///    `FOTOS_LIBRARIES__PHOTOS__ROOT` overrides `libraries.photos.root`.
+///    Names are case-insensitive and converted to lowercase. Variables which
+///    don't match a field are ignored, they might be meant for another
+///    program.
+/// 4. The `key=value` pairs in `overrides`, usually passed on the command
+///    line (e.g. `database.max_connections=10`).
+///
+/// Values are parsed as TOML (e.g. `true`, `10` or `[".*", "@eaDir"]`),
+/// except for fields holding a string, which take the value as it is.
+///
+/// The library fields of a configuration file written before the libraries
+/// (e.g. a top-level `root`) configure the default library, unless the file
+/// has a `libraries` table as well.
+pub fn load<I>(path: &Path, env: I, overrides: &[String]) -> anyhow::Result<AppConf>
+where
+    I: IntoIterator<Item = (String, String)>
+{
+    let conf: AppConf = confy::load_path(path)?;
+    let conf = upgrade_legacy(conf, path)?;
+    let (conf, _) = merge(conf, &env_overrides(env))?;
+
+    let mut pairs = vec![];
+    for pair in overrides {
+        let (key, raw) = pair.split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid override {pair:?}, expected key=value"))?;
+        pairs.push((key.trim().to_string(), raw.to_string()));
+    }
+
+    apply_all(conf, &pairs)
+}
+
+/// Upgrades `conf`, loaded from the configuration file `path`, if the file
+/// was written by an older version:
+///
+/// - The top-level library fields are moved to the default library. They are
+///   refused if the file configures the libraries already, rather than
+///   silently ignored.
+/// - A file without an `auth` table predates the authentication, which isn't
+///   required then, so that upgrading doesn't lock out the existing clients.
+fn upgrade_legacy(mut conf: AppConf, path: &Path) -> anyhow::Result<AppConf> {
+    // confy has already created the file if it was missing
+    let file: toml::value::Table = toml::from_str(&fs::read_to_string(path)?)?;
+    if !file.contains_key("auth") {
+        conf.auth.required = false;
+    }
+
+    let legacy: Vec<&str> = LEGACY_LIBRARY_KEYS.iter()
+        .copied()
+        .filter(|key| file.contains_key(*key))
+        .collect();
+    if legacy.is_empty() {
+        return Ok(conf);
+    }
+    if file.contains_key("libraries") {
+        anyhow::bail!(
+            "The fields {} of {} must be moved to a library, e.g. [libraries.{DEFAULT_LIBRARY}]",
+            legacy.join(", "),
+            path.display()
+        );
+    }
+
+    let mut value = Value::try_from(&conf)?;
+    let library = value.get_mut("libraries")
+        .and_then(|libraries| libraries.get_mut(DEFAULT_LIBRARY))
+        .and_then(Value::as_table_mut)
+        .ok_or_else(|| anyhow::anyhow!("The default library {DEFAULT_LIBRARY:?} is missing"))?;
+    for key in legacy {
+        library.insert(key.to_string(), file[key].clone());
+    }
+
+    Ok(value.try_into()?)
+}
+
+/// Applies the `key=value` pairs to `conf`, in order.
+/// Keys which don't match a field are refused.
+pub fn apply_all(conf: AppConf, pairs: &[(String, String)]) -> anyhow::Result<AppConf> {
+    let (conf, unknown) = merge(conf, pairs)?;
+    if let Some(key) = unknown.first() {
+        anyhow::bail!("Unknown configuration key {key:?}");
+    }
+
+    Ok(conf)
+}
+
+/// Applies the `key=value` pairs to `conf`, in order. Returns the merged
+/// configuration and the keys which don't match a field, which are ignored.
+fn merge(conf: AppConf, pairs: &[(String, String)]) -> anyhow::Result<(AppConf, Vec<String>)> {
+    let mut value = Value::try_from(&conf)?;
+    for (key, raw) in pairs {
+        apply(&mut value, key, raw)?;
+    }
+
+    let conf: AppConf = value.try_into()?;
+
+    // Unknown fields are ignored by serde, so check that every overridden
+    // field still exists in the deserialized configuration
+    let merged = Value::try_from(&conf)?;
+    let unknown = pairs.iter()
+        .filter(|(key, _)| lookup(&merged, key).is_none())
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    Ok((conf, unknown))
+}
+
+/// Collects the overrides from the `FOTOS_*` environment variables.
+fn env_overrides<I>(env: I) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (String, String)>
+{
+    let mut pairs: Vec<(String, String)> = env.into_iter()
+        .filter(|(name, _)| name != CONFIG_ENV)
+        .filter_map(|(name, raw)| {
+            let key = name.strip_prefix(ENV_PREFIX)?
+                .to_lowercase()
+                .split("__")
+                .collect::<Vec<_>>()
+                .join(".");
+            Some((key, raw))
+        })
+        .filter(|(key, _)| key.split('.').all(|segment| !segment.is_empty()))
+        .collect();
+
+    // The order of the environment is unspecified, make it deterministic
+    pairs.sort();
+    pairs
+}
+
+/// Sets the field `key` of `value`, a dotted path, to `raw`.
+/// Missing tables are created on the way, e.g. for a new library.
+fn apply(value: &mut Value, key: &str, raw: &str) -> anyhow::Result<()> {
+    let segments: Vec<&str> = key.split('.').collect();
+    if segments.iter().any(|segment| segment.is_empty()) {
+        anyhow::bail!("Invalid configuration key {key:?}");
+    }
+
+    let (last, parents) = segments.split_last().unwrap_or((&"", &[]));
+    let mut current = value;
+    for segment in parents {
+        let table = current.as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("Invalid configuration key {key:?}"))?;
+        current = table.entry(segment.to_string())
+            .or_insert_with(|| Value::Table(Default::default()));
+    }
+
+    let table = current.as_table_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid configuration key {key:?}"))?;
+    let parsed = match table.get(*last) {
+        Some(Value::String(_)) => Value::String(raw.to_string()),
+        _ => parse_value(raw)
+    };
+    table.insert(last.to_string(), parsed);
+
+    Ok(())
+}
+
+/// Parses `raw` as a TOML value, falling back to a string.
+fn parse_value(raw: &str) -> Value {
+    toml::from_str::<toml::value::Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn lookup<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(value, |current, segment| current.get(segment))
+}
+
+#[cfg(test)]
+mod tests;
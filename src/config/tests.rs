@@ -0,0 +1,231 @@
+use crate::{AppConf, DEFAULT_LIBRARY};
+
+use std::{env, fs, path::PathBuf};
+
+fn make_file(name: &str, content: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("fotos-config-{name}-{}.toml", std::process::id()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn precedence_test() {
+    // the file overrides the defaults, the environment overrides the file,
+    // the command line overrides the environment
+    let path = make_file("precedence", "
+        connection = '127.0.0.1:8080'
+        max_level = 'WARN'
+
+        [database]
+        url = 'sqlite://file.db'
+    ");
+    let env = vars(&[
+        ("FOTOS_MAX_LEVEL", "DEBUG"),
+        ("FOTOS_DATABASE__URL", "sqlite://env.db"),
+        ("FOTOS_DATABASE__MAX_CONNECTIONS", "10"),
+        ("FOTOS_CONFIG", "ignored.toml"),
+        ("HOME", "/root"),
+    ]);
+    let overrides = vec!["database.url=sqlite://cli.db".to_string()];
+
+    let conf = super::load(&path, env, &overrides).unwrap();
+    assert_eq!(conf.connection, vec!["127.0.0.1:8080"]);
+    assert_eq!(conf.max_level, "DEBUG");
+    assert_eq!(conf.database.url, "sqlite://cli.db");
+    assert_eq!(conf.database.max_connections, 10);
+    assert_eq!(conf.database.busy_timeout_ms, 5000);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn library_overrides_test() {
+    // libraries can be added and changed, values are parsed as toml
+    // unless the field is a string
+    let path = make_file("libraries", "");
+    let env = vars(&[("FOTOS_LIBRARIES__SCRATCH__ROOT", "/tmp/scratch")]);
+    let overrides = vec![
+        "libraries.scratch.read_only=true".to_string(),
+        "libraries.photos.trash=30".to_string(),
+        "libraries.photos.exclude=['@eaDir']".to_string(),
+    ];
+
+    let conf = super::load(&path, env, &overrides).unwrap();
+    let scratch = conf.library("scratch").unwrap();
+    assert_eq!(scratch.conf.root, "/tmp/scratch");
+    assert!(scratch.conf.read_only);
+    let photos = conf.library(DEFAULT_LIBRARY).unwrap();
+    assert_eq!(photos.conf.trash, "30");
+    assert_eq!(photos.conf.exclude, vec!["@eaDir"]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn connection_overrides_test() {
+    // the addresses can be given as a list or as a single string
+    let path = make_file("connection", "connection = ['127.0.0.1:8080', 'unix:/run/fotos.sock']");
+    let conf = super::load(&path, vec![], &[]).unwrap();
+    assert_eq!(conf.connection, vec!["127.0.0.1:8080", "unix:/run/fotos.sock"]);
+
+    let env = vars(&[("FOTOS_CONNECTION", "[::]:3000")]);
+    let conf = super::load(&path, env, &[]).unwrap();
+    assert_eq!(conf.connection, vec!["[::]:3000"]);
+
+    let overrides = vec!["connection=['0.0.0.0:3000', '[::]:3000']".to_string()];
+    let conf = super::load(&path, vec![], &overrides).unwrap();
+    assert_eq!(conf.connection, vec!["0.0.0.0:3000", "[::]:3000"]);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn invalid_overrides_test() {
+    let path = make_file("invalid", "");
+    for overrides in [
+        vec!["database.url".to_string()],
+        vec!["databse.url=sqlite://x.db".to_string()],
+        vec!["database.max_connections=many".to_string()],
+        vec!["connection.port=80".to_string()],
+    ] {
+        assert!(super::load(&path, vec![], &overrides).is_err(), "{overrides:?}");
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn unknown_env_test() {
+    // the variables which don't match a field are ignored, unlike the
+    // unknown keys of the command line
+    let path = make_file("unknown-env", "");
+    let env = vars(&[
+        ("FOTOS_VERSION", "1.2.3"),
+        ("FOTOS_DEPLOY__COLOR", "blue"),
+        ("FOTOS_", "empty"),
+        ("FOTOS_MAX_LEVEL", "WARN"),
+    ]);
+
+    let conf = super::load(&path, env, &[]).unwrap();
+    assert_eq!(conf.max_level, "WARN");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn serialize_test() {
+    // the configuration file written for a new user can be read back,
+    // TOML needs the plain fields before the tables
+    let conf = AppConf {
+        static_dir: Some("/srv/fotos".to_string()),
+        ..Default::default()
+    };
+    let path = make_file("serialize", &toml::to_string(&conf).unwrap());
+
+    let loaded = super::load(&path, vec![], &[]).unwrap();
+    assert_eq!(loaded.static_dir.as_deref(), Some("/srv/fotos"));
+    assert_eq!(loaded.immutable_assets, conf.immutable_assets);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn renditions_test() {
+    // the presets of the file replace the default ones, and must resize
+    // the images
+    let path = make_file("renditions", "
+        [renditions]
+        restricted = true
+
+        [renditions.presets.square]
+        max_width = 640
+        max_height = 640
+    ");
+
+    let conf = super::load(&path, vars(&[]), &[]).unwrap();
+    assert!(conf.renditions.restricted);
+    assert_eq!(conf.renditions.presets["square"].max_width, Some(640));
+    assert!(!conf.renditions.presets["square"].thumbnail);
+    assert!(!conf.renditions.presets.contains_key("thumb"));
+    assert!(conf.validate().is_ok());
+
+    let overrides = vec!["renditions.presets.square.max_width=0".to_string()];
+    let conf = super::load(&path, vars(&[]), &overrides).unwrap();
+    assert!(conf.validate().is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn legacy_file_test() {
+    // the library fields of a file written before the libraries configure
+    // the default library
+    let path = make_file("legacy", "
+        root = '/srv/photos'
+        read_only = true
+        connection = '127.0.0.1:8080'
+        max_level = 'WARN'
+        exclude = ['.*', '@eaDir/']
+        trash = '/srv/trash'
+        trash_retention_days = 7
+    ");
+
+    let conf = super::load(&path, vec![], &[]).unwrap();
+    assert_eq!(conf.connection, vec!["127.0.0.1:8080"]);
+    assert!(!conf.auth.required);
+    assert_eq!(conf.libraries.len(), 1);
+    let library = &conf.libraries[DEFAULT_LIBRARY];
+    assert_eq!(library.root, "/srv/photos");
+    assert!(library.read_only);
+    assert_eq!(library.exclude, vec![".*", "@eaDir/"]);
+    assert_eq!(library.trash, "/srv/trash");
+    assert_eq!(library.trash_retention_days, 7);
+
+    // they are refused next to the libraries, instead of being ignored
+    fs::write(&path, "
+        root = '/srv/photos'
+
+        [libraries.photos]
+        root = '/srv/other'
+    ").unwrap();
+    let err = super::load(&path, vec![], &[]).unwrap_err();
+    assert!(err.to_string().contains("root"), "{err}");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn legacy_auth_test() {
+    // a file written before the authentication doesn't require it
+    let path = make_file("legacy-auth", "
+        connection = '127.0.0.1:8080'
+    ");
+    let conf = super::load(&path, vec![], &[]).unwrap();
+    assert!(!conf.auth.required);
+
+    // it can still be required by an override
+    let conf = super::load(&path, vars(&[("FOTOS_AUTH__REQUIRED", "true")]), &[]).unwrap();
+    assert!(conf.auth.required);
+
+    // a file with an auth table keeps the default
+    fs::write(&path, "
+        [auth]
+        session_ttl_hours = 24
+    ").unwrap();
+    let conf = super::load(&path, vec![], &[]).unwrap();
+    assert!(conf.auth.required);
+    assert_eq!(conf.auth.session_ttl_hours, 24);
+
+    // so does a new file, written with the default values
+    fs::remove_file(&path).unwrap();
+    let conf = super::load(&path, vec![], &[]).unwrap();
+    assert!(conf.auth.required);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,64 @@
+use crate::CorsConf;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::{str::FromStr, time::Duration};
+use tower_http::cors::{Any, CorsLayer};
+
+/// The value allowing any origin, method or header.
+pub const WILDCARD: &str = "*";
+
+/// Builds the CORS layer configured in `conf`, or `None` if no origin is
+/// allowed, in which case the browsers block all the cross-origin requests.
+pub fn layer(conf: &CorsConf) -> anyhow::Result<Option<CorsLayer>> {
+    if conf.allowed_origins.is_empty() {
+        return Ok(None);
+    }
+
+    // The browsers reject wildcards in responses to requests with credentials
+    let lists = [&conf.allowed_origins, &conf.allowed_methods, &conf.allowed_headers, &conf.exposed_headers];
+    if conf.allow_credentials && lists.iter().any(|list| is_wildcard(list)) {
+        anyhow::bail!("CORS wildcards can't be used when credentials are allowed");
+    }
+
+    let mut layer = CorsLayer::new()
+        .allow_credentials(conf.allow_credentials)
+        .max_age(Duration::from_secs(conf.max_age_secs));
+
+    layer = if is_wildcard(&conf.allowed_origins) {
+        layer.allow_origin(Any)
+    } else {
+        layer.allow_origin(parse_all::<HeaderValue>(&conf.allowed_origins, "origin")?)
+    };
+    layer = if is_wildcard(&conf.allowed_methods) {
+        layer.allow_methods(Any)
+    } else {
+        layer.allow_methods(parse_all::<Method>(&conf.allowed_methods, "method")?)
+    };
+    layer = if is_wildcard(&conf.allowed_headers) {
+        layer.allow_headers(Any)
+    } else {
+        layer.allow_headers(parse_all::<HeaderName>(&conf.allowed_headers, "header")?)
+    };
+    layer = if is_wildcard(&conf.exposed_headers) {
+        layer.expose_headers(Any)
+    } else {
+        layer.expose_headers(parse_all::<HeaderName>(&conf.exposed_headers, "header")?)
+    };
+
+    Ok(Some(layer))
+}
+
+fn is_wildcard(values: &[String]) -> bool {
+    values.iter().any(|value| value == WILDCARD)
+}
+
+fn parse_all<T: FromStr>(values: &[String], kind: &str) -> anyhow::Result<Vec<T>> {
+    values.iter()
+        .map(|value| value.parse()
+            .map_err(|_| anyhow::anyhow!("Invalid CORS {kind} {value:?}"))
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,115 @@
+use crate::{test_utils::make_pool, AppConf, AppState, AuthConf, CorsConf};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    response::Response,
+    Router
+};
+use rstest::*;
+use tower::ServiceExt;
+
+static FRONTEND: &str = "http://localhost:5173";
+
+async fn make_app(cors: CorsConf) -> Router {
+    let pool = make_pool().await;
+
+    let conf = AppConf {
+        auth: AuthConf { required: false, ..Default::default() },
+        cors,
+        ..Default::default()
+    };
+    crate::app(AppState::new(conf, pool))
+}
+
+fn frontend_conf() -> CorsConf {
+    CorsConf {
+        allowed_origins: vec![FRONTEND.to_string()],
+        ..Default::default()
+    }
+}
+
+async fn preflight(app: Router, origin: &str, method: &str) -> Response {
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/data/photos/upload.jpg")
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type,authorization,x-csrf-token")
+        .body(Body::empty())
+        .unwrap();
+
+    app.oneshot(request).await.unwrap()
+}
+
+fn header(response: &Response, name: header::HeaderName) -> Option<&str> {
+    response.headers().get(name).map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn preflight_test() {
+    // the preflight requests of the allowed origins are answered, the
+    // credentials and the CSRF token are allowed by default
+    let response = preflight(make_app(frontend_conf()).await, FRONTEND, "PUT").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some(FRONTEND));
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_METHODS), Some("GET,PUT,POST,DELETE"));
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS), Some("content-type,authorization,x-csrf-token"));
+    assert_eq!(header(&response, header::ACCESS_CONTROL_MAX_AGE), Some("600"));
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_CREDENTIALS), None);
+}
+
+#[rstest]
+#[case(CorsConf::default(), FRONTEND)]
+#[case(frontend_conf(), "http://evil.example")]
+#[tokio::test]
+async fn preflight_rejected_test(#[case] conf: CorsConf, #[case] origin: &str) {
+    // other origins, or all of them if none is configured, aren't allowed
+    let response = preflight(make_app(conf).await, origin, "DELETE").await;
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN), None);
+}
+
+#[tokio::test]
+async fn simple_request_test() {
+    // the responses to the allowed origins expose the configured headers
+    let conf = CorsConf {
+        allowed_origins: vec!["*".to_string()],
+        ..Default::default()
+    };
+    let request = Request::get("/data")
+        .header(header::ORIGIN, FRONTEND)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = make_app(conf).await.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+    assert_eq!(header(&response, header::ACCESS_CONTROL_EXPOSE_HEADERS), Some("link,x-truncated"));
+}
+
+#[tokio::test]
+async fn credentials_test() {
+    // credentials are allowed for the configured origins
+    let conf = CorsConf {
+        allow_credentials: true,
+        allowed_headers: vec!["content-type".to_string(), "authorization".to_string()],
+        ..frontend_conf()
+    };
+
+    let response = preflight(make_app(conf).await, FRONTEND, "POST").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some(FRONTEND));
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_CREDENTIALS), Some("true"));
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS), Some("content-type,authorization"));
+}
+
+#[rstest]
+#[case(CorsConf { allowed_origins: vec!["*".to_string()], allow_credentials: true, ..Default::default() })]
+#[case(CorsConf { allowed_headers: vec!["*".to_string()], allow_credentials: true, ..frontend_conf() })]
+#[case(CorsConf { allowed_methods: vec!["GE T".to_string()], ..frontend_conf() })]
+#[case(CorsConf { allowed_headers: vec!["content type".to_string()], ..frontend_conf() })]
+#[case(CorsConf { allowed_origins: vec!["http://localhost\n".to_string()], ..Default::default() })]
+fn invalid_conf_test(#[case] conf: CorsConf) {
+    assert!(super::layer(&conf).is_err());
+}
@@ -0,0 +1,144 @@
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match
+};
+use std::{
+    path::Path,
+    sync::Arc
+};
+
+/// The name of the files containing the per-folder exclude patterns.
+/// They follow the same syntax and semantics as `.gitignore` files.
+pub const IGNORE_FILENAME: &str = ".fotosignore";
+
+/// Returns a pattern matching exactly the path `relative`,
+/// relative to the root folder, with the special characters escaped.
+pub fn anchored_pattern(relative: &Path) -> String {
+    let mut pattern = String::new();
+    for component in relative.iter() {
+        pattern.push('/');
+        for c in component.to_string_lossy().chars() {
+            if matches!(c, '\\' | '*' | '?' | '[' | ']' | '!' | '#') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+    }
+
+    pattern
+}
+
+/// The patterns excluding files and folders from being served.
+///
+/// It combines the global exclude list from the configuration with the
+/// `.fotosignore` files of a folder and all its parents.
+/// Like with git, patterns in deeper folders take precedence and can
+/// re-include files with a `!` pattern.
+#[derive(Clone)]
+pub struct Exclusions {
+    /// The matchers, from the lowest to the highest precedence.
+    matchers: Vec<Arc<Gitignore>>,
+
+    /// Whether a path is hidden, which no pattern can re-include.
+    hidden: Arc<dyn Fn(&Path) -> bool + Send + Sync>
+}
+
+impl Exclusions {
+    /// Creates the exclusions for the root folder `root` using the global
+    /// exclude list `patterns`.
+    /// The `.fotosignore` file of `root` is read as well.
+    pub fn new(root: &Path, patterns: &[String]) -> anyhow::Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+
+        let global = Self {
+            matchers: vec![Arc::new(builder.build()?)],
+            hidden: Arc::new(|_| false)
+        };
+        Ok(global.descend(root))
+    }
+
+    /// Returns the exclusions applying to the entries of `folder`,
+    /// which must be a child folder of the one of `self`.
+    pub fn descend(&self, folder: &Path) -> Self {
+        let mut matchers = self.matchers.clone();
+
+        let ignore_file = folder.join(IGNORE_FILENAME);
+        if ignore_file.is_file() {
+            let (gitignore, err) = Gitignore::new(&ignore_file);
+            if let Some(err) = err {
+                tracing::warn!("Invalid patterns in {}: {}", ignore_file.display(), err);
+            }
+            matchers.push(Arc::new(gitignore));
+        }
+
+        Self { matchers, hidden: self.hidden.clone() }
+    }
+
+    /// Returns the exclusions also excluding the paths for which `hidden`
+    /// returns `true`.
+    pub fn hide(mut self, hidden: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.hidden = Arc::new(hidden);
+        self
+    }
+
+    /// Returns the exclusions applying to the entries of `folder`,
+    /// which must be beneath `root`. `self` must be the exclusions of `root`.
+    pub fn for_folder(&self, root: &Path, folder: &Path) -> Self {
+        let mut exclusions = self.clone();
+        if let Ok(relative) = folder.strip_prefix(root) {
+            let mut current = root.to_path_buf();
+            for component in relative.components() {
+                current.push(component);
+                exclusions = exclusions.descend(&current);
+            }
+        }
+
+        exclusions
+    }
+
+    /// Checks whether the entry `path` of the folder is excluded.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if (self.hidden)(path) {
+            return true;
+        }
+        for matcher in self.matchers.iter().rev() {
+            match matcher.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => ()
+            }
+        }
+
+        false
+    }
+
+    /// Checks whether `fullpath`, or any folder between `root` and `fullpath`,
+    /// is excluded. `self` must be the exclusions of `root`.
+    pub fn is_path_excluded(&self, root: &Path, fullpath: &Path) -> bool {
+        let relative = match fullpath.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return false
+        };
+
+        let mut exclusions = self.clone();
+        let mut current = root.to_path_buf();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            current.push(component);
+            let is_last = components.peek().is_none();
+            let is_dir = !is_last || current.is_dir();
+
+            if exclusions.is_excluded(&current, is_dir) {
+                return true;
+            }
+            if !is_last {
+                exclusions = exclusions.descend(&current);
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,86 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::{collections::HashMap, io::Write};
+
+type FileRow = (String, String, String, String, Option<String>, Option<i64>, Option<i64>);
+
+/// An indexed file with its annotations, as exported by [`export`].
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ExportedFile {
+    pub library: String,
+
+    /// The percent-encoded path relative to the root folder of the library,
+    /// as stored in the index.
+    pub path: String,
+
+    pub csum: String,
+    pub mimetype: Option<String>,
+    pub size: Option<i64>,
+
+    /// The modification time, in seconds since the Unix epoch.
+    pub mtime: Option<i64>,
+
+    pub tags: Vec<String>
+}
+
+/// Returns the indexed files of the library `library`, or of all the
+/// libraries if `None`, with their tags. Files in the trash are skipped,
+/// as well as the files for which `visible` returns false, given the name
+/// of their library and their relative path as stored in the index.
+pub async fn exported_files<F>(pool: &SqlitePool, library: Option<&str>, visible: F) -> anyhow::Result<Vec<ExportedFile>>
+where
+    F: Fn(&str, &str) -> bool
+{
+    let rows: Vec<FileRow> = sqlx::query_as(
+            "SELECT id, library, relative_path, csum, mimetype, size, mtime FROM files
+            WHERE trash_id IS NULL AND (?1 IS NULL OR library = ?1)
+            ORDER BY library, relative_path"
+        )
+        .bind(library)
+        .fetch_all(pool)
+        .await?;
+
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    let tag_rows: Vec<(String, String)> = sqlx::query_as("SELECT file_id, tag FROM tags ORDER BY tag")
+        .fetch_all(pool)
+        .await?;
+    for (file_id, tag) in tag_rows {
+        tags.entry(file_id).or_default().push(tag);
+    }
+
+    let files = rows.into_iter()
+        .filter(|(_, library, path, ..)| visible(library, path))
+        .map(|(id, library, path, csum, mimetype, size, mtime)| ExportedFile {
+            tags: tags.remove(&id).unwrap_or_default(),
+            library,
+            path,
+            csum,
+            mimetype,
+            size,
+            mtime
+        })
+        .collect();
+    Ok(files)
+}
+
+/// Writes the indexed files of the library `library`, or of all the
+/// libraries if `None`, to `writer` as newline delimited json, except the
+/// ones for which `visible` returns false, see [`exported_files`].
+/// Returns the number of exported files.
+pub async fn export<F, W>(pool: &SqlitePool, library: Option<&str>, visible: F, mut writer: W) -> anyhow::Result<usize>
+where
+    F: Fn(&str, &str) -> bool,
+    W: Write
+{
+    let files = exported_files(pool, library, visible).await?;
+    for file in &files {
+        serde_json::to_writer(&mut writer, file)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    Ok(files.len())
+}
+
+#[cfg(test)]
+mod tests;
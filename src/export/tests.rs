@@ -0,0 +1,54 @@
+use crate::test_utils::make_pool;
+
+use sqlx::SqlitePool;
+
+/// Opens a database with three indexed files, two of them tagged.
+async fn make_indexed_pool() -> SqlitePool {
+    let pool = make_pool().await;
+
+    sqlx::query(
+            "INSERT INTO files (id, library, relative_path, csum, mimetype, size, mtime) VALUES
+            ('a', 'photos', 'penguins.jpg', 'abc', 'image/jpeg', 3, 10),
+            ('b', 'photos', 'apollon.jpg', 'def', 'image/jpeg', 4, 20),
+            ('c', 'scratch', 'notes.txt', 'ghi', 'text/plain', 5, 30);
+            INSERT INTO tags (file_id, tag) VALUES ('a', 'zoo'), ('a', 'birds')"
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool
+}
+
+#[tokio::test]
+async fn export_test() {
+    // the files are exported with their tags as newline delimited json
+    let pool = make_indexed_pool().await;
+
+    let mut output = vec![];
+    let count = super::export(&pool, Some("photos"), |_, _| true, &mut output).await.unwrap();
+    assert_eq!(count, 2);
+
+    let lines: Vec<serde_json::Value> = String::from_utf8(output).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines[0]["path"], "apollon.jpg");
+    assert_eq!(lines[0]["tags"], serde_json::json!([]));
+    assert_eq!(lines[1]["path"], "penguins.jpg");
+    assert_eq!(lines[1]["tags"], serde_json::json!(["birds", "zoo"]));
+
+    let all = super::exported_files(&pool, None, |_, _| true).await.unwrap();
+    assert_eq!(all.len(), 3);
+    assert_eq!(all[2].library, "scratch");
+}
+
+#[tokio::test]
+async fn export_visible_test() {
+    // the files which aren't visible aren't exported, nor their tags
+    let pool = make_indexed_pool().await;
+
+    let visible = |library: &str, path: &str| library == "photos" && path != "penguins.jpg";
+    let files = super::exported_files(&pool, None, visible).await.unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].path, "apollon.jpg");
+}
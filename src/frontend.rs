@@ -0,0 +1,127 @@
+use crate::routes::API_PREFIXES;
+
+use axum::{
+    body::{boxed, Body},
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    response::{IntoResponse, Response}
+};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::{Path, PathBuf};
+use tower::ServiceExt;
+use tower_http::services::{fs::ServeFileSystemResponseBody, ServeDir, ServeFile};
+
+/// The page served for the paths which aren't files, letting the frontend
+/// route them.
+pub const INDEX: &str = "index.html";
+
+/// The `Cache-Control` header of the assets whose name changes with their
+/// content: they can be cached for good.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// The `Cache-Control` header of the other files, e.g. `index.html`: they
+/// are revalidated at every use, so that a new build is picked up at once.
+const REVALIDATE: &str = "no-cache";
+
+/// Serves the built web frontend, a single-page app.
+#[derive(Clone, Debug)]
+pub struct Frontend {
+    dir: PathBuf,
+    base_path: String,
+    immutable: GlobSet
+}
+
+impl Frontend {
+    /// Serves the files in `dir` under `base_path`. The files matching the
+    /// glob patterns `immutable`, relative to `dir` (e.g. `assets/**`), are
+    /// cached by the clients for good.
+    pub fn new(dir: &Path, base_path: &str, immutable: &[String]) -> anyhow::Result<Self> {
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            base_path: base_path.to_string(),
+            immutable: glob_set(immutable)?
+        })
+    }
+
+    /// Serves the file addressed by `request`, or `index.html` if there is
+    /// none. The precompressed `.br` or `.gz` variant of the file is served
+    /// if it exists and the client accepts it.
+    ///
+    /// Missing immutable assets and paths under the routes of the API are
+    /// not found instead, so that clients don't get the page in place of
+    /// a script or of data.
+    pub async fn serve(self, mut request: Request<Body>) -> Response {
+        let path = match request.uri().path().strip_prefix(&self.base_path) {
+            Some(path) if path.is_empty() || path.starts_with('/') => path.trim_start_matches('/').to_string(),
+            _ => return StatusCode::NOT_FOUND.into_response()
+        };
+        match format!("/{path}").parse() {
+            Ok(uri) => *request.uri_mut() = uri,
+            Err(_) => return StatusCode::NOT_FOUND.into_response()
+        }
+
+        let first = path.split('/').next().unwrap_or_default();
+        if API_PREFIXES.contains(&first) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+
+        let is_immutable = self.immutable.is_match(&path);
+        let method = request.method().clone();
+        let headers = request.headers().clone();
+        let mut response = serve_with(self.files(), request).await;
+
+        let is_page = method == Method::GET || method == Method::HEAD;
+        if response.status() == StatusCode::NOT_FOUND && is_page && !is_immutable {
+            let mut index = Request::new(Body::empty());
+            *index.method_mut() = method;
+            *index.headers_mut() = headers;
+            response = serve_with(self.index(), index).await;
+        }
+
+        if response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED {
+            let cache_control = if is_immutable { IMMUTABLE } else { REVALIDATE };
+            response.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+        }
+
+        response
+    }
+
+    fn files(&self) -> ServeDir {
+        ServeDir::new(&self.dir)
+            .precompressed_br()
+            .precompressed_gzip()
+    }
+
+    fn index(&self) -> ServeFile {
+        ServeFile::new(self.dir.join(INDEX))
+            .precompressed_br()
+            .precompressed_gzip()
+    }
+}
+
+async fn serve_with<S>(service: S, request: Request<Body>) -> Response
+where
+    S: tower::Service<Request<Body>, Response = Response<ServeFileSystemResponseBody>, Error = std::io::Error>
+{
+    match service.oneshot(request).await {
+        Ok(response) => response.map(boxed),
+        Err(err) => {
+            tracing::error!("Cannot serve the frontend: {:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Compiles the glob patterns of the immutable assets.
+pub fn glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|err| anyhow::anyhow!("Invalid pattern of immutable assets {pattern:?}: {err}"))?;
+        builder.add(glob);
+    }
+
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests;
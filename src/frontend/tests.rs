@@ -0,0 +1,118 @@
+use crate::{test_utils::{make_pool, read_text}, AppConf, AppState, AuthConf};
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    response::Response,
+    Router
+};
+use rstest::*;
+use std::{env, fs, path::{Path, PathBuf}};
+use tower::ServiceExt;
+
+static INDEX: &str = "<html>fotos</html>";
+static SCRIPT: &str = "console.log('fotos')";
+
+/// Makes a build of the frontend in a temporary folder.
+fn make_build(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("fotos-frontend-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("assets")).unwrap();
+    fs::write(dir.join("index.html"), INDEX).unwrap();
+    fs::write(dir.join("favicon.ico"), "icon").unwrap();
+    fs::write(dir.join("assets/index-4f3a2b1c.js"), SCRIPT).unwrap();
+    fs::write(dir.join("assets/index-4f3a2b1c.js.gz"), "gzipped").unwrap();
+    fs::write(dir.join("assets/index-4f3a2b1c.js.br"), "brotli").unwrap();
+    dir
+}
+
+async fn make_app(dir: &Path, base_path: &str) -> Router {
+    let pool = make_pool().await;
+
+    let conf = AppConf {
+        auth: AuthConf { required: false, ..Default::default() },
+        base_path: base_path.to_string(),
+        static_dir: Some(dir.to_string_lossy().to_string()),
+        ..Default::default()
+    };
+    crate::app(AppState::new(conf, pool))
+}
+
+async fn get(app: Router, uri: &str, encoding: Option<&str>) -> Response {
+    let mut request = Request::get(uri);
+    if let Some(encoding) = encoding {
+        request = request.header(header::ACCEPT_ENCODING, encoding);
+    }
+
+    app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+}
+
+fn cache_control(response: &Response) -> Option<&str> {
+    response.headers().get(header::CACHE_CONTROL).map(|value| value.to_str().unwrap())
+}
+
+#[rstest]
+#[case("", "/")]
+#[case("", "/index.html")]
+#[case("", "/albums/2023/summer")]
+#[case("/fotos", "/fotos/")]
+#[case("/fotos", "/fotos")]
+#[case("/fotos", "/fotos/albums")]
+#[case("/fotos", "/fotos/index.html")]
+#[tokio::test]
+async fn index_test(#[case] base_path: &str, #[case] uri: &str) {
+    // the paths which aren't files are routed by the frontend
+    let dir = make_build(&format!("index{}", uri.len()));
+    let mut response = get(make_app(&dir, base_path).await, uri, None).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(cache_control(&response), Some("no-cache"));
+    assert_eq!(read_text(&mut response).await, INDEX);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[rstest]
+#[case(None, None, SCRIPT)]
+#[case(Some("gzip"), Some("gzip"), "gzipped")]
+#[case(Some("br"), Some("br"), "brotli")]
+#[case(Some("deflate"), None, SCRIPT)]
+#[tokio::test]
+async fn precompressed_test(#[case] accepted: Option<&str>, #[case] encoding: Option<&str>, #[case] expected: &str) {
+    // the precompressed variants of the files are served to the clients
+    // accepting them, the hashed assets are cached for good
+    let dir = make_build(&format!("precompressed{}", expected.len()));
+    let mut response = get(make_app(&dir, "").await, "/assets/index-4f3a2b1c.js", accepted).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(cache_control(&response), Some("public, max-age=31536000, immutable"));
+    assert_eq!(response.headers().get(header::CONTENT_ENCODING).map(|value| value.to_str().unwrap()), encoding);
+    assert_eq!(read_text(&mut response).await, expected);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn api_priority_test() {
+    // the routes of the API are never served by the frontend, nor are the
+    // missing assets
+    let dir = make_build("api");
+    let app = make_app(&dir, "").await;
+
+    let mut response = get(app.clone(), "/data", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_text(&mut response).await, r#"[{"name":"photos","read_only":false}]"#);
+
+    for uri in ["/data/missing/penguins.jpg", "/tags", "/trash", "/assets/index-00000000.js"] {
+        let mut response = get(app.clone(), uri, None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        assert_ne!(read_text(&mut response).await, INDEX, "{uri}");
+    }
+
+    let mut response = get(app, "/favicon.ico", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(cache_control(&response), Some("no-cache"));
+    assert_eq!(read_text(&mut response).await, "icon");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
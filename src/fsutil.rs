@@ -0,0 +1,66 @@
+use futures_util::future::BoxFuture;
+use std::{
+    io,
+    path::{Path, PathBuf}
+};
+use tokio::fs;
+
+/// Copies the file or folder `from` to `to`.
+/// Symbolic links are copied as links, the content of their target is never
+/// read: it might be outside of the root folder.
+pub fn copy_recursive(from: PathBuf, to: PathBuf) -> BoxFuture<'static, io::Result<()>> {
+    Box::pin(async move {
+        let file_type = fs::symlink_metadata(&from).await?.file_type();
+        if file_type.is_symlink() {
+            return copy_symlink(&from, &to).await;
+        }
+        if !file_type.is_dir() {
+            fs::copy(&from, &to).await?;
+            return Ok(());
+        }
+
+        fs::create_dir(&to).await?;
+        let mut entries = fs::read_dir(&from).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            copy_recursive(entry.path(), to.join(entry.file_name())).await?;
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(unix)]
+async fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::symlink(fs::read_link(from).await?, to).await
+}
+
+#[cfg(not(unix))]
+async fn copy_symlink(from: &Path, _to: &Path) -> io::Result<()> {
+    let msg = format!("Cannot copy the symbolic link {}", from.display());
+    Err(io::Error::new(io::ErrorKind::Unsupported, msg))
+}
+
+/// Removes the file or folder `fullpath`.
+pub async fn remove(fullpath: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(fullpath).await?.is_dir() {
+        fs::remove_dir_all(fullpath).await
+    } else {
+        fs::remove_file(fullpath).await
+    }
+}
+
+/// Moves the file or folder `from` to `to`.
+/// Unlike a plain rename it also works across file systems, e.g. for a
+/// trash folder on another disk, by copying and then removing `from`.
+pub async fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to).await {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            if let Err(err) = copy_recursive(from.to_path_buf(), to.to_path_buf()).await {
+                let _ = remove(to).await;
+                return Err(err);
+            }
+            remove(from).await
+        },
+        result => result
+    }
+}
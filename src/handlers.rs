@@ -1,3 +1,80 @@
+use crate::{
+    acl::Permission,
+    api::error::{ApiError, ApiResult},
+    Library
+};
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response}
+};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
 pub mod data;
+pub mod ops;
+pub mod session;
+pub mod shares;
+pub mod tags;
+pub mod tokens;
+pub mod trash;
+pub mod upload;
+pub mod users;
+
+pub use data::{download, list_libraries};
+pub use ops::{copy_entry, delete_entry, move_entry, rename_entry};
+pub use session::{get_session, login, logout};
+pub use shares::{create_share, list_shares, open_share, revoke_share};
+pub use tags::{add_tags, get_tags, remove_tags};
+pub use tokens::{create_token, list_tokens, revoke_token};
+pub use trash::{list_trash, purge_trashed, restore_trashed};
+pub use upload::{upload, upload_multipart};
+pub use users::{create_user, list_users, remove_user, update_user};
+
+/// Refuses requests which would modify the content of the library,
+/// if it is configured as read-only.
+fn ensure_writable(library: &Library) -> ApiResult<()> {
+    if library.conf.read_only {
+        let msg = format!("library {} is read-only", library.name);
+        return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+    }
+
+    Ok(())
+}
+
+/// Refuses requests needing `permission` on `relative`, a path relative to
+/// the root folder, if the access rules of the library don't grant it.
+fn ensure_allowed(library: &Library, relative: &Path, permission: Permission) -> ApiResult<()> {
+    if !library.access.allows(relative, permission) {
+        let msg = format!("{permission} access to {} is denied", relative.to_string_lossy());
+        return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+    }
+
+    Ok(())
+}
+
+/// Resolves the symbolic links of the existing folder `folder`, refusing
+/// folders which lie outside of the root folder of `library` once resolved.
+/// Returns the resolved folder, relative to the resolved root folder.
+async fn resolve_folder(library: &Library, folder: &Path) -> ApiResult<PathBuf> {
+    let canonical = fs::canonicalize(folder).await.map_err(|_| {
+        let msg = format!("folder {} doesn't exist", folder.to_string_lossy());
+        ApiError::new(StatusCode::NOT_FOUND).with_msg(msg)
+    })?;
+    let root = fs::canonicalize(&library.conf.root).await?;
+    canonical.strip_prefix(&root)
+        .map(Path::to_path_buf)
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST)
+            .with_msg("Path outside of the root folder".to_string())
+        )
+}
 
-pub use data::download;
+/// The response to a password check refused after too many wrong
+/// passwords, telling when to try again.
+fn too_many_failures(retry_after: i64) -> Response {
+    let mut response = ApiError::new(StatusCode::TOO_MANY_REQUESTS)
+        .with_msg("too many wrong passwords, try again later".to_string())
+        .into_response();
+    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
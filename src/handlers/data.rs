@@ -1,19 +1,33 @@
 use crate::{
-    api::error::{ApiError, ApiResult},
-    AppState
+    acl::Access,
+    api::{
+        error::{ApiError, ApiResult},
+        prefix::Prefix,
+        subpath::{self, SubPath}
+    },
+    auth::Principal,
+    cache,
+    exclude::Exclusions,
+    mimetype,
+    renditions::{self, Rendition},
+    AppState,
+    CacheConf,
+    Library,
+    RenditionsConf
 };
 
 use axum::{
     body::StreamBody,
-    extract::{self, Query, State},
-    http::{StatusCode, header},
+    extract::{Extension, Query, State},
+    http::{StatusCode, header, HeaderValue},
     Json,
     response::{IntoResponse, Response}
 };
 use mime::Mime;
-use mime_guess;
 use serde::{Deserialize, Serialize};
 use tokio_stream::StreamExt;
+use std::ffi::{OsStr, OsString};
+use std::fs::FileType;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs;
@@ -22,112 +36,381 @@ use tokio_util::io::ReaderStream;
 
 #[derive(Eq, PartialEq, PartialOrd, Debug, Ord, Serialize)]
 struct FolderEntry {
+    /// The name of the entry, for display purposes.
+    /// Invalid UTF-8 sequences are replaced by `U+FFFD`.
     filename: String,
 
+    /// The lossless, percent-encoded name of the entry. This is the form
+    /// to be used in the urls of the data endpoint.
+    path: String,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     mimetype: Option<String>,
 
-    is_dir: bool
+    is_dir: bool,
+
+    /// Whether the entry is a symbolic link, which the recursive listings
+    /// don't follow.
+    #[serde(skip)]
+    is_symlink: bool
 }
 
 impl FolderEntry {
-    async fn new(parent: &Path, filename: &str) -> ApiResult<Self> {
+    /// Makes the entry of `filename` in the folder `parent`, of type
+    /// `file_type`. Symbolic links are listed as their target, or as a file
+    /// if the target doesn't exist. The mimetype is guessed from the
+    /// extension, reading every file of a large folder would be too slow.
+    async fn new(parent: &Path, filename: &OsStr, file_type: FileType) -> Self {
         let filepath = parent.join(filename);
-        if is_dir(&filepath).await? {
-            Ok(Self {
-                filename: filename.to_string(),
-                mimetype: None,
-                is_dir: true
-            })
+        let is_symlink = file_type.is_symlink();
+        let is_dir = if is_symlink {
+            fs::metadata(&filepath).await.map(|metadata| metadata.is_dir()).unwrap_or(false)
+        } else {
+            file_type.is_dir()
+        };
+        let mimetype = if is_dir {
+            None
         } else {
-            Ok(Self {
-                filename: filename.to_string(),
-                mimetype: Some(get_mimetype(&filepath).to_string()),
-                is_dir: false
-            })
+            Some(mimetype::guess(&filepath).to_string())
+        };
+
+        Self {
+            filename: filename.to_string_lossy().to_string(),
+            path: subpath::encode_name(filename),
+            mimetype,
+            is_dir,
+            is_symlink
         }
     }
+
+    /// The name of the entry on the local file system.
+    fn os_name(&self) -> OsString {
+        subpath::decode_name(&self.path)
+    }
 }
 
 /// Handles the route for the path specified by `subpath` by returning the
 /// content of the ressource.
 /// 
-/// For example if the root folder is `/opt/content`
+/// For example if the root folder of the library `photos` is `/opt/content`
 /// and the client does an http request like
 /// 
-/// #    GET /my/little/pony
+/// #    GET /data/photos/my/little/pony
 ///
 /// The endpoint will return the content of the file
 /// `/opt/content/my/little/pony`.
 /// If it is a folder, it will return a json response containing the list
-/// of the folder's entry names.
+/// of the folder's entry names. The list is paginated, the link to the next
+/// page is returned in the `Link` header, relative to the [`Prefix`] of the
+/// request. The folder can also be listed
+/// recursively, either as a tree of subfolders or as a flat list of files.
 /// If it is a file, it will return the content of the file as a binary stream.
 /// 
 /// # Arguments
 /// 
 /// - `State(state)` - The shared state of the application.
+/// - `library` - The library specified in the http route.
 /// - `subpath` - The path to the resource as specified in the http route.
-/// - `params` - Specify resizing options for images.
+///   Filenames which aren't valid UTF-8 must be passed in the percent-encoded
+///   form returned in the `path` field of the folder entries.
+/// - `prefix` - The prefix of the generated links.
+/// - `params` - Specify resizing options for images, or sorting, filtering
+///   and pagination options for folders.
 pub async fn download(
     State(state): State<Arc<AppState>>,
-    subpath: Option<extract::Path<String>>,
+    library: Library,
+    subpath: SubPath,
+    prefix: Prefix,
     params: Query<Params>
 ) -> ApiResult<Response> {
-    let subpath = subpath.as_ref().map(|p| p.as_str());
-    let fullpath = make_fullpath(&state.conf.root, subpath)?;
+    let mut path = format!("/data/{}", library.name);
+    if !subpath.is_root() {
+        path = format!("{path}/{}", subpath::encode_path(subpath.as_path()));
+    }
+
+    serve(&state, &library, subpath.as_path(), &prefix.url(&path), &params, &[]).await
+}
+
+/// Serves `relative`, a path relative to the root folder of `library`, like
+/// [`download`]. `url` is the url of the request, as seen by the client,
+/// without query. The images are only served resized to one of `sizes`,
+/// unless it is empty.
+pub(crate) async fn serve(
+    state: &AppState,
+    library: &Library,
+    relative: &Path,
+    url: &str,
+    params: &Params,
+    sizes: &[u32]
+) -> ApiResult<Response> {
+    let root = Path::new(&library.conf.root);
+    let fullpath = make_fullpath(&library.conf.root, relative)?;
+    let exclusions = library.exclusions()?;
+    if exclusions.is_path_excluded(root, &fullpath) {
+        return Err(not_found(&fullpath));
+    }
+    ensure_visible_target(library, &fullpath).await?;
     let is_dir = is_dir(&fullpath).await?;
 
     let result: ApiResult<Response> = if is_dir {
-        get_folder_entries(&fullpath).await
-            .map(|children| Json(children).into_response())
+        let exclusions = exclusions.for_folder(root, &fullpath);
+        list_folder(state, library, &fullpath, &exclusions, relative, url, params).await
     }
     else {
-        get_file_stream(&fullpath, &params).await
+        let rendition = params.rendition(&state.conf.renditions, sizes)?;
+        get_file_stream(&state.conf.cache, &fullpath, &rendition, sizes).await
             .map(|stream| stream.into_response())
     };
 
     result
 }
 
+/// A library, as listed by [`list_libraries`].
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LibraryEntry {
+    pub name: String,
+    pub read_only: bool
+}
+
+/// Lists the configured libraries, sorted by name, except the ones hidden
+/// altogether by their access rules.
+pub async fn list_libraries(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>
+) -> Json<Vec<LibraryEntry>> {
+    let principal = principal.map(|Extension(principal)| principal);
+    let libraries = state.conf.all_libraries()
+        .into_iter()
+        .filter(|library| {
+            let access = Access::new(&library.conf.access, &state.conf.auth.groups, principal.as_ref());
+            !access.is_hidden(Path::new(""))
+        })
+        .map(|library| LibraryEntry {
+            name: library.name,
+            read_only: library.conf.read_only
+        })
+        .collect();
+
+    Json(libraries)
+}
+
 /// Query parameters for the data endpoint.
-/// 
+///
+/// For files:
+///
 /// - `max_width` - If provided will rescale the image such to have width
-///     smaller than `max_width`
+///   smaller than `max_width`
 /// - `max_height` - If provided will rescale the image such to have height
-///     smaller than `max_height`
+///   smaller than `max_height`
 /// - `thumbnails` - If provided and set to true a fast integer algorithm
-///     will be used for resizing.
-///     This May give aliasing artifacts if new size is close to old size.
-#[derive(Default, Deserialize)]
+///   will be used for resizing.
+///   This May give aliasing artifacts if new size is close to old size.
+/// - `preset` - The name of a configured preset, setting the three
+///   previous parameters.
+/// - `signature` - The signature of the three first parameters, needed
+///   if the renditions are restricted to the presets and the signed sizes.
+///
+/// For folders:
+///
+/// - `cursor` - The cursor returned in the `Link` header of the previous page.
+/// - `limit` - The maximum number of entries in the page.
+/// - `sort` - The key used for sorting: `name` (natural ordering, default),
+///   `mtime`, `date` (capture date) or `size`.
+/// - `order` - Either `asc` (default) or `desc`.
+/// - `dirs_first` - If set to true folders will be listed before files.
+/// - `mimetype` - Only list files whose mimetype starts with the given prefix
+///   (e.g. `image/`).
+/// - `name` - Only list entries whose name matches the given glob pattern
+///   (e.g. `*.jpg`).
+/// - `tag` - Only list files with the given tag, and the folders
+///   containing them.
+/// - `depth` - Return the tree of the subfolders, nested up to `depth` levels,
+///   instead of the entries of the folder. The number of folders in the tree
+///   is limited, the `X-Truncated: true` header is set when some have been
+///   left out.
+/// - `recursive` - If set to `flat`, stream all the files beneath the folder
+///   with their relative paths as newline delimited json.
+///   `depth` can be used to limit how deep the folder is walked, by default
+///   up to the maximum depth.
+#[derive(Default, Deserialize, Serialize)]
 pub struct Params {
+    #[serde(skip_serializing_if = "Option::is_none")]
     max_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     max_height: Option<u32>,
-    thumbnail: Option<bool>
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<listing::SortKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<listing::Order>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dirs_first: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mimetype: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    depth: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recursive: Option<tree::Recursive>
+}
+
+impl Params {
+    /// Makes the value of the `Link` header pointing to the page of `url`
+    /// starting after `cursor`, keeping all the other query parameters.
+    fn next_page_link(&self, url: &str, cursor: String) -> ApiResult<HeaderValue> {
+        let query = serde_urlencoded::to_string(Params {
+            cursor: Some(cursor),
+            sort: self.sort,
+            order: self.order,
+            mimetype: self.mimetype.clone(),
+            name: self.name.clone(),
+            tag: self.tag.clone(),
+            preset: self.preset.clone(),
+            signature: self.signature.clone(),
+            ..*self
+        })?;
+
+        let link = HeaderValue::from_str(&format!("<{url}?{query}>; rel=\"next\""))?;
+        Ok(link)
+    }
+
+    /// Returns the rendition of the images requested, either with a preset
+    /// of `conf` or with the sizes, which must be signed if the renditions
+    /// are restricted, unless they are among `sizes` (e.g. the sizes of a
+    /// share).
+    fn rendition(&self, conf: &RenditionsConf, sizes: &[u32]) -> ApiResult<Rendition> {
+        let requested = Rendition {
+            max_width: self.max_width,
+            max_height: self.max_height,
+            thumbnail: self.thumbnail.unwrap_or(false)
+        };
+
+        if let Some(name) = &self.preset {
+            if requested.is_resized() {
+                let msg = "a preset can't be combined with max_width or max_height".to_string();
+                return Err(ApiError::new(StatusCode::BAD_REQUEST).with_msg(msg));
+            }
+            return conf.presets.get(name)
+                .map(Rendition::from)
+                .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST)
+                    .with_msg(format!("preset {name} doesn't exist"))
+                );
+        }
+
+        let authorized = !sizes.is_empty() && has_allowed_size(&requested, sizes);
+        if conf.restricted && requested.is_resized() && !authorized {
+            let signed = match (&conf.signing_key, &self.signature) {
+                (Some(key), Some(signature)) => renditions::verify(key, &requested, signature),
+                _ => false
+            };
+            if !signed {
+                let msg = "images can only be resized to a preset or to a signed size".to_string();
+                return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+            }
+        }
+
+        Ok(requested)
+    }
+}
+
+/// Lists the entries of the folder `fullpath` according to the query
+/// parameters in `params`. The link to the next page points to `url`.
+async fn list_folder(
+    state: &AppState,
+    library: &Library,
+    fullpath: &PathBuf,
+    exclusions: &Exclusions,
+    relative: &Path,
+    url: &str,
+    params: &Params
+) -> ApiResult<Response> {
+    let depth = params.depth.map(|depth| depth.min(tree::MAX_DEPTH));
+
+    if params.recursive == Some(tree::Recursive::Flat) {
+        let depth = depth.unwrap_or(tree::MAX_DEPTH);
+        let stream = tree::walk_flat(fullpath.clone(), exclusions.clone(), depth);
+        let headers = [(header::CONTENT_TYPE, "application/x-ndjson")];
+        return Ok((headers, StreamBody::new(stream)).into_response());
+    }
+
+    if let Some(depth) = depth {
+        let tree = tree::folder_tree(fullpath.clone(), exclusions.clone(), depth, tree::MAX_NODES).await?;
+        let mut response = Json(tree.nodes).into_response();
+        if tree.truncated {
+            response.headers_mut().insert(tree::TRUNCATED_HEADER, HeaderValue::from_static("true"));
+        }
+        return Ok(response);
+    }
+
+    let entries = get_folder_entries(fullpath, exclusions).await?;
+    let page = listing::paginate(
+        &state.pool,
+        library,
+        fullpath,
+        &subpath::encode_path(relative),
+        entries,
+        params
+    ).await?;
+
+    let mut response = Json(page.entries).into_response();
+    if let Some(cursor) = page.next {
+        let link = params.next_page_link(url, cursor)?;
+        response.headers_mut().insert(header::LINK, link);
+    }
+    Ok(response)
 }
 
 /// Makes a fullpath valid on the local file system from the path of
 /// the http route.
-fn make_fullpath(root: &str, subpath: Option<&str>) -> ApiResult<PathBuf>
+fn make_fullpath(root: &str, subpath: &Path) -> ApiResult<PathBuf>
 {
-    let mut fullpath = Path::new(root).to_path_buf();
-    if let Some(subpath) = subpath {
-        fullpath = fullpath.join(subpath);
-    }
+    let fullpath = Path::new(root).join(subpath);
 
     if fullpath.exists() {
         Ok(fullpath)
     }
     else {
-        let msg = format!("path {} doesn't exist", fullpath.to_str().unwrap_or(""));
-        Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(msg))
+        Err(not_found(&fullpath))
+    }
+}
+
+/// Checks that the access rules don't hide the target of the symbolic links
+/// along `fullpath`, if it lies beneath the root folder: `public/family`
+/// linking to `../family` is hidden as much as `family`.
+async fn ensure_visible_target(library: &Library, fullpath: &Path) -> ApiResult<()> {
+    let root = fs::canonicalize(&library.conf.root).await?;
+    let target = fs::canonicalize(fullpath).await?;
+    if let Ok(relative) = target.strip_prefix(&root) {
+        if library.access.is_hidden(relative) {
+            return Err(not_found(fullpath));
+        }
     }
+
+    Ok(())
+}
+
+fn not_found(fullpath: &Path) -> ApiError {
+    let msg = format!("path {} doesn't exist", fullpath.to_string_lossy());
+    ApiError::new(StatusCode::NOT_FOUND).with_msg(msg)
 }
 
 /// Gets the mimetype of a file on the local file system.
-fn get_mimetype (filepath : &PathBuf) -> Mime {
-    mime_guess::from_path(filepath)
-        .first()
-        .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+fn get_mimetype (filepath : &Path) -> Mime {
+    mimetype::detect(filepath)
 }
 
 /// Checks whether `path` is a directory.
@@ -138,22 +421,20 @@ async fn is_dir(path: &PathBuf) -> ApiResult<bool>
 }
 
 /// Returns the filename of all the entry in the folder specified by
-/// `fullpath`
-async fn get_folder_entries(fullpath: &PathBuf) -> ApiResult<Vec<FolderEntry>> {
+/// `fullpath`, except the ones matched by `exclusions`.
+async fn get_folder_entries(fullpath: &PathBuf, exclusions: &Exclusions) -> ApiResult<Vec<FolderEntry>> {
     let entries = fs::read_dir(fullpath).await?;
     let mut entries = ReadDirStream::new(entries);
 
     let mut result = vec![];
     while let Some(entry) = entries.next().await {
         if let Ok(entry) = entry {
-            let filename = entry.file_name().to_str()
-                .ok_or_else(||
-                    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR)
-                    .with_msg("Encoding issue".to_string())
-                )?
-                .to_string();
-
-            result.push(FolderEntry::new(fullpath, &filename).await?);
+            let filename = entry.file_name();
+            let file_type = entry.file_type().await?;
+            let entry = FolderEntry::new(fullpath, &filename, file_type).await;
+            if !exclusions.is_excluded(&fullpath.join(&filename), entry.is_dir) {
+                result.push(entry);
+            }
         }
     }
 
@@ -161,19 +442,27 @@ async fn get_folder_entries(fullpath: &PathBuf) -> ApiResult<Vec<FolderEntry>> {
 }
 
 /// Returns the content of the file specified by `fullpath` as a binary
-/// stream.
-async fn get_file_stream(fullpath: &PathBuf, params: &Params) -> ApiResult<impl IntoResponse> {
+/// stream. Resized images are taken from the cache `cache` if possible.
+/// Images are refused unless resized to one of `sizes`, if not empty.
+async fn get_file_stream(
+    cache: &CacheConf,
+    fullpath: &PathBuf,
+    rendition: &Rendition,
+    sizes: &[u32]
+) -> ApiResult<impl IntoResponse> {
     // Based on https://github.com/tokio-rs/axum/discussions/608
 
+    let limited = !sizes.is_empty() && imgs::is_image(fullpath);
+    if limited && (!rendition.is_resized() || !has_allowed_size(rendition, sizes)) {
+        let sizes: Vec<String> = sizes.iter().map(u32::to_string).collect();
+        let msg = format!("images can only be downloaded resized to {}", sizes.join(", "));
+        return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+    }
+
     let resize = imgs::is_image(fullpath)
-        && imgs::needs_resize(fullpath, params.max_width, params.max_height)?;
+        && imgs::needs_resize(fullpath, rendition.max_width, rendition.max_height)?;
     let body: Response = if resize {
-        let bytes = imgs::resize(
-            fullpath,
-            params.max_width,
-            params.max_height,
-            params.thumbnail.unwrap_or(false)
-        ).await?;
+        let bytes = resized(cache, fullpath, rendition).await?;
         bytes.into_response()
     } else {
         let file = tokio::fs::File::open(fullpath).await?;
@@ -182,8 +471,8 @@ async fn get_file_stream(fullpath: &PathBuf, params: &Params) -> ApiResult<impl
     };
 
     let filename = fullpath.file_name()
-        .and_then(|s| s.to_str())
-        .unwrap_or("");
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
     let mimetype = get_mimetype(fullpath);
 
     let headers = [
@@ -200,7 +489,31 @@ async fn get_file_stream(fullpath: &PathBuf, params: &Params) -> ApiResult<impl
     Ok((headers, body))
 }
 
+/// Checks whether the width and the height `rendition` is limited to are
+/// among `sizes`.
+fn has_allowed_size(rendition: &Rendition, sizes: &[u32]) -> bool {
+    let allowed = |size: Option<u32>| size.is_none_or(|size| sizes.contains(&size));
+    allowed(rendition.max_width) && allowed(rendition.max_height)
+}
+
+/// Returns the image `fullpath` resized according to `rendition`, from the
+/// cache `cache` if it has already been resized.
+async fn resized(cache: &CacheConf, fullpath: &PathBuf, rendition: &Rendition) -> anyhow::Result<Vec<u8>> {
+    let key = cache::key(fullpath, &rendition.key()).await?;
+    if let Some(bytes) = cache::get(cache, &key).await {
+        return Ok(bytes);
+    }
+
+    let bytes = imgs::resize(fullpath, rendition.max_width, rendition.max_height, rendition.thumbnail).await?;
+    if let Err(err) = cache::put(cache, &key, &bytes).await {
+        tracing::warn!("Cannot cache {}: {:?}", fullpath.display(), err);
+    }
+    Ok(bytes)
+}
+
 pub mod imgs;
+mod listing;
+mod tree;
 
 #[cfg(test)]
 mod tests;
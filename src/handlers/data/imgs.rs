@@ -1,4 +1,6 @@
-use anyhow;
+use crate::mimetype;
+
+use anyhow::anyhow;
 use std::{path::PathBuf, io::Cursor};
 use image::{
     io::Reader as ImageReader,
@@ -36,16 +38,16 @@ pub fn needs_resize(filepath: &PathBuf, max_width: Option<u32>, max_height: Opti
     Ok(result)
 }
 
-/// Load the image at `filepath`.
+/// Load the image at `filepath`, which has the format `format`.
 #[cfg(feature = "turbojpeg")]
-async fn load(filepath: &PathBuf) -> anyhow::Result<DynamicImage> {
-    let img = if ImageFormat::from_path(filepath)? == ImageFormat::Jpeg {
+async fn load(filepath: &PathBuf, format: ImageFormat) -> anyhow::Result<DynamicImage> {
+    let img = if format == ImageFormat::Jpeg {
         // Use turbojpeg for better performance
         let jpeg_data = tokio::fs::read(filepath).await?;
         DynamicImage::ImageRgb8(turbojpeg::decompress_image(&jpeg_data)?)
     } else {
-        let reader = ImageReader::open(filepath)?
-            .with_guessed_format()?;
+        let mut reader = ImageReader::open(filepath)?;
+        reader.set_format(format);
 
         reader.decode()?
     };
@@ -53,19 +55,19 @@ async fn load(filepath: &PathBuf) -> anyhow::Result<DynamicImage> {
     Ok(img)
 }
 
-/// Load the image at `filepath`.
+/// Load the image at `filepath`, which has the format `format`.
 #[cfg(not(feature = "turbojpeg"))]
-async fn load(filepath: &PathBuf) -> anyhow::Result<DynamicImage> {
-    let reader = ImageReader::open(filepath)?
-        .with_guessed_format()?;
+async fn load(filepath: &PathBuf, format: ImageFormat) -> anyhow::Result<DynamicImage> {
+    let mut reader = ImageReader::open(filepath)?;
+    reader.set_format(format);
     let img = reader.decode()?;
     Ok(img)
 }
 
-/// Encode `image` using the format of the original `filepath`.
+/// Encode `image` using the format `format` of the original file.
 #[cfg(feature = "turbojpeg")]
-fn encode(filepath: &PathBuf, image: DynamicImage) -> anyhow::Result<Vec<u8>> {
-    let bytes = if ImageFormat::from_path(filepath)? == ImageFormat::Jpeg {
+fn encode(format: ImageFormat, image: DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let bytes = if format == ImageFormat::Jpeg {
         // Use turbojpeg for better performance
         let rgb = image.into_rgba8();
 
@@ -74,24 +76,18 @@ fn encode(filepath: &PathBuf, image: DynamicImage) -> anyhow::Result<Vec<u8>> {
         jpeg_data.to_vec()
     } else {
         let mut bytes: Vec<u8> = Vec::new();
-        image.write_to(
-            &mut Cursor::new(&mut bytes),
-            ImageFormat::from_path(filepath)?
-        )?;
+        image.write_to(&mut Cursor::new(&mut bytes), format)?;
         bytes
     };
 
     Ok(bytes)
 }
 
-/// Encode `image` using the format of the original `filepath`.
+/// Encode `image` using the format `format` of the original file.
 #[cfg(not(feature = "turbojpeg"))]
-fn encode(filepath: &PathBuf, image: DynamicImage) -> anyhow::Result<Vec<u8>> {
+fn encode(format: ImageFormat, image: DynamicImage) -> anyhow::Result<Vec<u8>> {
     let mut bytes: Vec<u8> = Vec::new();
-    image.write_to(
-        &mut Cursor::new(&mut bytes),
-        ImageFormat::from_path(filepath)?
-    )?;
+    image.write_to(&mut Cursor::new(&mut bytes), format)?;
 
     Ok(bytes)
 }
@@ -100,8 +96,12 @@ fn encode(filepath: &PathBuf, image: DynamicImage) -> anyhow::Result<Vec<u8>> {
 /// This function keeps the ratio of the image. Therefore it is not guaranteed
 /// that the new image will have the dimension `next_width`.
 /// If `thumbnail` is true a fast integer algorithm will be used for resizing.
+/// The format of the image is detected from its content, the resized image
+/// is encoded with the same format.
 pub async fn resize(filepath: &PathBuf, next_width: Option<u32>, next_height: Option<u32>, thumbnail: bool) -> anyhow::Result<Vec<u8>> {
-    let img = load(filepath).await?;
+    let format = mimetype::image_format(filepath)
+        .ok_or_else(|| anyhow!("Unsupported image format"))?;
+    let img = load(filepath, format).await?;
     let (width, height) = img.dimensions();
 
     let img = if thumbnail {
@@ -116,6 +116,6 @@ pub async fn resize(filepath: &PathBuf, next_width: Option<u32>, next_height: Op
         )
     };
 
-    let bytes = encode(filepath, img)?;
+    let bytes = encode(format, img)?;
     Ok(bytes)
 }
@@ -0,0 +1,324 @@
+use crate::{
+    acl::Permission,
+    api::{error::{ApiError, ApiResult}, subpath},
+    index,
+    Library
+};
+use super::{FolderEntry, Params};
+
+use axum::http::StatusCode;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH
+};
+
+/// The number of entries returned in a page if the client doesn't specify
+/// a `limit`.
+pub const DEFAULT_LIMIT: usize = 500;
+
+/// The maximum number of entries a client can request in a single page.
+pub const MAX_LIMIT: usize = 5000;
+
+/// The keys the entries of a folder can be sorted by.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// Sort by filename, using a natural ordering (`img2` before `img10`).
+    #[default]
+    Name,
+
+    /// Sort by the time of the last modification.
+    Mtime,
+
+    /// Sort by the capture date stored in the EXIF data of the image.
+    /// Entries without capture date are sorted last.
+    Date,
+
+    /// Sort by the size of the file.
+    Size
+}
+
+/// The direction of the sorting.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc
+}
+
+/// A page of the entries of a folder.
+pub struct Page {
+    pub entries: Vec<FolderEntry>,
+
+    /// The cursor to be used for requesting the next page.
+    /// `None` if this is the last page.
+    pub next: Option<String>
+}
+
+/// What the index knows about a file of the listed folder.
+struct IndexedFile {
+    /// The mimetype detected from the content of the file.
+    mimetype: Option<String>,
+
+    /// The capture date, see [`index::capture_date`]. `0` if the file has
+    /// none, `None` if it hasn't been read yet.
+    taken_at: Option<i64>
+}
+
+/// The position of an entry in the sorted listing.
+/// It is what the cursors encode, so that the next page can be computed
+/// even if the folder has changed in the meanwhile.
+#[derive(Debug, Deserialize, Serialize)]
+struct Position {
+    is_dir: bool,
+    key: Option<i64>,
+    name: String,
+
+    /// The encoded name, unlike `name` it is unique within the folder.
+    path: String
+}
+
+/// Sorts, filters and paginates the entries of the folder `fullpath`
+/// according to the query parameters in `params`.
+///
+/// # Arguments
+///
+/// - `pool` - The database, used for filtering by tag and for the mimetypes
+///   of the indexed files.
+/// - `library` - The library containing the folder.
+/// - `fullpath` - The folder on the local file system.
+/// - `relative` - The path of the folder relative to the root, as stored in
+///   the index.
+/// - `entries` - The entries of the folder.
+/// - `params` - The query parameters of the request.
+pub async fn paginate(
+    pool: &SqlitePool,
+    library: &Library,
+    fullpath: &Path,
+    relative: &str,
+    entries: Vec<FolderEntry>,
+    params: &Params
+) -> ApiResult<Page> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let after = params.cursor.as_deref().map(decode_cursor).transpose()?;
+    let name_matcher = params.name.as_deref().map(compile_glob).transpose()?;
+    let tagged = match &params.tag {
+        Some(tag) => Some(tagged_paths(pool, library, tag).await?),
+        None => None
+    };
+
+    let indexed = indexed_files(pool, library, relative).await?;
+    let entries: Vec<FolderEntry> = entries.into_iter()
+        .map(|mut entry| {
+            let mimetype = indexed.get(&entry.path).and_then(|file| file.mimetype.clone());
+            if mimetype.is_some() && !entry.is_dir {
+                entry.mimetype = mimetype;
+            }
+            entry
+        })
+        .filter(|entry| matches_name(entry, name_matcher.as_ref()))
+        .filter(|entry| matches_mimetype(entry, params.mimetype.as_deref()))
+        .filter(|entry| matches_tag(entry, relative, tagged.as_ref()))
+        .collect();
+
+    let sort = params.sort.unwrap_or_default();
+    let order = params.order.unwrap_or_default();
+    let dirs_first = params.dirs_first.unwrap_or(false);
+
+    let fullpath = fullpath.to_path_buf();
+    let mut entries = tokio::task::spawn_blocking(move || with_positions(&fullpath, entries, sort, &indexed))
+        .await?;
+    entries.sort_by(|(_, a), (_, b)| compare(a, b, dirs_first, order));
+
+    let mut entries = entries.into_iter()
+        .skip_while(|(_, position)| after.as_ref()
+            .map(|after| compare(position, after, dirs_first, order) != Ordering::Greater)
+            .unwrap_or(false)
+        )
+        .take(limit + 1)
+        .collect::<Vec<_>>();
+
+    let next = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|(_, position)| encode_cursor(position))
+    } else {
+        None
+    };
+
+    Ok(Page {
+        entries: entries.into_iter().map(|(entry, _)| entry).collect(),
+        next
+    })
+}
+
+/// Computes the sort position of every entry in `entries`, using what the
+/// index knows about them in `indexed`.
+/// This might need to read the files, therefore it shouldn't be called
+/// from an async context.
+fn with_positions(
+    fullpath: &Path,
+    entries: Vec<FolderEntry>,
+    sort: SortKey,
+    indexed: &HashMap<String, IndexedFile>
+) -> Vec<(FolderEntry, Position)> {
+    entries.into_iter()
+        .map(|entry| {
+            let filepath = fullpath.join(entry.os_name());
+            let key = if entry.is_dir { None } else { sort_key(&filepath, sort, indexed.get(&entry.path)) };
+            let position = Position {
+                is_dir: entry.is_dir,
+                key,
+                name: entry.filename.clone(),
+                path: entry.path.clone()
+            };
+            (entry, position)
+        })
+        .collect()
+}
+
+/// Returns the value the file `filepath` is sorted by. The capture date is
+/// taken from the index if the file is indexed, `indexed`, since reading it
+/// from the image is slow.
+fn sort_key(filepath: &PathBuf, sort: SortKey, indexed: Option<&IndexedFile>) -> Option<i64> {
+    match sort {
+        SortKey::Name => None,
+        SortKey::Mtime => fs::metadata(filepath).ok()
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as i64),
+        SortKey::Size => fs::metadata(filepath).ok()
+            .map(|metadata| metadata.len() as i64),
+        SortKey::Date => match indexed.and_then(|file| file.taken_at) {
+            Some(0) => None,
+            Some(taken_at) => Some(taken_at),
+            None => index::capture_date(filepath)
+        }
+    }
+}
+
+/// Compares the positions of two entries.
+/// Entries without a key come after entries with a key in both orders, ties
+/// are broken by the natural ordering of the filenames.
+fn compare(a: &Position, b: &Position, dirs_first: bool, order: Order) -> Ordering {
+    let dirs = if dirs_first {
+        b.is_dir.cmp(&a.is_dir)
+    } else {
+        Ordering::Equal
+    };
+    let missing = a.key.is_none().cmp(&b.key.is_none());
+
+    let keys = match (a.key, b.key) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => Ordering::Equal
+    };
+    let ordering = keys
+        .then_with(|| natord::compare(&a.name, &b.name))
+        .then_with(|| a.path.cmp(&b.path));
+
+    dirs.then(missing).then(match order {
+        Order::Asc => ordering,
+        Order::Desc => ordering.reverse()
+    })
+}
+
+fn encode_cursor(position: &Position) -> String {
+    // Serializing a struct of strings and numbers cannot fail
+    let json = serde_json::to_vec(position).unwrap_or_default();
+    URL_SAFE_NO_PAD.encode(json)
+}
+
+fn decode_cursor(cursor: &str) -> ApiResult<Position> {
+    URL_SAFE_NO_PAD.decode(cursor).ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST)
+            .with_msg("Invalid cursor".to_string())
+        )
+}
+
+fn compile_glob(pattern: &str) -> ApiResult<GlobMatcher> {
+    Glob::new(pattern)
+        .map(|glob| glob.compile_matcher())
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST)
+            .with_msg(format!("Invalid name pattern: {err}"))
+        )
+}
+
+/// Returns the relative paths of all the files of `library` tagged with `tag`,
+/// except the hidden ones.
+async fn tagged_paths(pool: &SqlitePool, library: &Library, tag: &str) -> ApiResult<HashSet<String>> {
+    let paths: Vec<String> = sqlx::query_scalar(
+            "SELECT files.relative_path FROM files
+            JOIN tags ON tags.file_id = files.id
+            WHERE tags.tag = ? AND files.library = ? AND files.trash_id IS NULL"
+        )
+        .bind(tag)
+        .bind(&library.name)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(paths.into_iter()
+        .filter(|path| library.access.allows(&subpath::decode_path(path), Permission::Read))
+        .collect())
+}
+
+/// Returns what the index knows about the files of the folder `relative`,
+/// by encoded filename.
+async fn indexed_files(pool: &SqlitePool, library: &Library, relative: &str) -> ApiResult<HashMap<String, IndexedFile>> {
+    let prefix = if relative.is_empty() { String::new() } else { format!("{relative}/") };
+    let rows: Vec<(String, Option<String>, Option<i64>)> = sqlx::query_as(
+            "SELECT substr(relative_path, length(?1) + 1), mimetype, taken_at FROM files
+            WHERE library = ?2 AND trash_id IS NULL AND missing_since IS NULL
+            AND substr(relative_path, 1, length(?1)) = ?1
+            AND instr(substr(relative_path, length(?1) + 1), '/') = 0"
+        )
+        .bind(&prefix)
+        .bind(&library.name)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter()
+        .map(|(name, mimetype, taken_at)| (name, IndexedFile { mimetype, taken_at }))
+        .collect())
+}
+
+fn matches_name(entry: &FolderEntry, matcher: Option<&GlobMatcher>) -> bool {
+    matcher.map(|matcher| matcher.is_match(&entry.filename))
+        .unwrap_or(true)
+}
+
+/// Folders have no mimetype, they are kept regardless of the filter.
+fn matches_mimetype(entry: &FolderEntry, prefix: Option<&str>) -> bool {
+    match (prefix, &entry.mimetype) {
+        (Some(prefix), Some(mimetype)) => mimetype.starts_with(prefix),
+        _ => true
+    }
+}
+
+/// Files match if they are tagged, folders if they contain a tagged file.
+fn matches_tag(entry: &FolderEntry, relative: &str, tagged: Option<&HashSet<String>>) -> bool {
+    let tagged = match tagged {
+        Some(tagged) => tagged,
+        None => return true
+    };
+
+    let path = if relative.is_empty() {
+        entry.path.clone()
+    } else {
+        format!("{relative}/{}", entry.path)
+    };
+
+    if entry.is_dir {
+        let prefix = format!("{path}/");
+        tagged.iter().any(|tagged| tagged.starts_with(&prefix))
+    } else {
+        tagged.contains(&path)
+    }
+}
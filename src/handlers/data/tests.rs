@@ -1,29 +1,32 @@
-use crate::{AppConf, AppState, infrastructure};
-use super::{FolderEntry, Params};
+use crate::{
+    api::subpath::SubPath,
+    exclude::Exclusions,
+    index,
+    renditions::{self, Rendition},
+    test_utils::{self, make_root, read_body},
+    AppConf,
+    AppState,
+    AuthConf,
+    LibraryConf,
+    DEFAULT_LIBRARY
+};
+use super::{FolderEntry, Params, listing::{Order, SortKey}, tree::{self, Recursive}};
 
 use axum::{
-    extract::{Query, State, self},
-    http::StatusCode,
-    body::{HttpBody}
+    body::{Body, HttpBody},
+    http::{Request, StatusCode},
+    response::Response
 };
 use bytes::Bytes;
 use http_body::combinators::UnsyncBoxBody;
 use image::{io::Reader as ImageReader, DynamicImage};
 use ring::digest::{Context, Digest, SHA256};
-use ring::test;
 use rstest::*;
-use sqlx::{SqlitePool, Sqlite};
-use std::{env, io, sync::Arc, vec};
+use std::{env, fs, io, path::{Path, PathBuf}, sync::Arc, time::{Duration, SystemTime}, vec};
+use tower::ServiceExt;
 
 // FIXME: replace unwrap with expect
 
-static DB_URL: &str = "sqlite://test.db";
-
-async fn setup() {
-    infrastructure::ensure_db::<Sqlite>(DB_URL).await
-        .expect("Database migration failed");
-}
-
 
 #[tokio::test]
 async fn get_folder_entries_test() {
@@ -32,15 +35,18 @@ async fn get_folder_entries_test() {
         .expect("Cannot read current dir")
         .join("data");
 
-    let fullpath = super::make_fullpath(root.to_str().unwrap(), None).unwrap();
-    let mut actual = super::get_folder_entries(&fullpath)
+    let fullpath = super::make_fullpath(root.to_str().unwrap(), Path::new("")).unwrap();
+    let exclusions = Exclusions::new(&fullpath, &LibraryConf::default().exclude).unwrap();
+    let mut actual = super::get_folder_entries(&fullpath, &exclusions)
         .await.unwrap();
     actual.sort();
 
     let folder_entry = |filename: &str, mimetype: Option<&str>, is_dir: bool| FolderEntry {
         filename: filename.to_string(),
+        path: filename.to_string(),
         mimetype: mimetype.map(|mt| mt.to_string()),
-        is_dir
+        is_dir,
+        is_symlink: false
     };
 
     let expected = vec![
@@ -52,38 +58,58 @@ async fn get_folder_entries_test() {
     assert_eq!(actual, expected);
 }
 
-async fn make_state() -> State<Arc<AppState>> {
+async fn make_state() -> Arc<AppState> {
     let root = env::current_dir()
         .unwrap()
         .join("data");
+    make_state_with_root(&root).await
+}
+
+async fn make_state_with_root(root: &Path) -> Arc<AppState> {
     let root = root.to_str().unwrap();
-    let conf = AppConf {
+    let library = LibraryConf {
         root: root.to_string(),
-        connection: "0.0.0.0:3000".to_string(),
-        max_level: "DEBUG".to_string()
+        ..Default::default()
+    };
+    let conf = AppConf {
+        auth: AuthConf { required: false, ..Default::default() },
+        connection: vec!["0.0.0.0:3000".to_string()],
+        max_level: "DEBUG".to_string(),
+        libraries: [(DEFAULT_LIBRARY.to_string(), library)].into(),
+        ..Default::default()
     };
 
-    let pool = SqlitePool::connect(DB_URL)
-        .await
-        .unwrap();
-    let state = AppState {
-        conf,
-        pool
+    // The listings read the index
+    let pool = test_utils::make_pool().await;
+    let state = AppState::new(conf, pool);
+
+    Arc::new(state)
+}
+
+/// Requests the percent-encoded `path` of the default library from the
+/// data endpoint.
+async fn download(state: &Arc<AppState>, path: &str, params: &Params) -> Response {
+    let query = serde_urlencoded::to_string(params).unwrap();
+    let uri = if path.is_empty() {
+        format!("/data/{DEFAULT_LIBRARY}?{query}")
+    } else {
+        format!("/data/{DEFAULT_LIBRARY}/{path}?{query}")
     };
+    let request = Request::get(uri)
+        .body(Body::empty())
+        .unwrap();
 
-    State(Arc::new(state))
+    crate::app(state.clone()).oneshot(request).await.unwrap()
 }
 
 #[tokio::test]
 async fn folder_return_type_test() {
-    setup().await;
-
     // if the path is a folder the endpoint will return a json
     let state = make_state().await;
     let params = Params::default();
-    let subpath = extract::Path("folder".to_string());
+    let subpath = "folder";
 
-    let response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let response = download(&state, subpath, &params).await;
     let content_type = response.headers().get("Content-Type").unwrap();
 
     assert_eq!(content_type.to_str().unwrap(), "application/json");
@@ -91,14 +117,12 @@ async fn folder_return_type_test() {
 
 #[tokio::test]
 async fn file_return_type_test() {
-    setup().await;
-
     // if the path is a file the response headers will contain the content type of the file
     let state = make_state().await;
     let params = Params::default();
-    let subpath = extract::Path("penguins.jpg".to_string());
+    let subpath = "penguins.jpg";
 
-    let response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let response = download(&state, subpath, &params).await;
     let content_type = response.headers().get("Content-Type").cloned().unwrap();
 
     assert_eq!(content_type.to_str().unwrap(), "image/jpeg");
@@ -117,38 +141,30 @@ async fn sha256_digest(body: &mut UnsyncBoxBody<Bytes, axum::Error>) -> anyhow::
 
 #[tokio::test]
 async fn file_return_checksum_test() {
-    setup().await;
-
     // if the path is a file the endpoint will return the content of the file
     let state = make_state().await;
     let params = Params::default();
-    let subpath = extract::Path("penguins.jpg".to_string());
+    let subpath = "penguins.jpg";
 
-    let mut response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let mut response = download(&state, subpath, &params).await;
 
     let body = response.body_mut();
     let actual_hash = sha256_digest(body).await.unwrap();
 
-    let expected_hash = "382AD1ABC24D92D8941A38CA3B8B3A2AF9B616D13347F10361C3790D4C78C7E7";
-    let expected_hash = test::from_hex(expected_hash).unwrap();
+    let actual_hash: String = actual_hash.as_ref().iter().map(|byte| format!("{byte:02X}")).collect();
 
-    assert_eq!(&expected_hash, actual_hash.as_ref());
+    assert_eq!(actual_hash, "382AD1ABC24D92D8941A38CA3B8B3A2AF9B616D13347F10361C3790D4C78C7E7");
 }
 
 #[tokio::test]
 async fn not_exists_return_type_test() {
-    setup().await;
-
     // if the path doesn't exist the endpoint will return a 404 error code
     let state = make_state().await;
     let params = Params::default();
-    let subpath = extract::Path("not_exists".to_string());
-
-    let result = super::download(state, Some(subpath), Query(params)).await;
-    assert!(result.is_err());
+    let subpath = "not_exists";
 
-    let status = result.unwrap_err();
-    assert_eq!(status.status, StatusCode::from_u16(404).unwrap());
+    let response = download(&state, subpath, &params).await;
+    assert_eq!(response.status(), StatusCode::from_u16(404).unwrap());
 }
 
 #[rstest]
@@ -156,14 +172,12 @@ async fn not_exists_return_type_test() {
 #[case("apollon.jpg")]
 #[tokio::test]
 async fn file_download_name_test(#[case] filename: &str) {
-    setup().await;
-
     // if the path is a file the browser will download the file with the correct name
     let state = make_state().await;
     let params = Params::default();
 
-    let subpath = extract::Path(filename.to_string());
-    let response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let subpath = filename;
+    let response = download(&state, subpath, &params).await;
     let content_type = response.headers().get("Content-Disposition").cloned().unwrap();
 
     assert_eq!(content_type.to_str().unwrap(), format!("attachment; filename=\"{filename}\""));
@@ -191,8 +205,6 @@ async fn read_image(body: &mut UnsyncBoxBody<Bytes, axum::Error>) -> DynamicImag
 #[case(Some(true))]
 #[tokio::test]
 async fn lower_max_width_test(#[case] thumbnail: Option<bool>) {
-    setup().await;
-
     // if the path is an image and the max_width query parameter is set
     // to a value lower than the image's width,
     // the endpoint will resize the image and mantain the ratio.
@@ -202,11 +214,12 @@ async fn lower_max_width_test(#[case] thumbnail: Option<bool>) {
     let params = Params { 
         max_width: Some(200),
         max_height: None,
-        thumbnail
+        thumbnail,
+        ..Default::default()
     };
 
-    let subpath = extract::Path(filename.to_string());
-    let mut response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let subpath = filename;
+    let mut response = download(&state, subpath, &params).await;
     let body = response.body_mut();
 
     let image = read_image(body).await;
@@ -219,8 +232,6 @@ async fn lower_max_width_test(#[case] thumbnail: Option<bool>) {
 #[case(Some(true))]
 #[tokio::test]
 async fn higher_max_width_test(#[case] thumbnail: Option<bool>) {
-    setup().await;
-
     // if the path is an image and the max_width query parameter is higher than
     // the image's width, the endpoint won't resize the image.
 
@@ -229,11 +240,12 @@ async fn higher_max_width_test(#[case] thumbnail: Option<bool>) {
     let params = Params { 
         max_width: Some(500),
         max_height: None,
-        thumbnail
+        thumbnail,
+        ..Default::default()
     };
 
-    let subpath = extract::Path(filename.to_string());
-    let mut response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let subpath = filename;
+    let mut response = download(&state, subpath, &params).await;
     let body = response.body_mut();
 
     let image = read_image(body).await;
@@ -246,8 +258,6 @@ async fn higher_max_width_test(#[case] thumbnail: Option<bool>) {
 #[case(Some(true))]
 #[tokio::test]
 async fn lower_max_height_test(#[case] thumbnail: Option<bool>) {
-    setup().await;
-
     // if the path is an image and the max_height query parameter is set
     // to a value lower than the image's height,
     // the endpoint will resize the image and mantain the ratio.
@@ -257,11 +267,12 @@ async fn lower_max_height_test(#[case] thumbnail: Option<bool>) {
     let params = Params { 
         max_width: None,
         max_height: Some(100),
-        thumbnail
+        thumbnail,
+        ..Default::default()
     };
 
-    let subpath = extract::Path(filename.to_string());
-    let mut response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let subpath = filename;
+    let mut response = download(&state, subpath, &params).await;
     let body = response.body_mut();
 
     let image = read_image(body).await;
@@ -274,8 +285,6 @@ async fn lower_max_height_test(#[case] thumbnail: Option<bool>) {
 #[case(Some(true))]
 #[tokio::test]
 async fn higher_max_height_test(#[case] thumbnail: Option<bool>) {
-    setup().await;
-
     // if the path is an image and the max_height query parameter is higher than
     // the image's height, the endpoint won't resize the image.
 
@@ -288,13 +297,564 @@ async fn higher_max_height_test(#[case] thumbnail: Option<bool>) {
     let params = Params { 
         max_width: None,
         max_height: Some(300),
-        thumbnail
+        thumbnail,
+        ..Default::default()
     };
 
-    let subpath = extract::Path(filename.to_string());
-    let mut response = super::download(state, Some(subpath), Query(params)).await.unwrap();
+    let subpath = filename;
+    let mut response = download(&state, subpath, &params).await;
     let body = response.body_mut();
 
     let image = read_image(body).await;
     assert!(image.height() == 296);
 }
+
+#[rstest]
+#[case("thumb", None, StatusCode::OK)]
+#[case("grid", None, StatusCode::OK)]
+#[case("huge", None, StatusCode::BAD_REQUEST)]
+#[case("thumb", Some(100), StatusCode::BAD_REQUEST)]
+#[tokio::test]
+async fn preset_test(#[case] preset: &str, #[case] max_width: Option<u32>, #[case] expected: StatusCode) {
+    // the presets of the configuration set the size of the images
+    let state = make_state().await;
+    let params = Params {
+        preset: Some(preset.to_string()),
+        max_width,
+        ..Default::default()
+    };
+
+    let mut response = download(&state, "penguins.jpg", &params).await;
+    assert_eq!(response.status(), expected);
+    if expected == StatusCode::OK {
+        let image = read_image(response.body_mut()).await;
+        let preset = &state.conf.renditions.presets[preset];
+        assert_eq!(image.width(), preset.max_width.unwrap().min(474));
+    }
+}
+
+#[rstest]
+#[case(Some(200), None, None, StatusCode::FORBIDDEN)]
+#[case(Some(200), None, Some("invalid"), StatusCode::FORBIDDEN)]
+#[case(Some(200), None, Some("secret"), StatusCode::OK)]
+#[case(Some(300), None, Some("secret"), StatusCode::OK)]
+#[case(None, Some("thumb"), None, StatusCode::OK)]
+#[case(None, None, None, StatusCode::OK)]
+#[tokio::test]
+async fn restricted_renditions_test(
+    #[case] max_width: Option<u32>,
+    #[case] preset: Option<&str>,
+    #[case] key: Option<&str>,
+    #[case] expected: StatusCode
+) {
+    // if the renditions are restricted, the images are only resized to
+    // a preset or to a size signed with the configured key
+    let mut state = Arc::into_inner(make_state().await).unwrap();
+    state.conf.renditions.restricted = true;
+    state.conf.renditions.signing_key = Some("secret".to_string());
+    let state = Arc::new(state);
+
+    let rendition = Rendition { max_width, ..Default::default() };
+    let params = Params {
+        max_width,
+        preset: preset.map(str::to_string),
+        signature: key.map(|key| renditions::sign(key, &rendition).unwrap()),
+        ..Default::default()
+    };
+
+    let mut response = download(&state, "penguins.jpg", &params).await;
+    assert_eq!(response.status(), expected);
+    if expected == StatusCode::OK {
+        let image = read_image(response.body_mut()).await;
+        assert_eq!(image.width(), max_width.or(preset.map(|_| 200)).unwrap_or(474));
+    }
+}
+
+async fn read_names(response: &mut Response) -> Vec<String> {
+    let buf = read_body(response).await;
+    let entries: Vec<serde_json::Value> = serde_json::from_slice(&buf).unwrap();
+    entries.iter()
+        .map(|entry| entry["filename"].as_str().unwrap().to_string())
+        .collect()
+}
+
+/// Parses the query parameters of the `Link` header, if any.
+fn next_page(response: &Response) -> Option<Params> {
+    let link = response.headers().get("Link")?.to_str().unwrap();
+    let (url, query) = link.strip_prefix('<')?.split('>').next()?.split_once('?')?;
+    assert_eq!(url, format!("/data/{DEFAULT_LIBRARY}"));
+    Some(serde_urlencoded::from_str(query).unwrap())
+}
+
+#[tokio::test]
+async fn folder_pagination_test() {
+    // if the folder has more entries than `limit`, the endpoint will
+    // return a cursor to the next page in the `Link` header
+    let mut params = Some(Params {
+        limit: Some(1),
+        ..Default::default()
+    });
+
+    let mut actual = vec![];
+    while let Some(next) = params {
+        let state = make_state().await;
+        let mut response = download(&state, "", &next).await;
+        let names = read_names(&mut response).await;
+        assert_eq!(names.len(), 1);
+
+        actual.extend(names);
+        params = next_page(&response);
+    }
+
+    assert_eq!(actual, vec!["apollon.jpg", "folder", "penguins.jpg"]);
+}
+
+#[rstest]
+#[case("", None, "/data/photos/folder", "</data/photos/folder?")]
+#[case("/fotos", None, "/fotos/data/photos/folder", "</fotos/data/photos/folder?")]
+#[case("/fotos", Some("/nas/fotos/"), "/fotos/data/photos/folder", "</nas/fotos/data/photos/folder?")]
+#[case("", Some("/fotos"), "/data/photos/folder", "</fotos/data/photos/folder?")]
+#[case("/fotos", Some("fotos>"), "/fotos/data/photos/folder", "</fotos/data/photos/folder?")]
+#[tokio::test]
+async fn pagination_prefix_test(
+    #[case] base_path: &str,
+    #[case] forwarded_prefix: Option<&str>,
+    #[case] uri: &str,
+    #[case] expected: &str
+) {
+    // the link to the next page respects the base path, or the prefix
+    // stripped by the reverse proxy if valid
+    let mut state = Arc::try_unwrap(make_state().await).ok().unwrap();
+    state.conf.base_path = base_path.to_string();
+
+    let mut request = Request::get(format!("{uri}?limit=1"));
+    if let Some(prefix) = forwarded_prefix {
+        request = request.header("X-Forwarded-Prefix", prefix);
+    }
+    let request = request.body(Body::empty()).unwrap();
+
+    let response = crate::app(state).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let link = response.headers()["Link"].to_str().unwrap();
+    assert!(link.starts_with(expected), "{link}");
+}
+
+#[tokio::test]
+async fn folder_sort_test() {
+    // the entries can be sorted by size, with the folders listed first
+    let state = make_state().await;
+    let params = Params {
+        sort: Some(SortKey::Size),
+        order: Some(Order::Desc),
+        dirs_first: Some(true),
+        ..Default::default()
+    };
+
+    let mut response = download(&state, "", &params).await;
+    let actual = read_names(&mut response).await;
+
+    assert!(next_page(&response).is_none());
+    assert_eq!(actual, vec!["folder", "apollon.jpg", "penguins.jpg"]);
+}
+
+#[rstest]
+#[case(Order::Asc, vec!["old.jpg", "new.jpg", "folder"])]
+#[case(Order::Desc, vec!["new.jpg", "old.jpg", "folder"])]
+#[tokio::test]
+async fn missing_sort_key_test(#[case] order: Order, #[case] expected: Vec<&str>) {
+    // the entries without a sort key are listed last in both orders
+    let root = make_root(&format!("sort-{order:?}"));
+    fs::create_dir_all(root.join("folder")).unwrap();
+    let now = SystemTime::now();
+    for (filename, age) in [("new.jpg", 60), ("old.jpg", 3600)] {
+        fs::copy("data/penguins.jpg", root.join(filename)).unwrap();
+        fs::File::options().write(true).open(root.join(filename)).unwrap()
+            .set_modified(now - Duration::from_secs(age))
+            .unwrap();
+    }
+
+    let state = test_utils::make_state(&root).await;
+    let params = Params {
+        sort: Some(SortKey::Mtime),
+        order: Some(order),
+        ..Default::default()
+    };
+    let mut response = download(&state, "", &params).await;
+    assert_eq!(read_names(&mut response).await, expected);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn tag_filter_test() {
+    // only the tagged files and the folders containing them are listed
+    let root = make_root("tag-filter");
+    fs::create_dir_all(root.join("folder/sub")).unwrap();
+    fs::create_dir_all(root.join("other")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("folder/sub/penguins.jpg")).unwrap();
+    fs::copy("data/apollon.jpg", root.join("folder/apollon.jpg")).unwrap();
+    fs::copy("data/apollon.jpg", root.join("other/apollon.jpg")).unwrap();
+
+    let state = test_utils::make_state(&root).await;
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+    index::index_folder(&state.pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    sqlx::query("INSERT INTO tags (file_id, tag)
+            SELECT id, 'penguins' FROM files WHERE relative_path = 'folder/sub/penguins.jpg'")
+        .execute(&state.pool)
+        .await
+        .unwrap();
+
+    let params = Params { tag: Some("penguins".to_string()), ..Default::default() };
+    let mut response = download(&state, "", &params).await;
+    assert_eq!(read_names(&mut response).await, vec!["folder"]);
+    let mut response = download(&state, "folder", &params).await;
+    assert_eq!(read_names(&mut response).await, vec!["sub"]);
+    let mut response = download(&state, "folder/sub", &params).await;
+    assert_eq!(read_names(&mut response).await, vec!["penguins.jpg"]);
+
+    let params = Params { tag: Some("unknown".to_string()), ..Default::default() };
+    let mut response = download(&state, "", &params).await;
+    assert!(read_names(&mut response).await.is_empty());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[rstest]
+#[case(Some("*.jpg"), None, vec!["LorenPizzajpg.jpg"])]
+#[case(None, Some("image/png"), vec!["topolino.png"])]
+#[case(None, Some("image/"), vec!["LorenPizzajpg.jpg", "topolino.png"])]
+#[case(Some("*.gif"), None, vec![])]
+#[tokio::test]
+async fn folder_filter_test(
+    #[case] name: Option<&str>,
+    #[case] mimetype: Option<&str>,
+    #[case] expected: Vec<&str>
+) {
+    // the entries can be filtered by name and mimetype
+    let state = make_state().await;
+    let params = Params {
+        name: name.map(|name| name.to_string()),
+        mimetype: mimetype.map(|mimetype| mimetype.to_string()),
+        ..Default::default()
+    };
+    let subpath = "folder";
+
+    let mut response = download(&state, subpath, &params).await;
+    let actual = read_names(&mut response).await;
+
+    assert_eq!(actual, expected);
+}
+
+#[tokio::test]
+async fn invalid_cursor_test() {
+    // if the cursor is not valid the endpoint will return a 400 error code
+    let state = make_state().await;
+    let params = Params {
+        cursor: Some("not a cursor".to_string()),
+        ..Default::default()
+    };
+
+    let response = download(&state, "", &params).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[rstest]
+#[case(0, "[]")]
+#[case(1, r#"[{"filename":"folder","path":"folder"}]"#)]
+#[case(5, r#"[{"filename":"folder","path":"folder"}]"#)]
+#[tokio::test]
+async fn folder_tree_test(#[case] depth: u32, #[case] expected: &str) {
+    // if the depth query parameter is set, the endpoint will return
+    // the tree of the subfolders
+    let state = make_state().await;
+    let params = Params {
+        depth: Some(depth),
+        ..Default::default()
+    };
+
+    let mut response = download(&state, "", &params).await;
+    assert!(response.headers().get(tree::TRUNCATED_HEADER).is_none());
+    let actual = read_body(&mut response).await;
+
+    assert_eq!(String::from_utf8(actual).unwrap(), expected);
+}
+
+#[rstest]
+#[case(5, false, vec!["a", "a/1", "a/2", "b", "b/1"])]
+#[case(4, true, vec!["a", "a/1", "a/2", "b"])]
+#[case(2, true, vec!["a", "a/1"])]
+#[case(0, true, vec![])]
+#[tokio::test]
+async fn folder_tree_limit_test(#[case] max_nodes: usize, #[case] truncated: bool, #[case] expected: Vec<&str>) {
+    // the folder tree holds at most the given number of folders, and tells
+    // whether some have been left out
+    let root = make_root(&format!("tree-limit-{max_nodes}"));
+    for folder in ["a/1", "a/2", "b/1"] {
+        fs::create_dir_all(root.join(folder)).unwrap();
+    }
+
+    fn collect_paths(nodes: &serde_json::Value, parent: &str, paths: &mut Vec<String>) {
+        for node in nodes.as_array().into_iter().flatten() {
+            let path = format!("{parent}{}", node["path"].as_str().unwrap());
+            paths.push(path.clone());
+            collect_paths(&node["children"], &format!("{path}/"), paths);
+        }
+    }
+
+    let exclusions = Exclusions::new(&root, &[]).unwrap();
+    let tree = tree::folder_tree(root.clone(), exclusions, 5, max_nodes).await.unwrap();
+    let mut actual = vec![];
+    collect_paths(&serde_json::to_value(&tree.nodes).unwrap(), "", &mut actual);
+    assert_eq!(actual, expected);
+    assert_eq!(tree.truncated, truncated);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[rstest]
+#[case(None, vec!["apollon.jpg", "folder/LorenPizzajpg.jpg", "folder/topolino.png", "penguins.jpg"])]
+#[case(Some(1), vec!["apollon.jpg", "penguins.jpg"])]
+#[tokio::test]
+async fn folder_flat_test(#[case] depth: Option<u32>, #[case] expected: Vec<&str>) {
+    // if recursive is set to flat, the endpoint will stream all the files
+    // beneath the folder as newline delimited json
+    let state = make_state().await;
+    let params = Params {
+        recursive: Some(Recursive::Flat),
+        depth,
+        ..Default::default()
+    };
+
+    let mut response = download(&state, "", &params).await;
+    let content_type = response.headers().get("Content-Type").cloned().unwrap();
+    assert_eq!(content_type.to_str().unwrap(), "application/x-ndjson");
+
+    let body = read_body(&mut response).await;
+    let mut actual: Vec<String> = String::from_utf8(body).unwrap()
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry["path"].as_str().unwrap().to_string()
+        })
+        .collect();
+    actual.sort();
+
+    assert_eq!(actual, expected);
+}
+
+#[tokio::test]
+async fn symlinked_folder_test() {
+    // the recursive listings don't follow the links to folders, even when
+    // they make a loop
+    let root = make_root("symlinks");
+    fs::create_dir_all(root.join("folder")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("folder/penguins.jpg")).unwrap();
+    std::os::unix::fs::symlink(&root, root.join("folder/loop")).unwrap();
+    std::os::unix::fs::symlink("missing", root.join("dangling")).unwrap();
+    let state = test_utils::make_state(&root).await;
+
+    let params = Params { recursive: Some(Recursive::Flat), ..Default::default() };
+    let mut response = download(&state, "", &params).await;
+    let body = read_body(&mut response).await;
+    let mut actual: Vec<String> = String::from_utf8(body).unwrap()
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry["path"].as_str().unwrap().to_string()
+        })
+        .collect();
+    actual.sort();
+    assert_eq!(actual, vec!["dangling", "folder/penguins.jpg"]);
+
+    let params = Params { depth: Some(3), ..Default::default() };
+    let mut response = download(&state, "", &params).await;
+    let tree = String::from_utf8(read_body(&mut response).await).unwrap();
+    assert_eq!(tree, r#"[{"filename":"folder","path":"folder","children":[{"filename":"loop","path":"loop"}]}]"#);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+/// Creates a folder with some files that should be excluded from the listings.
+fn make_excluded_root() -> PathBuf {
+    let root = make_root("exclude");
+
+    fs::create_dir_all(root.join("@eaDir")).unwrap();
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join(".DS_Store"), b"").unwrap();
+    fs::write(root.join("Thumbs.db"), b"").unwrap();
+    fs::write(root.join("keep.jpg"), b"").unwrap();
+    fs::write(root.join("sub/.fotosignore"), b"*.png\n!keep.png\n").unwrap();
+    fs::write(root.join("sub/hidden.png"), b"").unwrap();
+    fs::write(root.join("sub/keep.png"), b"").unwrap();
+
+    root
+}
+
+#[tokio::test]
+async fn excluded_entries_test() {
+    // files matching the global exclude list or a .fotosignore file
+    // are neither listed nor downloadable
+    let root = make_excluded_root();
+
+    let state = make_state_with_root(&root).await;
+    let params = Params {
+        recursive: Some(Recursive::Flat),
+        ..Default::default()
+    };
+    let mut response = download(&state, "", &params).await;
+    let body = read_body(&mut response).await;
+    let mut actual: Vec<String> = String::from_utf8(body).unwrap()
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry["path"].as_str().unwrap().to_string()
+        })
+        .collect();
+    actual.sort();
+    assert_eq!(actual, vec!["keep.jpg", "sub/keep.png"]);
+
+    for excluded in ["sub/hidden.png", "@eaDir", ".DS_Store", "sub/.fotosignore"] {
+        let state = make_state_with_root(&root).await;
+        let subpath = excluded;
+        let response = download(&state, subpath, &Params::default()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn non_utf8_filename_test() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    // filenames which aren't valid UTF-8 are listed with a lossy display name
+    // and a lossless encoded path, which can be used for downloading the file
+    let root = make_root("encoding");
+    fs::copy("data/penguins.jpg", root.join(OsStr::from_bytes(b"caf\xe9.jpg"))).unwrap();
+
+    let state = make_state_with_root(&root).await;
+    let mut response = download(&state, "", &Params::default()).await;
+    let body = read_body(&mut response).await;
+    let entries: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["filename"], "caf\u{FFFD}.jpg");
+    assert_eq!(entries[0]["path"], "caf%E9.jpg");
+
+    let state = make_state_with_root(&root).await;
+    let subpath = "caf%E9.jpg";
+    let response = download(&state, subpath, &Params::default()).await;
+    let content_type = response.headers().get("Content-Type").cloned().unwrap();
+    assert_eq!(content_type.to_str().unwrap(), "image/jpeg");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[rstest]
+#[case("../secret")]
+#[case("folder/../../secret")]
+#[case("%2Fetc%2Fpasswd")]
+#[case("folder%2F..%2F..")]
+fn escaping_subpath_test(#[case] encoded: &str) {
+    // paths escaping the root folder are rejected
+    let result = SubPath::from_encoded(encoded);
+    assert_eq!(result.unwrap_err().status, StatusCode::BAD_REQUEST);
+}
+
+#[rstest]
+#[case("penguins.png")]
+#[case("penguins")]
+#[tokio::test]
+async fn sniffed_mimetype_test(#[case] filename: &str) {
+    // the mimetype is detected from the content of the file, even if the
+    // extension is wrong or missing, and the image can still be resized
+    let root = make_root(&format!("sniff-{filename}"));
+    fs::copy("data/penguins.jpg", root.join(filename)).unwrap();
+
+    let state = make_state_with_root(&root).await;
+    let params = Params {
+        max_width: Some(200),
+        ..Default::default()
+    };
+    let mut response = download(&state, filename, &params).await;
+    let content_type = response.headers().get("Content-Type").cloned().unwrap();
+    assert_eq!(content_type.to_str().unwrap(), "image/jpeg");
+
+    let image = read_image(response.body_mut()).await;
+    assert_eq!(image.width(), 200);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn listed_mimetype_test() {
+    // the listings take the mimetype of the indexed files from the index,
+    // and guess the one of the other files from their extension
+    let root = make_root("listed-mimetype");
+    fs::create_dir_all(root.join("folder")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("penguins")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("folder/penguins")).unwrap();
+
+    let state = test_utils::make_state(&root).await;
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+    index::index_folder(&state.pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    fs::copy("data/penguins.jpg", root.join("unindexed")).unwrap();
+
+    let mimetypes = |body: Vec<u8>| {
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        entries.iter()
+            .map(|entry| (entry["filename"].as_str().unwrap().to_string(), entry["mimetype"].as_str().map(str::to_string)))
+            .collect::<Vec<_>>()
+    };
+    let mut response = download(&state, "", &Params::default()).await;
+    assert_eq!(mimetypes(read_body(&mut response).await), vec![
+        ("folder".to_string(), None),
+        ("penguins".to_string(), Some("image/jpeg".to_string())),
+        ("unindexed".to_string(), Some("application/octet-stream".to_string()))
+    ]);
+    let mut response = download(&state, "folder", &Params::default()).await;
+    assert_eq!(mimetypes(read_body(&mut response).await), vec![
+        ("penguins".to_string(), Some("image/jpeg".to_string()))
+    ]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn indexed_date_sort_test() {
+    // the listings are sorted by the capture dates stored in the index,
+    // the files without capture date are listed last
+    let root = make_root("indexed-date");
+    for filename in ["a.jpg", "b.jpg", "c.jpg"] {
+        fs::copy("data/penguins.jpg", root.join(filename)).unwrap();
+    }
+
+    let state = test_utils::make_state(&root).await;
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+    index::index_folder(&state.pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    let unread = || sqlx::query_scalar::<_, i64>("SELECT count(*) FROM files WHERE taken_at IS NULL")
+        .fetch_one(&state.pool);
+    assert_eq!(unread().await.unwrap(), 0);
+
+    // the files indexed before the capture dates were get them when
+    // indexed again, even if they didn't change
+    sqlx::query("UPDATE files SET taken_at = NULL").execute(&state.pool).await.unwrap();
+    index::index_folder(&state.pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    assert_eq!(unread().await.unwrap(), 0);
+
+    for (filename, taken_at) in [("a.jpg", 20210101120000_i64), ("b.jpg", 20200101120000)] {
+        sqlx::query("UPDATE files SET taken_at = ? WHERE relative_path = ?")
+            .bind(taken_at)
+            .bind(filename)
+            .execute(&state.pool)
+            .await
+            .unwrap();
+    }
+    fs::copy("data/penguins.jpg", root.join("0.jpg")).unwrap();
+
+    let params = Params { sort: Some(SortKey::Date), ..Default::default() };
+    let mut response = download(&state, "", &params).await;
+    assert_eq!(read_names(&mut response).await, vec!["b.jpg", "a.jpg", "0.jpg", "c.jpg"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
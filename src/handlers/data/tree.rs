@@ -0,0 +1,184 @@
+use crate::{
+    api::{error::ApiResult, subpath},
+    exclude::Exclusions
+};
+use super::{get_folder_entries, FolderEntry};
+
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{io, path::PathBuf};
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+
+/// The maximum depth of the folder tree a client can request.
+pub const MAX_DEPTH: u32 = 32;
+
+/// The maximum number of folders in the folder tree, the tree is truncated
+/// beyond it.
+pub const MAX_NODES: usize = 10_000;
+
+/// The response header set to `true` when the folder tree has been
+/// truncated.
+pub const TRUNCATED_HEADER: &str = "x-truncated";
+
+/// The ways a folder can be listed recursively.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Recursive {
+    /// List all the files beneath the folder, with their relative paths.
+    Flat
+}
+
+/// A folder in the folder tree.
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    filename: String,
+
+    /// The percent-encoded name of the folder, see `FolderEntry::path`.
+    path: String,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<TreeNode>
+}
+
+/// The subfolders of a folder, nested up to a given depth.
+#[derive(Debug)]
+pub struct FolderTree {
+    pub nodes: Vec<TreeNode>,
+
+    /// True if some folders have been left out, because the tree had
+    /// reached its maximum number of folders.
+    pub truncated: bool
+}
+
+/// A file found by walking through a folder.
+#[derive(Debug, Serialize)]
+struct FlatEntry {
+    /// The path of the file relative to the folder being walked,
+    /// for display purposes.
+    filename: String,
+
+    /// The percent-encoded path of the file relative to the folder
+    /// being walked, see `FolderEntry::path`.
+    path: String,
+
+    mimetype: Option<String>
+}
+
+/// Returns the subfolders of `fullpath`, nested up to `depth` levels, and
+/// at most `max_nodes` of them. The subfolders are sorted by name, symbolic
+/// links to folders are listed without their children.
+pub async fn folder_tree(
+    fullpath: PathBuf,
+    exclusions: Exclusions,
+    depth: u32,
+    max_nodes: usize
+) -> ApiResult<FolderTree> {
+    let mut remaining = max_nodes;
+    let mut truncated = false;
+    let nodes = subfolders(fullpath, exclusions, depth, &mut remaining, &mut truncated).await?;
+    Ok(FolderTree { nodes, truncated })
+}
+
+/// Returns the subfolders of `fullpath`, nested up to `depth` levels.
+/// Every folder takes one of the `remaining` ones, `truncated` is set once
+/// there are none left for the next folder.
+fn subfolders<'a>(
+    fullpath: PathBuf,
+    exclusions: Exclusions,
+    depth: u32,
+    remaining: &'a mut usize,
+    truncated: &'a mut bool
+) -> BoxFuture<'a, ApiResult<Vec<TreeNode>>> {
+    Box::pin(async move {
+        if depth == 0 || *truncated {
+            return Ok(vec![]);
+        }
+
+        let mut folders: Vec<FolderEntry> = get_folder_entries(&fullpath, &exclusions).await?
+            .into_iter()
+            .filter(|entry| entry.is_dir)
+            .collect();
+        folders.sort_by(|a, b| natord::compare(&a.filename, &b.filename));
+
+        let mut nodes = Vec::with_capacity(folders.len());
+        for entry in folders {
+            if *remaining == 0 {
+                *truncated = true;
+                break;
+            }
+            *remaining -= 1;
+
+            let folder = fullpath.join(entry.os_name());
+            let children = if entry.is_symlink {
+                vec![]
+            } else {
+                let exclusions = exclusions.descend(&folder);
+                subfolders(folder, exclusions, depth - 1, remaining, truncated).await?
+            };
+            nodes.push(TreeNode {
+                filename: entry.filename,
+                path: entry.path,
+                children
+            });
+        }
+
+        Ok(nodes)
+    })
+}
+
+/// Walks through the folder `fullpath` and streams all the files beneath it
+/// as newline delimited json.
+/// Only the files up to `depth` levels beneath `fullpath` will be listed.
+///
+/// The walk happens in a background task, which stops as soon as the client
+/// disconnects. Folders which can't be read are skipped, symbolic links to
+/// folders aren't followed.
+pub fn walk_flat(
+    fullpath: PathBuf,
+    exclusions: Exclusions,
+    depth: u32
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        let mut stack = vec![(PathBuf::new(), exclusions, 1)];
+        while let Some((relative, exclusions, level)) = stack.pop() {
+            let folder = fullpath.join(&relative);
+            let entries = match get_folder_entries(&folder, &exclusions).await {
+                Ok(entries) => entries,
+                Err(err) => {
+                    tracing::warn!("Cannot read folder {}: {:?}", relative.display(), err.message);
+                    continue;
+                }
+            };
+
+            for entry in entries {
+                let path = relative.join(entry.os_name());
+
+                if entry.is_dir {
+                    if !entry.is_symlink && level < depth {
+                        let exclusions = exclusions.descend(&fullpath.join(&path));
+                        stack.push((path, exclusions, level + 1));
+                    }
+                    continue;
+                }
+
+                let mut line = serde_json::to_vec(&FlatEntry {
+                    filename: path.to_string_lossy().to_string(),
+                    path: subpath::encode_path(&path),
+                    mimetype: entry.mimetype
+                }).unwrap_or_default();
+                line.push(b'\n');
+
+                if tx.send(Ok(Bytes::from(line))).await.is_err() {
+                    // The client has gone away
+                    return;
+                }
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
@@ -0,0 +1,268 @@
+use crate::{
+    acl::Permission,
+    api::{
+        error::{ApiError, ApiResult},
+        subpath::{self, SubPath}
+    },
+    fsutil,
+    index,
+    trash,
+    AppState,
+    Library
+};
+
+use axum::{
+    extract::{Json, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response}
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc
+};
+use tokio::fs;
+use uuid::Uuid;
+
+/// The body of the move and copy requests.
+///
+/// - `from` - The percent-encoded path of the file or folder to move or copy.
+/// - `to` - The percent-encoded destination path, which must not exist.
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    pub from: String,
+    pub to: String
+}
+
+/// The body of the rename requests.
+///
+/// - `path` - The percent-encoded path of the file or folder to rename.
+/// - `name` - The percent-encoded new name.
+#[derive(Debug, Deserialize)]
+pub struct RenameRequest {
+    path: String,
+    name: String
+}
+
+/// The response of the move, copy and rename requests.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Moved {
+    /// The percent-encoded path of the file or folder after the operation.
+    pub path: String
+}
+
+/// A file or folder beneath the root folder.
+struct Resolved {
+    fullpath: PathBuf,
+
+    /// The path relative to the root folder, as stored in the index.
+    relative: String
+}
+
+/// Moves a file or folder, together with its metadata in the database.
+pub async fn move_entry(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    Json(request): Json<TransferRequest>
+) -> ApiResult<Response> {
+    super::ensure_writable(&library)?;
+    let from = resolve_existing(&library, &SubPath::from_encoded(&request.from)?, Permission::Write).await?;
+    let to = resolve_new(&library, &SubPath::from_encoded(&request.to)?).await?;
+    if to.fullpath.starts_with(&from.fullpath) {
+        return Err(bad_request("Cannot move a folder into itself"));
+    }
+
+    move_resolved(&state, &library, &from, &to).await?;
+    Ok(moved(&library, &to))
+}
+
+/// Renames a file or folder, keeping it in the same folder.
+pub async fn rename_entry(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    Json(request): Json<RenameRequest>
+) -> ApiResult<Response> {
+    super::ensure_writable(&library)?;
+    let name = subpath::decode_name(&request.name);
+    let mut components = Path::new(&name).components();
+    if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+        return Err(bad_request("Invalid name"));
+    }
+
+    let path = SubPath::from_encoded(&request.path)?;
+    let from = resolve_existing(&library, &path, Permission::Write).await?;
+    let to = resolve_new(&library, &SubPath(path.as_path().with_file_name(&name))).await?;
+
+    move_resolved(&state, &library, &from, &to).await?;
+    Ok(moved(&library, &to))
+}
+
+/// Copies a file or folder, together with its metadata in the database.
+pub async fn copy_entry(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    Json(request): Json<TransferRequest>
+) -> ApiResult<Response> {
+    super::ensure_writable(&library)?;
+    let from = resolve_existing(&library, &SubPath::from_encoded(&request.from)?, Permission::Read).await?;
+    let to = resolve_new(&library, &SubPath::from_encoded(&request.to)?).await?;
+    if to.fullpath.starts_with(&from.fullpath) {
+        return Err(bad_request("Cannot copy a folder into itself"));
+    }
+
+    // The copy can take a while, the database is only locked once it is done
+    if let Err(err) = fsutil::copy_recursive(from.fullpath.clone(), to.fullpath.clone()).await {
+        let _ = fsutil::remove(&to.fullpath).await;
+        return Err(err.into());
+    }
+
+    let copied = async {
+        let mut tx = state.pool.begin().await?;
+        index::copy_entries(&mut tx, &library.name, &from.relative, &to.relative).await?;
+        tx.commit().await?;
+        anyhow::Ok(())
+    };
+    if let Err(err) = copied.await {
+        let _ = fsutil::remove(&to.fullpath).await;
+        return Err(err.into());
+    }
+
+    Ok(moved(&library, &to))
+}
+
+/// Query parameters for the delete endpoint.
+///
+/// - `permanent` - If set to true the file or folder is deleted for good
+///   instead of being moved to the trash.
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteParams {
+    permanent: Option<bool>
+}
+
+/// Deletes the file or folder specified by `subpath`, together with its
+/// metadata in the database. Folders are deleted recursively.
+///
+/// By default the file or folder is moved to the trash, and the endpoint
+/// responds with the trash entry as json, so that it can be restored.
+/// Permanent deletions respond with `204 No Content`.
+pub async fn delete_entry(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    subpath: SubPath,
+    Query(params): Query<DeleteParams>
+) -> ApiResult<Response> {
+    super::ensure_writable(&library)?;
+    let entry = resolve_existing(&library, &subpath, Permission::Write).await?;
+    if library.conf.trash_dir().starts_with(&entry.fullpath) {
+        return Err(bad_request("Cannot delete the folder containing the trash"));
+    }
+
+    if !params.permanent.unwrap_or(false) {
+        let trashed = trash::move_to_trash(&state.pool, &library, &entry.fullpath).await?;
+        return Ok(Json(trashed).into_response());
+    }
+
+    // The entry is set aside first, so that it can be put back if the
+    // database can't be updated, and removed once it has been
+    let staged = entry.fullpath.with_file_name(format!(".fotos-delete-{}", Uuid::new_v4()));
+    fs::rename(&entry.fullpath, &staged).await?;
+
+    let removed = async {
+        let mut tx = state.pool.begin().await?;
+        index::remove_entries(&mut tx, &library.name, &entry.relative).await?;
+        tx.commit().await?;
+        anyhow::Ok(())
+    };
+    if let Err(err) = removed.await {
+        let _ = fs::rename(&staged, &entry.fullpath).await;
+        return Err(err.into());
+    }
+
+    if let Err(err) = fsutil::remove(&staged).await {
+        // Temporary files are hidden by the default exclude list
+        tracing::warn!("Cannot remove {}: {:?}", staged.display(), err);
+    }
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn move_resolved(state: &AppState, library: &Library, from: &Resolved, to: &Resolved) -> ApiResult<()> {
+    let mut tx = state.pool.begin().await?;
+    index::move_entries(&mut tx, &library.name, &from.relative, &to.relative).await?;
+    fs::rename(&from.fullpath, &to.fullpath).await?;
+
+    if let Err(err) = tx.commit().await {
+        // Keep the file system consistent with the database
+        let _ = fs::rename(&to.fullpath, &from.fullpath).await;
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+/// Resolves the path of an existing file or folder, which the request needs
+/// `permission` on. The root folder itself can't be resolved.
+async fn resolve_existing(library: &Library, subpath: &SubPath, permission: Permission) -> ApiResult<Resolved> {
+    let resolved = resolve(library, subpath, permission).await?;
+    if fs::symlink_metadata(&resolved.fullpath).await.is_err() {
+        let msg = format!("path {} doesn't exist", resolved.fullpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(msg));
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves the path of a file or folder that doesn't exist yet.
+/// Its parent folder must exist.
+async fn resolve_new(library: &Library, subpath: &SubPath) -> ApiResult<Resolved> {
+    let resolved = resolve(library, subpath, Permission::Write).await?;
+    if fs::symlink_metadata(&resolved.fullpath).await.is_ok() {
+        let msg = format!("{} already exists", subpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::CONFLICT).with_msg(msg));
+    }
+
+    Ok(resolved)
+}
+
+/// Resolves `subpath` to a path beneath the root folder of `library`, refusing paths
+/// which escape the root folder through symbolic links, excluded paths, and
+/// paths the access rules don't grant `permission` on, or on something
+/// beneath them.
+/// `SubPath` already refuses paths escaping the root folder with `..`.
+async fn resolve(library: &Library, subpath: &SubPath, permission: Permission) -> ApiResult<Resolved> {
+    if subpath.is_root() {
+        return Err(bad_request("The root folder can't be modified"));
+    }
+
+    let root = Path::new(&library.conf.root);
+    let fullpath = root.join(subpath.as_path());
+    let exclusions = library.exclusions()?;
+    if exclusions.is_path_excluded(root, &fullpath) {
+        let msg = format!("{} is excluded", subpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+    }
+    super::ensure_allowed(library, subpath.as_path(), permission)?;
+    if !library.access.allows_all(subpath.as_path(), permission) {
+        let msg = format!("{permission} access to a path beneath {} is denied", subpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+    }
+
+    super::resolve_folder(library, fullpath.parent().unwrap_or(root)).await?;
+
+    Ok(Resolved {
+        relative: index::relative_path(root, &fullpath),
+        fullpath
+    })
+}
+
+fn moved(library: &Library, to: &Resolved) -> Response {
+    let relative = to.fullpath.strip_prefix(&library.conf.root).unwrap_or(&to.fullpath);
+    Json(Moved { path: subpath::encode_path(relative) }).into_response()
+}
+
+fn bad_request(msg: &str) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST).with_msg(msg.to_string())
+}
+
+#[cfg(test)]
+mod tests;
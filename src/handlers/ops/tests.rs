@@ -0,0 +1,247 @@
+use crate::{
+    exclude::Exclusions,
+    index,
+    test_utils::{library_conf, make_conf, make_pool, make_root, read_json, send},
+    AppState,
+    LibraryConf,
+    DEFAULT_LIBRARY
+};
+use super::Moved;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode}
+};
+use rstest::*;
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::{fs, path::{Path, PathBuf}, sync::Arc};
+
+/// Creates a root folder with some files, indexes it and tags
+/// `folder/penguins.jpg`.
+async fn make_state(name: &str) -> (Arc<AppState>, PathBuf) {
+    let root = make_root(&format!("ops-{name}"));
+    fs::create_dir_all(root.join("folder")).unwrap();
+    fs::create_dir_all(root.join("other")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("folder/penguins.jpg")).unwrap();
+    fs::copy("data/apollon.jpg", root.join("apollon.jpg")).unwrap();
+
+    let pool = make_pool().await;
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+    index::index_folder(&pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    sqlx::query("INSERT INTO tags (file_id, tag, created_by, created_at)
+            SELECT id, 'penguins', 'alice', 1000 FROM files WHERE relative_path = 'folder/penguins.jpg'")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let conf = make_conf(library_conf(&root));
+    (Arc::new(AppState::new(conf, pool)), root)
+}
+
+/// Makes a request to the operation `op` of the default library.
+fn operation(op: &str, body: serde_json::Value) -> Request<Body> {
+    Request::post(format!("/ops/{DEFAULT_LIBRARY}/{op}"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Returns the indexed files with their tags.
+async fn indexed(pool: &SqlitePool) -> Vec<(String, Option<String>)> {
+    sqlx::query_as(
+            "SELECT files.relative_path, tags.tag FROM files
+            LEFT JOIN tags ON tags.file_id = files.id
+            ORDER BY files.relative_path"
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+fn transfer(op: &str, from: &str, to: &str) -> Request<Body> {
+    operation(op, json!({ "from": from, "to": to }))
+}
+
+fn entry(path: &str, tag: Option<&str>) -> (String, Option<String>) {
+    (path.to_string(), tag.map(|tag| tag.to_string()))
+}
+
+#[tokio::test]
+async fn move_folder_test() {
+    // moving a folder moves the indexed files beneath it, keeping their tags
+    let (state, root) = make_state("move").await;
+    let pool = state.pool.clone();
+
+    let mut response = send(&state, transfer("move", "folder", "other/moved")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_json::<Moved>(&mut response).await.path, "other/moved");
+    assert!(root.join("other/moved/penguins.jpg").is_file());
+    assert!(!root.join("folder").exists());
+
+    assert_eq!(indexed(&pool).await, vec![
+        entry("apollon.jpg", None),
+        entry("other/moved/penguins.jpg", Some("penguins")),
+    ]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn rename_file_test() {
+    // renaming a file keeps it in the same folder
+    let (state, root) = make_state("rename").await;
+    let pool = state.pool.clone();
+    let request = operation("rename", json!({
+        "path": "folder/penguins.jpg",
+        "name": "pinguini%20.jpg"
+    }));
+
+    let mut response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_json::<Moved>(&mut response).await.path, "folder/pinguini%20.jpg");
+    assert!(root.join("folder/pinguini .jpg").is_file());
+
+    assert_eq!(indexed(&pool).await, vec![
+        entry("apollon.jpg", None),
+        entry("folder/pinguini%20.jpg", Some("penguins")),
+    ]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn copy_folder_test() {
+    // copying a folder copies the indexed files and their tags
+    let (state, root) = make_state("copy").await;
+    let pool = state.pool.clone();
+
+    let response = send(&state, transfer("copy", "folder", "copy")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(root.join("folder/penguins.jpg").is_file());
+    assert!(root.join("copy/penguins.jpg").is_file());
+
+    assert_eq!(indexed(&pool).await, vec![
+        entry("apollon.jpg", None),
+        entry("copy/penguins.jpg", Some("penguins")),
+        entry("folder/penguins.jpg", Some("penguins")),
+    ]);
+
+    // the copied tags keep their author
+    let authors: Vec<(String, i64)> = sqlx::query_as("SELECT created_by, created_at FROM tags")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(authors, vec![("alice".to_string(), 1000); 2]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn delete_folder_test() {
+    // deleting a folder permanently removes the indexed files beneath it
+    // and their tags
+    let (state, root) = make_state("delete").await;
+    let pool = state.pool.clone();
+    let request = Request::delete(format!("/data/{DEFAULT_LIBRARY}/folder?permanent=true"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!root.join("folder").exists());
+
+    assert_eq!(indexed(&pool).await, vec![entry("apollon.jpg", None)]);
+    let tags: i64 = sqlx::query_scalar("SELECT count(*) FROM tags")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(tags, 0);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn database_failure_test() {
+    // the file system is left as it was if the database can't be updated
+    let (state, root) = make_state("db-failure").await;
+    state.pool.close().await;
+
+    let response = send(&state, transfer("copy", "folder", "copy")).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!root.join("copy").exists());
+
+    let request = Request::delete(format!("/data/{DEFAULT_LIBRARY}/folder?permanent=true"))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(root.join("folder/penguins.jpg").is_file());
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 3);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[rstest]
+#[case("folder", "../outside", StatusCode::BAD_REQUEST)]
+#[case("folder", "folder/inside", StatusCode::BAD_REQUEST)]
+#[case("folder", "apollon.jpg", StatusCode::CONFLICT)]
+#[case("missing", "other/missing", StatusCode::NOT_FOUND)]
+#[case("folder", "missing/folder", StatusCode::NOT_FOUND)]
+#[case("", "other/root", StatusCode::BAD_REQUEST)]
+#[case("folder", ".hidden", StatusCode::FORBIDDEN)]
+#[tokio::test]
+async fn invalid_move_test(#[case] from: &str, #[case] to: &str, #[case] expected: StatusCode) {
+    // invalid moves are refused and nothing changes
+    let (state, root) = make_state(&format!("invalid-{}", expected.as_u16())).await;
+    let pool = state.pool.clone();
+    let before = indexed(&pool).await;
+
+    let response = send(&state, transfer("move", from, to)).await;
+    assert_eq!(response.status(), expected);
+    assert_eq!(indexed(&pool).await, before);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlink_escape_test() {
+    // symbolic links can't be used to escape the root folder
+    let (state, root) = make_state("symlink").await;
+    let outside = root.with_extension("outside");
+    fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+    let response = send(&state, transfer("move", "apollon.jpg", "link/apollon.jpg")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(!Path::new(&outside).join("apollon.jpg").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&outside).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn copy_symlink_test() {
+    // the symbolic links of a copied folder are copied as links, the
+    // content of their target isn't
+    let (state, root) = make_state("copy-symlink").await;
+    let outside = root.with_extension("outside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::write(outside.join("secret.txt"), b"secret").unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("folder/outside")).unwrap();
+    std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("folder/secret.txt")).unwrap();
+
+    let response = send(&state, transfer("copy", "folder", "copied")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    for name in ["outside", "secret.txt"] {
+        let copied = root.join("copied").join(name);
+        assert!(fs::symlink_metadata(&copied).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_link(&copied).unwrap(), fs::read_link(root.join("folder").join(name)).unwrap());
+    }
+    assert_eq!(fs::read_dir(&outside).unwrap().count(), 1);
+
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&outside).unwrap();
+}
@@ -0,0 +1,105 @@
+use crate::{
+    api::{
+        error::{ApiError, ApiResult},
+        prefix::Prefix
+    },
+    auth::{self, SESSION_COOKIE},
+    users::{self, Session},
+    AppState
+};
+
+use axum::{
+    extract::{ConnectInfo, Extension, Json, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response}
+};
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
+
+const SECONDS_PER_HOUR: i64 = 60 * 60;
+
+/// The body of the login requests.
+#[derive(Debug, Deserialize)]
+pub struct Credentials {
+    pub name: String,
+    pub password: String
+}
+
+/// Logs a user in. Responds with the session, including the CSRF token to
+/// send in the `X-CSRF-Token` header of the requests modifying anything,
+/// and sets the session cookie.
+///
+/// Responds with `401 Unauthorized` if the name or the password is wrong,
+/// and with `429 Too Many Requests` for a while after too many wrong
+/// passwords for the name or from the address of the client.
+pub async fn login(
+    State(state): State<Arc<AppState>>,
+    prefix: Prefix,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(credentials): Json<Credentials>
+) -> ApiResult<Response> {
+    let now = auth::now();
+    let mut keys = vec![format!("name:{}", credentials.name)];
+    if let Some(ConnectInfo(addr)) = connect_info {
+        keys.push(format!("addr:{}", addr.ip()));
+    }
+    if let Some(retry_after) = keys.iter().filter_map(|key| state.logins.throttled(key, now)).max() {
+        return Ok(super::too_many_failures(retry_after));
+    }
+
+    let Some(user) = users::authenticate(&state.pool, &credentials.name, &credentials.password).await? else {
+        for key in &keys {
+            state.logins.record(key, now);
+        }
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED).with_msg("Invalid name or password".to_string()));
+    };
+    state.logins.clear(&keys[0]);
+
+    let ttl_secs = state.conf.auth.session_ttl_hours as i64 * SECONDS_PER_HOUR;
+    let (session, secret) = users::open_session(&state.pool, &user, ttl_secs).await?;
+    tracing::info!("User {} logged in", user.name);
+
+    let cookie = cookie(&prefix, &secret, ttl_secs, state.conf.auth.secure_cookie)?;
+    Ok((AppendHeaders([(header::SET_COOKIE, cookie)]), Json(session)).into_response())
+}
+
+/// Returns the session of the logged in user.
+///
+/// Responds with `404 Not Found` if the request isn't made in a session,
+/// e.g. with a token.
+pub async fn get_session(session: Option<Extension<Session>>) -> ApiResult<Json<Session>> {
+    match session {
+        Some(Extension(session)) => Ok(Json(session)),
+        None => Err(ApiError::new(StatusCode::NOT_FOUND).with_msg("No session".to_string()))
+    }
+}
+
+/// Logs the user out: closes the session and clears the session cookie.
+pub async fn logout(
+    State(state): State<Arc<AppState>>,
+    prefix: Prefix,
+    headers: HeaderMap
+) -> ApiResult<impl IntoResponse> {
+    if let Some(secret) = auth::session_secret(&headers) {
+        users::close_session(&state.pool, &secret).await?;
+    }
+
+    let cookie = cookie(&prefix, "", 0, state.conf.auth.secure_cookie)?;
+    Ok((StatusCode::NO_CONTENT, AppendHeaders([(header::SET_COOKIE, cookie)])))
+}
+
+/// The session cookie, only sent by the browsers to the application and
+/// out of reach of the scripts.
+fn cookie(prefix: &Prefix, secret: &str, max_age_secs: i64, secure: bool) -> ApiResult<HeaderValue> {
+    let path = match prefix.0.as_str() {
+        "" => "/",
+        prefix => prefix
+    };
+    let secure = if secure { "; Secure" } else { "" };
+    let cookie = format!("{SESSION_COOKIE}={secret}; Path={path}; Max-Age={max_age_secs}; HttpOnly; SameSite=Strict{secure}");
+
+    Ok(HeaderValue::from_str(&cookie)?)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,130 @@
+use crate::{
+    auth::{CSRF_HEADER, SESSION_COOKIE},
+    test_utils::{make_pool, read_json},
+    throttle::MAX_FAILURES,
+    users::{self, Role, Session},
+    AppConf,
+    AppState
+};
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, Method, Request, StatusCode},
+    response::Response,
+    Router
+};
+use serde_json::json;
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+/// Makes the application requiring authentication under `base_path`,
+/// with an annotator.
+async fn make_app(base_path: &str) -> Router {
+    let pool = make_pool().await;
+    users::create(&pool, "alice", "correct horse", Role::Annotator).await.unwrap();
+    let conf = AppConf {
+        base_path: base_path.to_string(),
+        ..Default::default()
+    };
+
+    crate::app(AppState::new(conf, pool))
+}
+
+async fn send(app: &Router, method: Method, uri: &str, cookie: Option<&str>, csrf_token: Option<&str>) -> Response {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(cookie) = cookie {
+        request = request.header(header::COOKIE, cookie);
+    }
+    if let Some(csrf_token) = csrf_token {
+        request = request.header(CSRF_HEADER, csrf_token);
+    }
+
+    app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+}
+
+async fn login(app: &Router, uri: &str, password: &str) -> Response {
+    login_from(app, uri, "alice", password, None).await
+}
+
+/// Logs `name` in, from the client address `addr` if any.
+async fn login_from(app: &Router, uri: &str, name: &str, password: &str, addr: Option<SocketAddr>) -> Response {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({"name": name, "password": password}).to_string()))
+        .unwrap();
+    if let Some(addr) = addr {
+        request.extensions_mut().insert(ConnectInfo(addr));
+    }
+
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn login_test() {
+    // logging in sets the session cookie, used until logging out
+    let app = make_app("").await;
+
+    let response = login(&app, "/session", "wrong password").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+    let mut response = login(&app, "/session", "correct horse").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap().to_string();
+    assert!(set_cookie.starts_with(&format!("{SESSION_COOKIE}=fotos_")));
+    assert!(set_cookie.ends_with("; Path=/; Max-Age=604800; HttpOnly; SameSite=Strict; Secure"));
+    let session: Session = read_json(&mut response).await;
+    assert_eq!(session.user.name, "alice");
+    let cookie = set_cookie.split(';').next().unwrap();
+
+    let mut response = send(&app, Method::GET, "/session", Some(cookie), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_json::<Session>(&mut response).await, session);
+
+    let response = send(&app, Method::DELETE, "/session", Some(cookie), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(&app, Method::DELETE, "/session", Some(cookie), Some(&session.csrf_token)).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(response.headers()[header::SET_COOKIE].to_str().unwrap().contains("Max-Age=0"));
+
+    let response = send(&app, Method::GET, "/session", Some(cookie), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn login_base_path_test() {
+    // the session cookie is only sent to the application
+    let app = make_app("/fotos").await;
+
+    let response = login(&app, "/fotos/session", "correct horse").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::SET_COOKIE].to_str().unwrap().contains("; Path=/fotos;"));
+}
+
+#[tokio::test]
+async fn login_throttle_test() {
+    // too many wrong passwords for a name, or from an address, block the
+    // logins for a while, even with the right password
+    let app = make_app("").await;
+    let (attacker, other): (SocketAddr, SocketAddr) = ("10.0.0.1:1234".parse().unwrap(), "10.0.0.2:1234".parse().unwrap());
+
+    for attempt in 0..MAX_FAILURES {
+        let response = login_from(&app, "/session", &format!("user{attempt}"), "wrong password", Some(attacker)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = login_from(&app, "/session", "alice", "correct horse", Some(attacker)).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    let response = login_from(&app, "/session", "alice", "correct horse", Some(other)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for _ in 0..MAX_FAILURES {
+        let response = login_from(&app, "/session", "alice", "wrong password", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = login_from(&app, "/session", "alice", "correct horse", Some(other)).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+}
@@ -0,0 +1,269 @@
+use crate::{
+    acl::{Access, Permission},
+    api::{
+        error::{ApiError, ApiResult},
+        library::route_param,
+        prefix::Prefix,
+        subpath::{self, SubPath}
+    },
+    auth::{self, Principal},
+    shares::{self, NewShare, Share},
+    AppState,
+    Library
+};
+use super::{data::{self, Params}, ensure_allowed, too_many_failures};
+
+use axum::{
+    async_trait,
+    extract::{Extension, FromRequestParts, Json, Path, Query, State},
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response}
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The cookie proving that the password of a share link has been given,
+/// scoped to the link.
+pub const SHARE_COOKIE: &str = "fotos_share";
+
+/// How long the password of a share link is remembered, in seconds.
+const SESSION_SECS: i64 = 60 * 60;
+
+/// The body of the requests creating a share link.
+///
+/// - `path` - The percent-encoded path of the folder or file to share.
+///   The whole library is shared if empty.
+/// - `expires_in_days` - The validity of the link.
+/// - `password` - The password needed to open the link, if any. It is
+///   given as the password of the `Basic` authentication scheme, whatever
+///   the user name.
+/// - `sizes` - The sizes the shared images may be resized to, with the
+///   `max_width` and `max_height` parameters. The original images can be
+///   downloaded if omitted.
+#[derive(Debug, Deserialize)]
+pub struct NewShareLink {
+    #[serde(default)]
+    pub path: String,
+
+    pub expires_in_days: u32,
+    pub password: Option<String>,
+
+    #[serde(default)]
+    pub sizes: Vec<u32>
+}
+
+/// A share link with its url, as seen by the client.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SharedLink {
+    #[serde(flatten)]
+    pub share: Share,
+
+    pub url: String
+}
+
+/// The id and the signature of a share link, taken from the `:id` and
+/// `:signature` parameters of the route.
+pub struct ShareLink {
+    pub id: String,
+    pub signature: String
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ShareLink
+where
+    S: Send + Sync
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match (route_param(parts, ":id"), route_param(parts, ":signature")) {
+            (Some(id), Some(signature)) => Ok(Self { id, signature }),
+            _ => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_msg("Missing share link in route".to_string()))
+        }
+    }
+}
+
+/// Creates a link sharing a folder or a file of the library.
+///
+/// The link serves what the access rules let everyone read, so the path
+/// must be readable by everyone as well as by the creator of the link.
+/// Responds with `404 Not Found` if the path doesn't exist and with
+/// `403 Forbidden` if it can't be shared.
+pub async fn create_share(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    prefix: Prefix,
+    principal: Option<Extension<Principal>>,
+    Json(request): Json<NewShareLink>
+) -> ApiResult<(StatusCode, Json<SharedLink>)> {
+    let subpath = SubPath::from_encoded(&request.path)?;
+    let root = std::path::Path::new(&library.conf.root);
+    let fullpath = root.join(subpath.as_path());
+    if !fullpath.exists() || library.exclusions()?.is_path_excluded(root, &fullpath) {
+        let msg = format!("path {} doesn't exist", subpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(msg));
+    }
+    ensure_allowed(&library, subpath.as_path(), Permission::Read)?;
+    if !public_access(&state, &library).allows(subpath.as_path(), Permission::Read) {
+        let msg = format!("path {} isn't readable by everyone", subpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+    }
+
+    let new = NewShare {
+        library: library.name.clone(),
+        path: subpath::encode_path(subpath.as_path()),
+        ttl_secs: request.expires_in_days as i64 * SECONDS_PER_DAY,
+        password: request.password,
+        sizes: request.sizes,
+        created_by: principal.map(|Extension(principal)| principal.author())
+    };
+    shares::check(&new)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST).with_msg(err.to_string()))?;
+    let share = shares::create(&state.pool, new).await?;
+
+    Ok((StatusCode::CREATED, Json(shared_link(&state, &prefix, share).await?)))
+}
+
+/// Lists the share links of the library, the most recent first, including
+/// the expired and revoked ones.
+pub async fn list_shares(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    prefix: Prefix
+) -> ApiResult<Json<Vec<SharedLink>>> {
+    let mut links = vec![];
+    for share in shares::list(&state.pool, &library.name).await? {
+        links.push(shared_link(&state, &prefix, share).await?);
+    }
+
+    Ok(Json(links))
+}
+
+/// Revokes the share link `id` of the library, which can't be opened from
+/// then on.
+pub async fn revoke_share(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    Path((_, id)): Path<(String, String)>
+) -> ApiResult<StatusCode> {
+    if !shares::revoke(&state.pool, &library.name, &id).await? {
+        return Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(format!("share link {id} doesn't exist")));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Handles the public routes of the share links, serving the shared folder
+/// or file like [`download`](super::download), with `subpath` relative to it.
+///
+/// Responds with `404 Not Found` if the link or its signature is unknown,
+/// with `410 Gone` if it has expired or been revoked, and with
+/// `401 Unauthorized` if its password is missing or wrong. Once the password
+/// has been given, it is remembered for an hour by a cookie scoped to the
+/// link. After too many wrong passwords, the link responds with
+/// `429 Too Many Requests` for a minute. Every request served is counted.
+pub async fn open_share(
+    State(state): State<Arc<AppState>>,
+    link: ShareLink,
+    subpath: SubPath,
+    prefix: Prefix,
+    headers: HeaderMap,
+    params: Query<Params>
+) -> ApiResult<Response> {
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND).with_msg("share link doesn't exist".to_string());
+    let share = shares::verify(&state.pool, &state.shares, &link.id, &link.signature).await?
+        .ok_or_else(not_found)?;
+    let now = auth::now();
+    if !share.is_active(now) {
+        let msg = "share link has expired or has been revoked".to_string();
+        return Err(ApiError::new(StatusCode::GONE).with_msg(msg));
+    }
+
+    let mut session_cookie = None;
+    let opened = !share.protected || match auth::cookie(&headers, SHARE_COOKIE) {
+        Some(token) => shares::verify_session(&state.pool, &state.shares, &share, &token, now).await?,
+        None => false
+    };
+    if !opened {
+        if let Some(retry_after) = state.shares.failures.throttled(&share.id, now) {
+            return Ok(too_many_failures(retry_after));
+        }
+        let Some(password) = basic_password(&headers) else {
+            return Ok(password_needed());
+        };
+        if !shares::check_password(&state.pool, &share.id, &password).await? {
+            state.shares.failures.record(&share.id, now);
+            return Ok(password_needed());
+        }
+
+        state.shares.failures.clear(&share.id);
+        let expires_at = (now + SESSION_SECS).min(share.expires_at);
+        let token = shares::open_session(&state.pool, &state.shares, &share, expires_at).await?;
+        let path = prefix.url(&share.link(&link.signature));
+        session_cookie = Some(cookie(&path, &token, expires_at - now, state.conf.auth.secure_cookie)?);
+    }
+
+    let mut library = state.conf.library(&share.library).ok_or_else(not_found)?;
+    library.access = public_access(&state, &library);
+    let mut relative = SubPath::from_encoded(&share.path)?.0;
+    let mut path = share.link(&link.signature);
+    if !subpath.is_root() {
+        relative.push(subpath.as_path());
+        path = format!("{path}/{}", subpath::encode_path(subpath.as_path()));
+    }
+
+    let mut response = data::serve(&state, &library, &relative, &prefix.url(&path), &params, &share.sizes).await?;
+    shares::record_access(&state.pool, &share.id).await?;
+    if let Some(cookie) = session_cookie {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    Ok(response)
+}
+
+/// The access rules applying to everyone, which restrict the share links.
+fn public_access(state: &AppState, library: &Library) -> Access {
+    Access::new(&library.conf.access, &state.conf.auth.groups, None)
+}
+
+async fn shared_link(state: &AppState, prefix: &Prefix, share: Share) -> ApiResult<SharedLink> {
+    let signature = shares::sign(&state.pool, &state.shares, &share).await?;
+    let url = prefix.url(&share.link(&signature));
+
+    Ok(SharedLink { share, url })
+}
+
+/// Returns the password of the `Basic` authentication of the request.
+fn basic_password(headers: &HeaderMap) -> Option<String> {
+    let encoded = headers.get(header::AUTHORIZATION)?
+        .to_str().ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (_, password) = decoded.split_once(':')?;
+
+    Some(password.to_string())
+}
+
+fn password_needed() -> Response {
+    let mut response = ApiError::new(StatusCode::UNAUTHORIZED)
+        .with_msg("share link needs a password".to_string())
+        .into_response();
+    let challenge = HeaderValue::from_static("Basic realm=\"share\", charset=\"UTF-8\"");
+    response.headers_mut().insert(header::WWW_AUTHENTICATE, challenge);
+    response
+}
+
+/// The cookie remembering the password of the share link at `path`, only
+/// sent by the browsers to the link and out of reach of the scripts.
+fn cookie(path: &str, token: &str, max_age_secs: i64, secure: bool) -> ApiResult<HeaderValue> {
+    let secure = if secure { "; Secure" } else { "" };
+    let cookie = format!("{SHARE_COOKIE}={token}; Path={path}; Max-Age={max_age_secs}; HttpOnly; SameSite=Lax{secure}");
+
+    Ok(HeaderValue::from_str(&cookie)?)
+}
+
+#[cfg(test)]
+mod tests;
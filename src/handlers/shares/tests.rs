@@ -0,0 +1,241 @@
+use super::{SharedLink, SHARE_COOKIE};
+use crate::{
+    acl::Permission,
+    auth::{self, Scope},
+    test_utils::{bearer, call, library_conf, make_pool, make_root, read_body, read_json},
+    throttle::MAX_FAILURES,
+    AccessRule,
+    AppConf,
+    AppState,
+    AuthConf,
+    LibraryConf,
+    RenditionsConf,
+    DEFAULT_LIBRARY
+};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    response::Response,
+    Router
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::json;
+use std::{fs, path::PathBuf};
+use tower::ServiceExt;
+
+/// Makes the application requiring authentication, with an admin token and
+/// a library holding `holidays` and `family` folders. The family folder is
+/// only readable by the admins.
+async fn make_app(name: &str) -> (Router, String, PathBuf) {
+    make_app_with(name, RenditionsConf::default()).await
+}
+
+/// Makes the application of [`make_app`], with the renditions `renditions`.
+async fn make_app_with(name: &str, renditions: RenditionsConf) -> (Router, String, PathBuf) {
+    let root = make_root(&format!("shares-{name}"));
+    for folder in ["family", "holidays"] {
+        fs::create_dir_all(root.join(folder)).unwrap();
+        fs::copy("data/penguins.jpg", root.join(folder).join("penguins.jpg")).unwrap();
+    }
+
+    let pool = make_pool().await;
+    let (_, admin) = auth::create(&pool, "admin", &[Scope::Admin], None).await.unwrap();
+
+    let rule = |subject: &str, permission| AccessRule {
+        path: "family".to_string(),
+        subjects: vec![subject.to_string()],
+        permission
+    };
+    let library = LibraryConf {
+        access: vec![rule("*", Permission::None), rule("@admin", Permission::Write)],
+        ..library_conf(&root)
+    };
+    let conf = AppConf {
+        auth: AuthConf::default(),
+        libraries: [(DEFAULT_LIBRARY.to_string(), library)].into(),
+        renditions,
+        ..Default::default()
+    };
+
+    (crate::app(AppState::new(conf, pool)), admin, root)
+}
+
+async fn create(app: &Router, admin: &str, body: serde_json::Value) -> (StatusCode, Option<SharedLink>) {
+    let uri = format!("/shares/{DEFAULT_LIBRARY}");
+    let mut response = call(app, Method::POST, &uri, bearer(admin), Some(body)).await;
+    let status = response.status();
+    let body = read_body(&mut response).await;
+
+    (status, serde_json::from_slice(&body).ok())
+}
+
+fn basic(password: &str) -> Option<String> {
+    Some(format!("Basic {}", STANDARD.encode(format!("anyone:{password}"))))
+}
+
+#[tokio::test]
+async fn share_folder_test() {
+    // a protected folder is served through its link, with resized images only
+    let (app, admin, root) = make_app("folder").await;
+    let body = json!({"path": "holidays", "expires_in_days": 7, "password": "hunter22", "sizes": [100]});
+    let (status, created) = create(&app, &admin, body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let link = created.unwrap();
+    assert_eq!(link.share.path, "holidays");
+    assert_eq!(link.share.created_by.as_deref(), Some("token:admin"));
+    assert_eq!(link.share.expires_at - link.share.created_at, 7 * 24 * 60 * 60);
+    assert!(link.url.starts_with(&format!("/share/{}/", link.share.id)));
+
+    let response = call(&app, Method::GET, &link.url, None, None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers()[header::WWW_AUTHENTICATE].to_str().unwrap().starts_with("Basic"));
+    let response = call(&app, Method::GET, &link.url, basic("hunter2"), None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut response = call(&app, Method::GET, &link.url, basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let entries: serde_json::Value = read_json(&mut response).await;
+    assert_eq!(entries[0]["path"], "penguins.jpg");
+
+    let image = format!("{}/penguins.jpg", link.url);
+    let response = call(&app, Method::GET, &image, basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = call(&app, Method::GET, &format!("{image}?max_width=200"), basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let mut response = call(&app, Method::GET, &format!("{image}?max_width=100"), basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let resized = image::load_from_memory(&read_body(&mut response).await).unwrap();
+    assert!(resized.width() <= 100);
+
+    // the link can't be tampered with, and can be revoked
+    let tampered = link.url.replace(&link.share.id, &uuid::Uuid::new_v4().to_string());
+    let response = call(&app, Method::GET, &tampered, basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let mut response = call(&app, Method::GET, &format!("/shares/{DEFAULT_LIBRARY}"), bearer(&admin), None).await;
+    let listed: Vec<SharedLink> = read_json(&mut response).await;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].url, link.url);
+    assert_eq!(listed[0].share.access_count, 2);
+
+    let uri = format!("/shares/{DEFAULT_LIBRARY}/{}", link.share.id);
+    let response = call(&app, Method::DELETE, &uri, bearer(&admin), None).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = call(&app, Method::GET, &link.url, basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn restricted_renditions_test() {
+    // the sizes of a share don't need to be signed when the renditions
+    // are restricted, the other sizes still do
+    let renditions = RenditionsConf { restricted: true, ..Default::default() };
+    let (app, admin, root) = make_app_with("restricted", renditions).await;
+    let body = json!({"path": "holidays", "expires_in_days": 7, "sizes": [100]});
+    let link = create(&app, &admin, body).await.1.unwrap();
+
+    let image = format!("{}/penguins.jpg", link.url);
+    let response = call(&app, Method::GET, &format!("{image}?max_width=100"), None, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = call(&app, Method::GET, &format!("{image}?max_width=200"), None, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let uri = format!("/data/{DEFAULT_LIBRARY}/holidays/penguins.jpg?max_width=100");
+    let response = call(&app, Method::GET, &uri, bearer(&admin), None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+/// Opens `uri` with the share cookie `cookie`.
+async fn with_cookie(app: &Router, uri: &str, cookie: &str) -> Response {
+    let request = Request::get(uri)
+        .header(header::COOKIE, cookie)
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn password_session_test() {
+    // the password is checked once, then remembered by a cookie scoped to
+    // the link, and too many wrong passwords block the link for a while
+    let (app, admin, root) = make_app("session").await;
+    let body = json!({"path": "holidays", "expires_in_days": 7, "password": "hunter22"});
+    let link = create(&app, &admin, body.clone()).await.1.unwrap();
+    let other = create(&app, &admin, body).await.1.unwrap();
+
+    let response = call(&app, Method::GET, &link.url, basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.contains(&format!("Path={};", link.url)), "{set_cookie}");
+    assert!(set_cookie.contains("HttpOnly"), "{set_cookie}");
+    let (cookie, _) = set_cookie.split_once(';').unwrap();
+    assert!(cookie.starts_with(&format!("{SHARE_COOKIE}=")));
+
+    let response = with_cookie(&app, &format!("{}/penguins.jpg", link.url), cookie).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(header::SET_COOKIE));
+    let response = with_cookie(&app, &other.url, cookie).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = with_cookie(&app, &link.url, &format!("{SHARE_COOKIE}=0.forged")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    for _ in 0..MAX_FAILURES {
+        let response = call(&app, Method::GET, &link.url, basic("hunter2"), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    let response = call(&app, Method::GET, &link.url, basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    let response = with_cookie(&app, &link.url, cookie).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = call(&app, Method::GET, &other.url, basic("hunter22"), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn share_file_test() {
+    // a single file is shared as it is, without password
+    let (app, admin, root) = make_app("file").await;
+    let (status, created) = create(&app, &admin, json!({"path": "holidays/penguins.jpg", "expires_in_days": 1})).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let link = created.unwrap();
+
+    let mut response = call(&app, Method::GET, &link.url, None, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_body(&mut response).await, fs::read("data/penguins.jpg").unwrap());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn create_share_test() {
+    // only the paths everyone may read can be shared
+    let (app, admin, root) = make_app("create").await;
+
+    let (status, _) = create(&app, &admin, json!({"path": "family", "expires_in_days": 1})).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = create(&app, &admin, json!({"path": "missing", "expires_in_days": 1})).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = create(&app, &admin, json!({"path": "holidays", "expires_in_days": 0})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = create(&app, &admin, json!({"path": "holidays", "expires_in_days": 1, "sizes": [0]})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // the rules applying to everyone hide the family folder from a shared library
+    let (status, created) = create(&app, &admin, json!({"expires_in_days": 1})).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let link = created.unwrap();
+    let mut response = call(&app, Method::GET, &link.url, None, None).await;
+    assert!(!String::from_utf8(read_body(&mut response).await).unwrap().contains("family"));
+    let response = call(&app, Method::GET, &format!("{}/family/penguins.jpg", link.url), None, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    fs::remove_dir_all(&root).unwrap();
+}
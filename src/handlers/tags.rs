@@ -0,0 +1,200 @@
+use crate::{
+    acl::Permission,
+    api::{
+        error::{ApiError, ApiResult},
+        subpath::{self, SubPath}
+    },
+    auth::{self, Principal},
+    index,
+    AppState,
+    Library
+};
+
+use axum::{
+    extract::{Extension, Json, State},
+    http::StatusCode
+};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc
+};
+use tokio::fs;
+
+/// The maximum length of a tag.
+pub const MAX_TAG_LENGTH: usize = 100;
+
+/// The condition selecting the indexed file `?1`, or all the indexed files
+/// beneath the folder `?1`, of the library `?2`.
+const FILES_CONDITION: &str = "library = ?2 AND trash_id IS NULL AND missing_since IS NULL
+    AND (?1 = '' OR relative_path = ?1 OR substr(relative_path, 1, length(?1) + 1) = ?1 || '/')";
+
+/// The body of the tagging requests.
+///
+/// - `tags` - The tags to add or to remove.
+#[derive(Debug, Deserialize)]
+pub struct TagsRequest {
+    pub tags: Vec<String>
+}
+
+/// Returns the tags of the file specified by `subpath`, or the tags of all
+/// the files beneath the folder specified by `subpath`, sorted by name.
+pub async fn get_tags(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    subpath: SubPath
+) -> ApiResult<Json<Vec<String>>> {
+    let (_, relative) = resolve(&library, &subpath).await?;
+    Ok(Json(tags_of(&state.pool, &library, &relative).await?))
+}
+
+/// Adds tags to the file specified by `subpath`, or to all the files
+/// beneath the folder specified by `subpath`.
+///
+/// Tags are only stored in the database, therefore they can be added
+/// even if the root folder is read-only. They are attributed to the user
+/// or the token adding them, if authentication is required. The files the
+/// access rules don't allow annotating are skipped. Responds with the
+/// resulting tags, like [`get_tags`].
+pub async fn add_tags(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    library: Library,
+    subpath: SubPath,
+    Json(request): Json<TagsRequest>
+) -> ApiResult<Json<Vec<String>>> {
+    let tags = validate(request.tags)?;
+    let (fullpath, relative) = resolve(&library, &subpath).await?;
+    super::ensure_allowed(&library, subpath.as_path(), Permission::Annotate)?;
+    if !fs::metadata(&fullpath).await?.is_dir() {
+        // The file might not have been indexed yet
+        index::index_file(&state.pool, &library.name, Path::new(&library.conf.root), &fullpath).await?;
+    }
+
+    let author = principal.map(|Extension(principal)| principal.author());
+    let now = auth::now();
+    let files = selected_files(&state.pool, &library, &relative, Permission::Annotate).await?;
+    let mut tx = state.pool.begin().await?;
+    for file_id in &files {
+        for tag in &tags {
+            sqlx::query(
+                    "INSERT OR IGNORE INTO tags (file_id, tag, created_by, created_at)
+                    VALUES (?, ?, ?, ?)"
+                )
+                .bind(file_id)
+                .bind(tag)
+                .bind(&author)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+
+    Ok(Json(tags_of(&state.pool, &library, &relative).await?))
+}
+
+/// Removes tags from the file specified by `subpath`, or from all the files
+/// beneath the folder specified by `subpath`.
+/// Responds with the remaining tags, like [`get_tags`].
+pub async fn remove_tags(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    subpath: SubPath,
+    Json(request): Json<TagsRequest>
+) -> ApiResult<Json<Vec<String>>> {
+    let tags = validate(request.tags)?;
+    let (_, relative) = resolve(&library, &subpath).await?;
+    super::ensure_allowed(&library, subpath.as_path(), Permission::Annotate)?;
+
+    let files = selected_files(&state.pool, &library, &relative, Permission::Annotate).await?;
+    let mut tx = state.pool.begin().await?;
+    for file_id in &files {
+        for tag in &tags {
+            sqlx::query("DELETE FROM tags WHERE file_id = ? AND tag = ?")
+                .bind(file_id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+
+    Ok(Json(tags_of(&state.pool, &library, &relative).await?))
+}
+
+/// Returns the ids of the indexed files selected by `relative`, see
+/// [`FILES_CONDITION`], which the access rules grant `permission` on.
+async fn selected_files(
+    pool: &SqlitePool,
+    library: &Library,
+    relative: &str,
+    permission: Permission
+) -> ApiResult<Vec<String>> {
+    let files: Vec<(String, String)> = sqlx::query_as(&format!(
+            "SELECT id, relative_path FROM files WHERE {FILES_CONDITION}"
+        ))
+        .bind(relative)
+        .bind(&library.name)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(files.into_iter()
+        .filter(|(_, path)| library.access.allows(&subpath::decode_path(path), permission))
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// Returns the tags of the files selected by `relative` which aren't hidden,
+/// sorted by name.
+async fn tags_of(pool: &SqlitePool, library: &Library, relative: &str) -> ApiResult<Vec<String>> {
+    let tags: Vec<(String, String)> = sqlx::query_as(&format!(
+            "SELECT tags.tag, files.relative_path FROM tags
+            JOIN files ON files.id = tags.file_id
+            WHERE {FILES_CONDITION}"
+        ))
+        .bind(relative)
+        .bind(&library.name)
+        .fetch_all(pool)
+        .await?;
+
+    let tags: BTreeSet<String> = tags.into_iter()
+        .filter(|(_, path)| library.access.allows(&subpath::decode_path(path), Permission::Read))
+        .map(|(tag, _)| tag)
+        .collect();
+    Ok(tags.into_iter().collect())
+}
+
+/// Resolves `subpath` to an existing, not excluded path on the local file
+/// system. Returns the path and the path as stored in the index.
+async fn resolve(library: &Library, subpath: &SubPath) -> ApiResult<(PathBuf, String)> {
+    let root = Path::new(&library.conf.root);
+    let fullpath = root.join(subpath.as_path());
+    let exclusions = library.exclusions()?;
+    if exclusions.is_path_excluded(root, &fullpath) || fs::metadata(&fullpath).await.is_err() {
+        let msg = format!("path {} doesn't exist", fullpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(msg));
+    }
+
+    let relative = index::relative_path(root, &fullpath);
+    Ok((fullpath, relative))
+}
+
+/// Trims the tags, and refuses empty or too long ones.
+fn validate(tags: Vec<String>) -> ApiResult<Vec<String>> {
+    tags.into_iter()
+        .map(|tag| {
+            let tag = tag.trim();
+            if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+                let msg = format!("Invalid tag {tag:?}");
+                return Err(ApiError::new(StatusCode::BAD_REQUEST).with_msg(msg));
+            }
+            Ok(tag.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests;
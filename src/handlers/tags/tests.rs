@@ -0,0 +1,165 @@
+use crate::{
+    auth::{self, Scope},
+    users::{self, Role},
+    test_utils::{library_conf, make_conf, make_pool, make_root, read_json, send},
+    AppConf,
+    AppState,
+    index,
+    LibraryConf,
+    DEFAULT_LIBRARY
+};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    response::Response
+};
+use serde_json::json;
+use std::{fs, path::PathBuf, sync::Arc};
+
+/// Creates a root folder with two files in `folder`. Only one of them is
+/// indexed.
+async fn make_state(name: &str, read_only: bool) -> (Arc<AppState>, PathBuf) {
+    let root = make_root(&format!("tags-{name}"));
+    fs::create_dir_all(root.join("folder")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("folder/penguins.jpg")).unwrap();
+
+    let library = LibraryConf { read_only, ..library_conf(&root) };
+    let pool = make_pool().await;
+    let exclusions = library.exclusions().unwrap();
+    index::index_folder(&pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    let conf = make_conf(library);
+    fs::copy("data/apollon.jpg", root.join("folder/apollon.jpg")).unwrap();
+
+    (Arc::new(AppState::new(conf, pool)), root)
+}
+
+/// Sends the tags `tags` of the file or folder `path` to the tags endpoint
+/// of the default library.
+async fn tags_request(state: &Arc<AppState>, method: Method, path: &str, tags: &[&str]) -> Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(format!("/tags/{DEFAULT_LIBRARY}/{path}"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "tags": tags }).to_string()))
+        .unwrap();
+    send(state, request).await
+}
+
+/// Adds or removes tags, returning the resulting tags.
+async fn update_tags(state: &Arc<AppState>, method: Method, path: &str, tags: &[&str]) -> Vec<String> {
+    let mut response = tags_request(state, method, path, tags).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    read_json(&mut response).await
+}
+
+async fn tags_of(state: &Arc<AppState>, path: &str) -> Vec<String> {
+    let uri = if path.is_empty() {
+        format!("/tags/{DEFAULT_LIBRARY}")
+    } else {
+        format!("/tags/{DEFAULT_LIBRARY}/{path}")
+    };
+    let mut response = send(state, Request::get(uri).body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    read_json(&mut response).await
+}
+
+#[tokio::test]
+async fn tag_folder_test() {
+    // tagging a folder tags the indexed files beneath it,
+    // tagging a file indexes it first
+    let (state, root) = make_state("folder", false).await;
+
+    let tags = update_tags(&state, Method::POST, "folder", &["vacation ", "abruzzo"]).await;
+    assert_eq!(tags, vec!["abruzzo", "vacation"]);
+    assert_eq!(tags_of(&state, "folder/penguins.jpg").await, vec!["abruzzo", "vacation"]);
+    assert!(tags_of(&state, "folder/apollon.jpg").await.is_empty());
+
+    let tags = update_tags(&state, Method::POST, "folder/apollon.jpg", &["statue"]).await;
+    assert_eq!(tags, vec!["statue"]);
+    assert_eq!(tags_of(&state, "folder").await, vec!["abruzzo", "statue", "vacation"]);
+
+    let tags = update_tags(&state, Method::DELETE, "folder", &["vacation"]).await;
+    assert_eq!(tags, vec!["abruzzo", "statue"]);
+    assert_eq!(tags_of(&state, "").await, vec!["abruzzo", "statue"]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn invalid_tags_test() {
+    let (state, root) = make_state("invalid", false).await;
+
+    let response = tags_request(&state, Method::POST, "folder", &[" "]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = tags_request(&state, Method::POST, "missing", &["tag"]).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn read_only_test() {
+    // a read-only root can be annotated, but not modified
+    let (state, root) = make_state("read-only", true).await;
+
+    let tags = update_tags(&state, Method::POST, "folder", &["penguins"]).await;
+    assert_eq!(tags, vec!["penguins"]);
+
+    let transfer = Request::post(format!("/ops/{DEFAULT_LIBRARY}/move"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "from": "folder", "to": "moved" }).to_string()))
+        .unwrap();
+    let response = send(&state, transfer).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let delete = Request::delete(format!("/data/{DEFAULT_LIBRARY}/folder"))
+        .body(Body::empty())
+        .unwrap();
+    let response = send(&state, delete).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(root.join("folder/penguins.jpg").is_file());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn attribution_test() {
+    // the tags are attributed to the user or the token adding them
+    let (state, root) = make_state("attribution", false).await;
+    let alice = users::create(&state.pool, "alice", "correct horse", Role::Annotator).await.unwrap();
+    let (session, secret) = users::open_session(&state.pool, &alice, 60).await.unwrap();
+    let (_, token) = auth::create(&state.pool, "scanner", &[Scope::Annotate], None).await.unwrap();
+    let conf = AppConf {
+        libraries: state.conf.libraries.clone(),
+        ..Default::default()
+    };
+    let state = Arc::new(AppState::new(conf, state.pool.clone()));
+
+    let request = Request::post(format!("/tags/{DEFAULT_LIBRARY}/folder/penguins.jpg"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::COOKIE, format!("{}={secret}", auth::SESSION_COOKIE))
+        .header(auth::CSRF_HEADER, &session.csrf_token)
+        .body(Body::from(json!({ "tags": ["penguins"] }).to_string()))
+        .unwrap();
+    assert_eq!(send(&state, request).await.status(), StatusCode::OK);
+
+    let request = Request::post(format!("/tags/{DEFAULT_LIBRARY}/folder"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::AUTHORIZATION, format!("Bearer {token}"))
+        .body(Body::from(json!({ "tags": ["penguins", "zoo"] }).to_string()))
+        .unwrap();
+    assert_eq!(send(&state, request).await.status(), StatusCode::OK);
+
+    let authors: Vec<(String, String)> = sqlx::query_as("SELECT tag, created_by FROM tags ORDER BY tag")
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+    assert_eq!(authors, vec![
+        ("penguins".to_string(), "alice".to_string()),
+        ("zoo".to_string(), "token:scanner".to_string())
+    ]);
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,78 @@
+use crate::{
+    api::error::{ApiError, ApiResult},
+    auth::{self, Scope, Token},
+    AppState
+};
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The body of the requests creating a token.
+///
+/// - `name` - A unique name, e.g. the client using the token.
+/// - `scopes` - What the token allows: `read`, `annotate` and/or `admin`.
+/// - `expires_in_days` - The validity of the token. It never expires
+///   if omitted.
+#[derive(Debug, Deserialize)]
+pub struct NewToken {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_in_days: Option<u32>
+}
+
+/// A newly created token, with the secret to pass in the `Authorization`
+/// header as `Bearer <secret>`. The secret can't be retrieved later on.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    pub token: Token,
+
+    pub secret: String
+}
+
+/// Lists the tokens, sorted by name.
+pub async fn list_tokens(State(state): State<Arc<AppState>>) -> ApiResult<Json<Vec<Token>>> {
+    Ok(Json(auth::list(&state.pool).await?))
+}
+
+/// Creates a token.
+///
+/// Responds with `409 Conflict` if a token with the same name exists.
+pub async fn create_token(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<NewToken>
+) -> ApiResult<(StatusCode, Json<CreatedToken>)> {
+    auth::check(&request.name, &request.scopes)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST).with_msg(err.to_string()))?;
+    if auth::get(&state.pool, &request.name).await?.is_some() {
+        let msg = format!("token {} already exists", request.name);
+        return Err(ApiError::new(StatusCode::CONFLICT).with_msg(msg));
+    }
+
+    let expires_at = request.expires_in_days
+        .map(|days| auth::now() + days as i64 * SECONDS_PER_DAY);
+    let (token, secret) = auth::create(&state.pool, &request.name, &request.scopes, expires_at).await?;
+
+    Ok((StatusCode::CREATED, Json(CreatedToken { token, secret })))
+}
+
+/// Revokes the token `id`, the requests using it are refused from then on.
+pub async fn revoke_token(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>
+) -> ApiResult<StatusCode> {
+    if !auth::revoke(&state.pool, &id).await? {
+        return Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(format!("token {id} doesn't exist")));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,90 @@
+use super::CreatedToken;
+use crate::{
+    auth::{self, Scope, Token},
+    test_utils::{bearer, call, make_pool, read_json},
+    AppConf,
+    AppState
+};
+
+use axum::{
+    http::{Method, StatusCode},
+    response::Response,
+    Router
+};
+use serde_json::json;
+
+/// Makes the application requiring authentication, with an admin token.
+async fn make_app() -> (Router, String) {
+    let pool = make_pool().await;
+    let (_, secret) = auth::create(&pool, "admin", &[Scope::Admin], None).await.unwrap();
+
+    (crate::app(AppState::new(AppConf::default(), pool)), secret)
+}
+
+async fn send(app: &Router, method: Method, uri: &str, secret: &str, body: Option<serde_json::Value>) -> Response {
+    call(app, method, uri, bearer(secret), body).await
+}
+
+#[tokio::test]
+async fn create_token_test() {
+    // a token is created with its scopes, its secret is only returned once
+    let (app, admin) = make_app().await;
+    let body = json!({"name": "frontend", "scopes": ["read"], "expires_in_days": 30});
+
+    let mut response = send(&app, Method::POST, "/tokens", &admin, Some(body.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: CreatedToken = read_json(&mut response).await;
+    assert_eq!(created.token.name, "frontend");
+    assert_eq!(created.token.scopes, vec![Scope::Read]);
+    let validity = created.token.expires_at.unwrap() - created.token.created_at;
+    assert_eq!(validity, 30 * 24 * 60 * 60);
+
+    let response = send(&app, Method::GET, "/data", &created.secret, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(&app, Method::GET, "/tokens", &created.secret, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let mut response = send(&app, Method::GET, "/tokens", &admin, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed: Vec<serde_json::Value> = read_json(&mut response).await;
+    assert_eq!(listed.len(), 2);
+    assert!(listed.iter().all(|token| token.get("secret").is_none() && token.get("hash").is_none()));
+
+    let response = send(&app, Method::POST, "/tokens", &admin, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn invalid_token_test() {
+    let (app, admin) = make_app().await;
+
+    let body = json!({"name": "frontend", "scopes": []});
+    let response = send(&app, Method::POST, "/tokens", &admin, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let body = json!({"name": "frontend", "scopes": ["write"]});
+    let response = send(&app, Method::POST, "/tokens", &admin, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn revoke_token_test() {
+    // revoked tokens are refused
+    let (app, admin) = make_app().await;
+    let body = json!({"name": "scanner", "scopes": ["admin"]});
+    let mut response = send(&app, Method::POST, "/tokens", &admin, Some(body)).await;
+    let created: CreatedToken = read_json(&mut response).await;
+
+    let uri = format!("/tokens/{}", created.token.id);
+    let response = send(&app, Method::DELETE, &uri, &admin, None).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send(&app, Method::DELETE, &uri, &admin, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = send(&app, Method::GET, "/data", &created.secret, None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut response = send(&app, Method::GET, "/tokens", &admin, None).await;
+    let listed: Vec<Token> = read_json(&mut response).await;
+    assert_eq!(listed.iter().map(|token| token.name.as_str()).collect::<Vec<_>>(), vec!["admin"]);
+}
@@ -0,0 +1,86 @@
+use crate::{
+    acl::Permission,
+    api::{
+        error::{ApiError, ApiResult},
+        subpath
+    },
+    trash::{self, TrashEntry},
+    AppState,
+    Library
+};
+use super::ops::Moved;
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode
+};
+use std::sync::Arc;
+use tokio::fs;
+use uuid::Uuid;
+
+/// Lists the entries of the trash of the library, the most recently
+/// deleted first. The entries deleted from hidden paths aren't listed.
+pub async fn list_trash(
+    State(state): State<Arc<AppState>>,
+    library: Library
+) -> ApiResult<Json<Vec<TrashEntry>>> {
+    let entries = trash::list(&state.pool, &library).await?
+        .into_iter()
+        .filter(|entry| library.access.allows(&entry.original_path(), Permission::Read))
+        .collect();
+
+    Ok(Json(entries))
+}
+
+/// Restores the trash entry `id` to the path it has been deleted from.
+///
+/// Responds with `409 Conflict` if a file or folder has been created at
+/// that path in the meanwhile.
+pub async fn restore_trashed(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    Path((_, id)): Path<(String, String)>
+) -> ApiResult<Json<Moved>> {
+    super::ensure_writable(&library)?;
+    let entry = find(&state, &library, &id).await?;
+    let target = std::path::Path::new(&library.conf.root).join(entry.original_path());
+    if fs::symlink_metadata(&target).await.is_ok() {
+        let msg = format!("{} already exists", entry.path);
+        return Err(ApiError::new(StatusCode::CONFLICT).with_msg(msg));
+    }
+
+    let restored = trash::restore(&state.pool, &library, &entry).await?;
+    let relative = restored.strip_prefix(&library.conf.root).unwrap_or(&restored);
+    Ok(Json(Moved { path: subpath::encode_path(relative) }))
+}
+
+/// Removes the trash entry `id` for good.
+pub async fn purge_trashed(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    Path((_, id)): Path<(String, String)>
+) -> ApiResult<StatusCode> {
+    super::ensure_writable(&library)?;
+    let entry = find(&state, &library, &id).await?;
+    trash::purge(&state.pool, &library, &entry.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Finds the trash entry `id`, which the request must be allowed to write
+/// to the path it has been deleted from.
+async fn find(state: &AppState, library: &Library, id: &str) -> ApiResult<TrashEntry> {
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND)
+        .with_msg(format!("trash entry {id} doesn't exist"));
+
+    // The id ends up in a path, don't even look up anything else
+    Uuid::parse_str(id).map_err(|_| not_found())?;
+    let entry = trash::get(&state.pool, library, id).await?
+        .filter(|entry| library.access.allows(&entry.original_path(), Permission::Read))
+        .ok_or_else(not_found)?;
+    super::ensure_allowed(library, &entry.original_path(), Permission::Write)?;
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,161 @@
+use crate::{
+    AppState,
+    index,
+    test_utils::{self, library_conf, make_conf, make_pool, make_root, read_body},
+    trash::TrashEntry,
+    LibraryConf,
+    DEFAULT_LIBRARY
+};
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    response::Response
+};
+use sqlx::SqlitePool;
+use std::{fs, path::PathBuf, sync::Arc};
+
+/// Creates a root folder with a tagged file in `folder`, and indexes it.
+async fn make_state(name: &str, trash: &str) -> (Arc<AppState>, PathBuf) {
+    let root = make_root(&format!("trash-{name}"));
+    fs::create_dir_all(root.join("folder")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("folder/penguins.jpg")).unwrap();
+
+    let library = LibraryConf { trash: trash.to_string(), ..library_conf(&root) };
+    let pool = make_pool().await;
+    let exclusions = library.exclusions().unwrap();
+    index::index_folder(&pool, DEFAULT_LIBRARY, &root, &exclusions, &root).await.unwrap();
+    let conf = make_conf(library);
+    sqlx::query("INSERT INTO tags (file_id, tag) SELECT id, 'penguins' FROM files")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    (Arc::new(AppState::new(conf, pool)), root)
+}
+
+/// Sends a request without body to `uri` through the application.
+async fn send(state: &Arc<AppState>, method: Method, uri: &str) -> Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    test_utils::send(state, request).await
+}
+
+async fn soft_delete(state: &Arc<AppState>, path: &str) -> TrashEntry {
+    let mut response = send(state, Method::DELETE, &format!("/data/{DEFAULT_LIBRARY}/{path}")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    serde_json::from_slice(&read_body(&mut response).await).unwrap()
+}
+
+async fn list_trash(state: &Arc<AppState>) -> Vec<TrashEntry> {
+    let mut response = send(state, Method::GET, &format!("/trash/{DEFAULT_LIBRARY}")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    serde_json::from_slice(&read_body(&mut response).await).unwrap()
+}
+
+async fn restore(state: &Arc<AppState>, id: &str) -> Response {
+    send(state, Method::POST, &format!("/trash/{DEFAULT_LIBRARY}/{id}/restore")).await
+}
+
+async fn purge(state: &Arc<AppState>, id: &str) -> Response {
+    send(state, Method::DELETE, &format!("/trash/{DEFAULT_LIBRARY}/{id}")).await
+}
+
+async fn tagged(pool: &SqlitePool) -> Vec<(String, Option<String>)> {
+    sqlx::query_as(
+            "SELECT files.relative_path, files.trash_id FROM files
+            JOIN tags ON tags.file_id = files.id"
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn delete_and_restore_test() {
+    // deleted folders are moved to the trash with their metadata,
+    // and can be restored
+    let (state, root) = make_state("restore", ".trash").await;
+
+    let entry = soft_delete(&state, "folder").await;
+    assert_eq!(entry.path, "folder");
+    assert!(entry.is_dir);
+    assert!(entry.expires_at.is_some());
+    assert!(!root.join("folder").exists());
+    assert!(root.join(".trash").join(&entry.id).join("folder/penguins.jpg").is_file());
+    assert_eq!(tagged(&state.pool).await, vec![
+        ("folder/penguins.jpg".to_string(), Some(entry.id.clone())),
+    ]);
+
+    let listed = list_trash(&state).await;
+    let ids: Vec<_> = listed.iter().map(|entry| entry.id.clone()).collect();
+    assert_eq!(ids, vec![entry.id.clone()]);
+    let id = entry.id;
+
+    let mut response = restore(&state, &id).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let restored: serde_json::Value = serde_json::from_slice(&read_body(&mut response).await).unwrap();
+    assert_eq!(restored["path"], "folder");
+    assert!(root.join("folder/penguins.jpg").is_file());
+    assert!(!root.join(".trash").join(&id).exists());
+    assert_eq!(tagged(&state.pool).await, vec![("folder/penguins.jpg".to_string(), None)]);
+    assert!(list_trash(&state).await.is_empty());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn restore_conflict_test() {
+    // a file created in the meanwhile is not overwritten by a restore
+    let (state, root) = make_state("conflict", ".trash").await;
+
+    let entry = soft_delete(&state, "folder/penguins.jpg").await;
+    fs::write(root.join("folder/penguins.jpg"), b"new").unwrap();
+
+    let response = restore(&state, &entry.id).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(fs::read(root.join("folder/penguins.jpg")).unwrap(), b"new");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn purge_test() {
+    // purging an entry removes it and its metadata for good
+    let (state, root) = make_state("purge", ".trash").await;
+
+    let entry = soft_delete(&state, "folder").await;
+    let response = purge(&state, &entry.id).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!root.join(".trash").join(&entry.id).exists());
+    assert!(tagged(&state.pool).await.is_empty());
+
+    for id in [entry.id.as_str(), "..", "%2E%2E"] {
+        let response = purge(&state, id).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    assert!(root.exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn visible_trash_test() {
+    // a trash folder inside the root is neither served nor deletable,
+    // even if it isn't hidden
+    let (state, root) = make_state("visible", "Trash").await;
+    soft_delete(&state, "folder/penguins.jpg").await;
+
+    let mut response = send(&state, Method::GET, &format!("/data/{DEFAULT_LIBRARY}")).await;
+    let body = String::from_utf8(read_body(&mut response).await).unwrap();
+    assert!(body.contains("folder"));
+    assert!(!body.contains("Trash"));
+
+    let response = send(&state, Method::DELETE, &format!("/data/{DEFAULT_LIBRARY}/Trash")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,377 @@
+use crate::{
+    acl::Permission,
+    api::{
+        error::{ApiError, ApiResult},
+        subpath::{self, SubPath}
+    },
+    index,
+    mimetype,
+    AppState,
+    Library
+};
+
+use axum::{
+    extract::{BodyStream, Multipart, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+    response::{IntoResponse, Response}
+};
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc
+};
+use tokio::{fs, io::AsyncWriteExt};
+use uuid::Uuid;
+
+/// The header a client can use to pass the expected SHA-256 checksum
+/// of an uploaded file, as hex string.
+pub const CHECKSUM_HEADER: &str = "x-checksum-sha256";
+
+/// What to do if an uploaded file already exists.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Fail with a `409 Conflict`.
+    #[default]
+    Reject,
+
+    /// Store the file with a new name, e.g. `photo (1).jpg`.
+    Rename,
+
+    /// Replace the existing file.
+    Overwrite
+}
+
+/// Query parameters for the upload endpoints.
+///
+/// - `on_conflict` - What to do if the file already exists: `reject`
+///   (default), `rename` or `overwrite`.
+/// - `checksum` - The expected SHA-256 checksum of the uploaded file.
+///   Only for raw uploads, for multipart uploads the checksum of each file
+///   can be passed in the `X-Checksum-Sha256` header of the part.
+#[derive(Default, Deserialize)]
+pub struct UploadParams {
+    on_conflict: Option<ConflictPolicy>,
+    checksum: Option<String>
+}
+
+/// A file that has been uploaded.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UploadedFile {
+    /// The name of the file, for display purposes.
+    pub filename: String,
+
+    /// The percent-encoded path of the file relative to the root folder.
+    pub path: String,
+
+    pub mimetype: String,
+
+    /// The SHA-256 checksum of the file.
+    pub csum: String
+}
+
+/// Handles the raw upload of a file to the path specified by `subpath`.
+///
+/// The body of the request is the content of the file. Missing parent
+/// folders are created. The expected checksum of the file can be passed in
+/// the `checksum` query parameter or in the `X-Checksum-Sha256` header.
+///
+/// Responds with `201 Created` and the uploaded file as json.
+pub async fn upload(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    subpath: SubPath,
+    params: Query<UploadParams>,
+    headers: HeaderMap,
+    body: BodyStream
+) -> ApiResult<Response> {
+    super::ensure_writable(&library)?;
+    let root = Path::new(&library.conf.root);
+    let filename = subpath.as_path().file_name()
+        .ok_or_else(|| bad_request("Missing filename"))?
+        .to_os_string();
+    let folder = root.join(subpath.as_path())
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| root.to_path_buf());
+
+    // The existing part of the folder must be inside the root folder, and
+    // still be once the missing part is created
+    check_excluded(&library, &folder.join(&filename))?;
+    let mut existing = folder.as_path();
+    while fs::metadata(existing).await.is_err() && existing != root {
+        existing = existing.parent().unwrap_or(root);
+    }
+    check_resolved(&library, existing, &filename).await?;
+    fs::create_dir_all(&folder).await?;
+    check_resolved(&library, &folder, &filename).await?;
+
+    let expected = params.checksum.clone()
+        .or_else(|| header_checksum(&headers));
+    let policy = params.on_conflict.unwrap_or_default();
+    let uploaded = store(&state, &library, &folder, &filename, body, expected, policy).await?;
+
+    Ok((StatusCode::CREATED, Json(uploaded)).into_response())
+}
+
+/// Handles the multipart upload of files into the folder specified by
+/// `subpath`, which must exist.
+///
+/// Every part with a filename is stored in the folder, parts without
+/// filename are ignored. The expected checksum of a file can be passed in
+/// the `X-Checksum-Sha256` header of its part.
+///
+/// Responds with `201 Created` and the list of uploaded files as json.
+/// If one of the files can't be stored, the files uploaded before it
+/// are kept.
+pub async fn upload_multipart(
+    State(state): State<Arc<AppState>>,
+    library: Library,
+    subpath: SubPath,
+    params: Query<UploadParams>,
+    mut multipart: Multipart
+) -> ApiResult<Response> {
+    super::ensure_writable(&library)?;
+    let folder = Path::new(&library.conf.root).join(subpath.as_path());
+    if !fs::metadata(&folder).await.map(|m| m.is_dir()).unwrap_or(false) {
+        let msg = format!("folder {} doesn't exist", subpath.to_string_lossy());
+        return Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(msg));
+    }
+
+    let policy = params.on_conflict.unwrap_or_default();
+    let mut uploaded = vec![];
+    while let Some(field) = multipart.next_field().await.map_err(bad_multipart)? {
+        let filename = match field.file_name() {
+            Some(filename) => sanitize_filename(filename)?,
+            None => continue
+        };
+        check_excluded(&library, &folder.join(&filename))?;
+        check_resolved(&library, &folder, &filename).await?;
+
+        let expected = header_checksum(field.headers());
+        uploaded.push(store(&state, &library, &folder, &filename, field, expected, policy).await?);
+    }
+
+    Ok((StatusCode::CREATED, Json(uploaded)).into_response())
+}
+
+/// Streams `body` to a temporary file in `folder`, verifies its checksum
+/// and moves it in place as `filename`, then registers it in the index.
+async fn store<S, E>(
+    state: &AppState,
+    library: &Library,
+    folder: &Path,
+    filename: &OsStr,
+    body: S,
+    expected: Option<String>,
+    policy: ConflictPolicy
+) -> ApiResult<UploadedFile>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<anyhow::Error>
+{
+    // Temporary files are hidden by the default exclude list
+    let temp = folder.join(format!(".fotos-upload-{}", Uuid::new_v4()));
+    let csum = match write_temp(&temp, body).await {
+        Ok(csum) => csum,
+        Err(err) => {
+            let _ = fs::remove_file(&temp).await;
+            return Err(err);
+        }
+    };
+
+    if let Some(expected) = expected {
+        if !expected.eq_ignore_ascii_case(&csum) {
+            let _ = fs::remove_file(&temp).await;
+            let msg = format!("Checksum mismatch: expected {expected}, got {csum}");
+            return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY).with_msg(msg));
+        }
+    }
+
+    let target = match move_in_place(&temp, folder, filename, policy).await {
+        Ok(target) => target,
+        Err(err) => {
+            let _ = fs::remove_file(&temp).await;
+            return Err(err);
+        }
+    };
+
+    let root = Path::new(&library.conf.root);
+    index::register_file(&state.pool, &library.name, root, &target, &csum).await?;
+
+    let relative = target.strip_prefix(root).unwrap_or(&target);
+    Ok(UploadedFile {
+        filename: target.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        path: subpath::encode_path(relative),
+        mimetype: mimetype::detect(&target).to_string(),
+        csum
+    })
+}
+
+/// Writes `body` to the file `temp` and returns its SHA-256 checksum.
+async fn write_temp<S, E>(temp: &Path, body: S) -> ApiResult<String>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<anyhow::Error>
+{
+    let mut file = fs::File::create(temp).await?;
+    let mut hasher = Sha256::new();
+
+    let mut body = Box::pin(body);
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|err| ApiError::new(StatusCode::BAD_REQUEST)
+            .with_msg("Cannot read request body".to_string())
+            .with_cause(err)
+        )?;
+        hasher.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Moves `temp` to `folder/filename`, according to the conflict policy.
+/// Returns the path the file has been moved to.
+async fn move_in_place(
+    temp: &Path,
+    folder: &Path,
+    filename: &OsStr,
+    policy: ConflictPolicy
+) -> ApiResult<PathBuf> {
+    let target = folder.join(filename);
+
+    match policy {
+        ConflictPolicy::Overwrite => {
+            fs::rename(temp, &target).await?;
+            Ok(target)
+        },
+        ConflictPolicy::Reject => {
+            link_no_clobber(temp, &target).await?
+                .then_some(target)
+                .ok_or_else(|| {
+                    let msg = format!("{} already exists", filename.to_string_lossy());
+                    ApiError::new(StatusCode::CONFLICT).with_msg(msg)
+                })
+        },
+        ConflictPolicy::Rename => {
+            for counter in 0.. {
+                let candidate = folder.join(numbered_filename(filename, counter));
+                if link_no_clobber(temp, &candidate).await? {
+                    return Ok(candidate);
+                }
+            }
+            unreachable!()
+        }
+    }
+}
+
+/// Atomically moves `temp` to `target` unless `target` already exists.
+/// Returns `false` if `target` exists.
+async fn link_no_clobber(temp: &Path, target: &Path) -> io::Result<bool> {
+    match fs::hard_link(temp, target).await {
+        Ok(()) => {
+            fs::remove_file(temp).await?;
+            Ok(true)
+        },
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(err) if matches!(err.kind(), io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied) => {
+            rename_no_clobber(temp, target).await
+        },
+        Err(err) => Err(err)
+    }
+}
+
+/// Moves `temp` to `target` unless `target` already exists, on file systems
+/// without hard links: an empty `target` is created first, so that no other
+/// upload can take its name, then replaced with `temp`.
+/// Returns `false` if `target` exists.
+async fn rename_no_clobber(temp: &Path, target: &Path) -> io::Result<bool> {
+    match fs::OpenOptions::new().write(true).create_new(true).open(target).await {
+        Ok(_) => (),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(err) => return Err(err)
+    }
+
+    if let Err(err) = fs::rename(temp, target).await {
+        let _ = fs::remove_file(target).await;
+        return Err(err);
+    }
+    Ok(true)
+}
+
+/// Returns `photo (counter).jpg` for the filename `photo.jpg`,
+/// or the filename itself if `counter` is zero.
+fn numbered_filename(filename: &OsStr, counter: usize) -> OsString {
+    if counter == 0 {
+        return filename.to_os_string();
+    }
+
+    let path = Path::new(filename);
+    let mut numbered = path.file_stem()
+        .unwrap_or(filename)
+        .to_os_string();
+    numbered.push(format!(" ({counter})"));
+    if let Some(extension) = path.extension() {
+        numbered.push(".");
+        numbered.push(extension);
+    }
+
+    numbered
+}
+
+/// Only keeps the last component of the filename sent by the client.
+fn sanitize_filename(filename: &str) -> ApiResult<OsString> {
+    Path::new(filename)
+        .file_name()
+        .map(OsStr::to_os_string)
+        .ok_or_else(|| bad_request(&format!("Invalid filename {filename}")))
+}
+
+/// Refuses uploads to excluded paths, and to paths the access rules don't
+/// allow writing to.
+fn check_excluded(library: &Library, target: &Path) -> ApiResult<()> {
+    let root = Path::new(&library.conf.root);
+    let exclusions = library.exclusions()?;
+    if exclusions.is_path_excluded(root, target) {
+        let msg = format!("{} is excluded", target.to_string_lossy());
+        return Err(ApiError::new(StatusCode::FORBIDDEN).with_msg(msg));
+    }
+
+    super::ensure_allowed(library, target.strip_prefix(root).unwrap_or(target), Permission::Write)
+}
+
+/// Refuses uploads of `filename` to `folder` if, once its symbolic links
+/// are resolved, it lies outside of the root folder, is excluded, or the
+/// access rules don't allow writing to it.
+async fn check_resolved(library: &Library, folder: &Path, filename: &OsStr) -> ApiResult<()> {
+    let relative = super::resolve_folder(library, folder).await?;
+    let root = Path::new(&library.conf.root);
+    check_excluded(library, &root.join(relative).join(filename))
+}
+
+fn header_checksum(headers: &HeaderMap) -> Option<String> {
+    headers.get(CHECKSUM_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+}
+
+fn bad_request(msg: &str) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST).with_msg(msg.to_string())
+}
+
+fn bad_multipart(err: axum::extract::multipart::MultipartError) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST)
+        .with_msg(format!("Invalid multipart request: {err}"))
+}
+
+#[cfg(test)]
+mod tests;
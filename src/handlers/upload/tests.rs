@@ -0,0 +1,239 @@
+use crate::{
+    index,
+    test_utils::{make_root, make_state, read_json, send},
+    AppState,
+    DEFAULT_LIBRARY
+};
+use super::UploadedFile;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    response::Response
+};
+use rstest::*;
+use std::{fs, sync::Arc};
+
+static PENGUINS_CSUM: &str = "382ad1abc24d92d8941a38ca3b8b3a2af9b616d13347f10361c3790d4c78c7e7";
+
+/// Uploads `data/penguins.jpg` to `subpath` of the default library.
+async fn upload(
+    state: &Arc<AppState>,
+    subpath: &str,
+    policy: Option<&str>,
+    checksum: Option<&str>
+) -> Response {
+    let mut params = vec![];
+    if let Some(policy) = policy {
+        params.push(("on_conflict", policy));
+    }
+    if let Some(checksum) = checksum {
+        params.push(("checksum", checksum));
+    }
+    let query = serde_urlencoded::to_string(params).unwrap();
+
+    let request = Request::put(format!("/data/{DEFAULT_LIBRARY}/{subpath}?{query}"))
+        .body(Body::from(fs::read("data/penguins.jpg").unwrap()))
+        .unwrap();
+    send(state, request).await
+}
+
+#[tokio::test]
+async fn raw_upload_test() {
+    // the content of the request is stored in the file and registered
+    // in the index
+    let root = make_root("upload-raw");
+    let state = make_state(&root).await;
+    let pool = state.pool.clone();
+
+    let mut response = upload(&state, "new/penguins.jpg", None, Some(PENGUINS_CSUM)).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let uploaded: UploadedFile = read_json(&mut response).await;
+    assert_eq!(uploaded.path, "new/penguins.jpg");
+    assert_eq!(uploaded.mimetype, "image/jpeg");
+    assert_eq!(uploaded.csum, PENGUINS_CSUM);
+    assert_eq!(fs::read(root.join("new/penguins.jpg")).unwrap(), fs::read("data/penguins.jpg").unwrap());
+
+    let csum: String = sqlx::query_scalar("SELECT csum FROM files WHERE library = 'photos' AND relative_path = 'new/penguins.jpg'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(csum, PENGUINS_CSUM);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[rstest]
+#[case(None, Err(StatusCode::CONFLICT))]
+#[case(Some("reject"), Err(StatusCode::CONFLICT))]
+#[case(Some("rename"), Ok("penguins (1).jpg"))]
+#[case(Some("overwrite"), Ok("penguins.jpg"))]
+#[tokio::test]
+async fn conflict_policy_test(
+    #[case] policy: Option<&str>,
+    #[case] expected: Result<&str, StatusCode>
+) {
+    // if the file already exists, the conflict policy decides what happens
+    let root = make_root(&format!("conflict-{policy:?}"));
+    fs::write(root.join("penguins.jpg"), b"existing").unwrap();
+    let state = make_state(&root).await;
+
+    let mut response = upload(&state, "penguins.jpg", policy, None).await;
+    match expected {
+        Ok(filename) => {
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let uploaded: UploadedFile = read_json(&mut response).await;
+            assert_eq!(uploaded.filename, filename);
+            assert_eq!(fs::read(root.join(filename)).unwrap(), fs::read("data/penguins.jpg").unwrap());
+        },
+        Err(status) => {
+            assert_eq!(response.status(), status);
+            assert_eq!(fs::read(root.join("penguins.jpg")).unwrap(), b"existing");
+        }
+    }
+
+    // no temporary files are left behind
+    assert!(fs::read_dir(&root).unwrap()
+        .all(|entry| !entry.unwrap().file_name().to_string_lossy().starts_with('.')));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn overwrite_checksum_test() {
+    // overwriting an indexed file updates its checksum, even if the size
+    // and the modification time look unchanged
+    let root = make_root("upload-overwrite");
+    let size = fs::metadata("data/penguins.jpg").unwrap().len() as usize;
+    fs::write(root.join("penguins.jpg"), vec![0; size]).unwrap();
+    let state = make_state(&root).await;
+    index::index_file(&state.pool, DEFAULT_LIBRARY, &root, &root.join("penguins.jpg")).await.unwrap();
+
+    let response = upload(&state, "penguins.jpg", Some("overwrite"), None).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let indexed: (String, String) = sqlx::query_as("SELECT csum, mimetype FROM files WHERE relative_path = 'penguins.jpg'")
+        .fetch_one(&state.pool)
+        .await
+        .unwrap();
+    assert_eq!(indexed, (PENGUINS_CSUM.to_string(), "image/jpeg".to_string()));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn checksum_mismatch_test() {
+    // if the checksum doesn't match, the upload is rejected
+    let root = make_root("upload-checksum");
+    let state = make_state(&root).await;
+
+    let response = upload(&state, "penguins.jpg", None, Some("0123")).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[rstest]
+#[case("link/penguins.jpg")]
+#[case("link/new/penguins.jpg")]
+#[tokio::test]
+async fn symlink_escape_test(#[case] subpath: &str) {
+    // symbolic links can't be used to upload files outside of the root
+    // folder, nor to create folders there
+    let root = make_root("upload-symlink");
+    let state = make_state(&root).await;
+    let outside = root.with_extension("outside");
+    fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+    let response = upload(&state, subpath, None, None).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&outside).unwrap();
+}
+
+#[tokio::test]
+async fn rename_no_clobber_test() {
+    // without hard links, the target is reserved before being replaced
+    let root = make_root("upload-no-link");
+    let (first, second) = (root.join("first.tmp"), root.join("second.tmp"));
+    fs::write(&first, "first").unwrap();
+    fs::write(&second, "second").unwrap();
+
+    let target = root.join("photo.jpg");
+    assert!(super::rename_no_clobber(&first, &target).await.unwrap());
+    assert!(!super::rename_no_clobber(&second, &target).await.unwrap());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "first");
+    assert!(!first.exists());
+    assert!(second.exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn multipart_upload_test() {
+    // all the files of a multipart request are stored in the folder
+    let root = make_root("upload-multipart");
+    let state = make_state(&root).await;
+
+    let body = "--XYZ\r\n\
+        Content-Disposition: form-data; name=\"comment\"\r\n\r\n\
+        not a file\r\n\
+        --XYZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        first\r\n\
+        --XYZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"../b.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        second\r\n\
+        --XYZ--\r\n";
+    let request = Request::post(format!("/data/{DEFAULT_LIBRARY}"))
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XYZ")
+        .body(Body::from(body))
+        .unwrap();
+
+    let mut response = send(&state, request).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let uploaded: Vec<UploadedFile> = read_json(&mut response).await;
+    let paths: Vec<&str> = uploaded.iter().map(|file| file.path.as_str()).collect();
+    assert_eq!(paths, vec!["a.txt", "b.txt"]);
+    assert_eq!(fs::read_to_string(root.join("a.txt")).unwrap(), "first");
+    assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "second");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn body_limit_test() {
+    // only the uploads may exceed the default limit of the body size
+    let root = make_root("upload-limit");
+    let state = make_state(&root).await;
+    let large = "x".repeat(3 * 1024 * 1024);
+
+    let body = format!("--XYZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"large.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        {large}\r\n\
+        --XYZ--\r\n");
+    let request = Request::post(format!("/data/{DEFAULT_LIBRARY}"))
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XYZ")
+        .body(Body::from(body))
+        .unwrap();
+    assert_eq!(send(&state, request).await.status(), StatusCode::CREATED);
+    assert_eq!(fs::metadata(root.join("large.txt")).unwrap().len(), large.len() as u64);
+
+    let request = Request::post(format!("/tags/{DEFAULT_LIBRARY}/large.txt"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(format!(r#"{{"tags": ["{large}"]}}"#)))
+        .unwrap();
+    assert_eq!(send(&state, request).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    fs::remove_dir_all(&root).unwrap();
+}
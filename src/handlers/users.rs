@@ -0,0 +1,98 @@
+use crate::{
+    api::error::{ApiError, ApiResult},
+    users::{self, Role, User},
+    AppState
+};
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// The body of the requests creating a user.
+///
+/// - `name` - A unique name, which the tags added by the user are
+///   attributed to.
+/// - `password` - At least 8 characters.
+/// - `role` - `viewer`, `annotator` or `admin`.
+#[derive(Debug, Deserialize)]
+pub struct NewUser {
+    pub name: String,
+    pub password: String,
+    pub role: Role
+}
+
+/// The body of the requests updating a user. The omitted fields are left
+/// unchanged. Changing the password closes the sessions of the user.
+#[derive(Debug, Deserialize)]
+pub struct UserUpdate {
+    pub password: Option<String>,
+    pub role: Option<Role>
+}
+
+/// Lists the users, sorted by name.
+pub async fn list_users(State(state): State<Arc<AppState>>) -> ApiResult<Json<Vec<User>>> {
+    Ok(Json(users::list(&state.pool).await?))
+}
+
+/// Creates a user.
+///
+/// Responds with `409 Conflict` if a user with the same name exists.
+pub async fn create_user(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<NewUser>
+) -> ApiResult<(StatusCode, Json<User>)> {
+    users::check(&request.name, &request.password)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST).with_msg(err.to_string()))?;
+    if users::get(&state.pool, &request.name).await?.is_some() {
+        let msg = format!("user {} already exists", request.name);
+        return Err(ApiError::new(StatusCode::CONFLICT).with_msg(msg));
+    }
+
+    let user = users::create(&state.pool, &request.name, &request.password, request.role).await?;
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+/// Changes the password and/or the role of the user `id`.
+/// Responds with the updated user.
+pub async fn update_user(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<UserUpdate>
+) -> ApiResult<Json<User>> {
+    if let Some(password) = &request.password {
+        users::check_password(password)
+            .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST).with_msg(err.to_string()))?;
+    }
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND).with_msg(format!("user {id} doesn't exist"));
+    let user = users::get(&state.pool, &id).await?
+        .filter(|user| user.id == id)
+        .ok_or_else(not_found)?;
+
+    if let Some(role) = request.role {
+        users::set_role(&state.pool, &user.id, role).await?;
+    }
+    if let Some(password) = &request.password {
+        users::set_password(&state.pool, &user.id, password).await?;
+    }
+
+    Ok(Json(users::get(&state.pool, &user.id).await?.ok_or_else(not_found)?))
+}
+
+/// Removes the user `id` and closes their sessions. The tags they added
+/// stay attributed to them.
+pub async fn remove_user(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>
+) -> ApiResult<StatusCode> {
+    if !users::remove(&state.pool, &id).await? {
+        return Err(ApiError::new(StatusCode::NOT_FOUND).with_msg(format!("user {id} doesn't exist")));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests;
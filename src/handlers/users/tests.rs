@@ -0,0 +1,68 @@
+use crate::{
+    auth::{self, Scope},
+    test_utils::{bearer, call, make_pool, read_json},
+    users::{self, Role, User},
+    AppConf,
+    AppState
+};
+
+use axum::{
+    http::{Method, StatusCode},
+    response::Response,
+    Router
+};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+/// Makes the application requiring authentication, with an admin token.
+async fn make_app() -> (Router, SqlitePool, String) {
+    let pool = make_pool().await;
+    let (_, secret) = auth::create(&pool, "admin", &[Scope::Admin], None).await.unwrap();
+    let app = crate::app(AppState::new(AppConf::default(), pool.clone()));
+
+    (app, pool, secret)
+}
+
+async fn send(app: &Router, method: Method, uri: &str, secret: &str, body: Option<serde_json::Value>) -> Response {
+    call(app, method, uri, bearer(secret), body).await
+}
+
+#[tokio::test]
+async fn manage_users_test() {
+    // users are created, updated and removed by the admins
+    let (app, pool, admin) = make_app().await;
+    let body = json!({"name": "alice", "password": "correct horse", "role": "viewer"});
+
+    let mut response = send(&app, Method::POST, "/users", &admin, Some(body.clone())).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: User = read_json(&mut response).await;
+    assert_eq!(created.role, Role::Viewer);
+
+    let response = send(&app, Method::POST, "/users", &admin, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let body = json!({"name": "bob", "password": "short", "role": "viewer"});
+    let response = send(&app, Method::POST, "/users", &admin, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let uri = format!("/users/{}", created.id);
+    let body = json!({"role": "annotator", "password": "new password"});
+    let mut response = send(&app, Method::PUT, &uri, &admin, Some(body)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let updated: User = read_json(&mut response).await;
+    assert_eq!(updated.role, Role::Annotator);
+    assert!(users::authenticate(&pool, "alice", "new password").await.unwrap().is_some());
+
+    let mut response = send(&app, Method::GET, "/users", &admin, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let listed: Vec<serde_json::Value> = read_json(&mut response).await;
+    assert_eq!(listed.len(), 1);
+    assert!(listed[0].get("password_hash").is_none());
+
+    let response = send(&app, Method::DELETE, &uri, &admin, None).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = send(&app, Method::DELETE, &uri, &admin, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(&app, Method::PUT, &uri, &admin, Some(json!({"role": "admin"}))).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,560 @@
+use crate::{api::subpath, auth, exclude::Exclusions, mimetype};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::{
+    collections::HashSet,
+    io::BufReader,
+    path::Path,
+    time::UNIX_EPOCH
+};
+use tokio::{fs, io::AsyncReadExt};
+use uuid::Uuid;
+
+/// What happened while indexing a folder.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct IndexStats {
+    /// The files that have been added to the index or updated.
+    pub indexed: usize,
+
+    /// The files whose size and modification time didn't change since
+    /// they were last indexed.
+    pub unchanged: usize,
+
+    /// The indexed files that haven't been found. They are kept in the
+    /// index with their tags until they are found again, see
+    /// [`purge_missing`].
+    pub missing: usize,
+
+    /// The files that couldn't be indexed.
+    pub failed: usize
+}
+
+/// What has been found while verifying the indexed files.
+/// The files are listed by their path as stored in the index.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VerifyStats {
+    /// The number of files matching their checksum.
+    pub ok: usize,
+
+    /// The files that have been modified since they were indexed,
+    /// they will be updated by the next indexing.
+    pub changed: Vec<String>,
+
+    /// The files whose content doesn't match their checksum although their
+    /// size and modification time didn't change, e.g. because of a disk
+    /// failure.
+    pub corrupted: Vec<String>,
+
+    /// The files that don't exist anymore, or can't be read.
+    pub missing: Vec<String>
+}
+
+impl VerifyStats {
+    /// Whether the content of all the indexed files is intact.
+    pub fn is_intact(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Indexes all the files beneath `folder`, which must be beneath `root`,
+/// the root folder of the library `library`.
+///
+/// Files and folders matched by `exclusions` (the exclusions of `root`)
+/// are skipped. Files that have been deleted from `folder` are marked as
+/// missing, but nothing is marked if `folder` turns out empty, e.g. because
+/// the disk isn't mounted.
+pub async fn index_folder(
+    pool: &SqlitePool,
+    library: &str,
+    root: &Path,
+    exclusions: &Exclusions,
+    folder: &Path
+) -> anyhow::Result<IndexStats> {
+    let mut stats = IndexStats::default();
+    let mut seen = HashSet::new();
+    let mut is_empty = true;
+
+    let mut stack = vec![(folder.to_path_buf(), exclusions.for_folder(root, folder))];
+    while let Some((current, exclusions)) = stack.pop() {
+        let mut entries = fs::read_dir(&current).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            is_empty = false;
+            let path = entry.path();
+            let is_dir = entry.file_type().await?.is_dir();
+            if exclusions.is_excluded(&path, is_dir) {
+                continue;
+            }
+
+            if is_dir {
+                let exclusions = exclusions.descend(&path);
+                stack.push((path, exclusions));
+                continue;
+            }
+
+            seen.insert(relative_path(root, &path));
+            match index_file(pool, library, root, &path).await {
+                Ok(true) => stats.indexed += 1,
+                Ok(false) => stats.unchanged += 1,
+                Err(err) => {
+                    tracing::warn!("Cannot index {}: {:?}", path.display(), err);
+                    stats.failed += 1;
+                }
+            }
+        }
+    }
+
+    if is_empty {
+        tracing::warn!("{} is empty, the files indexed beneath it aren't marked as missing", folder.display());
+        return Ok(stats);
+    }
+    stats.missing = mark_missing(pool, library, &relative_path(root, folder), &seen).await?;
+    Ok(stats)
+}
+
+/// The size, modification time, missing since and capture date of an
+/// indexed file.
+type IndexedRow = (Option<i64>, Option<i64>, Option<i64>, Option<i64>);
+
+/// Adds the file `filepath` of the library `library` to the index,
+/// or updates it if it has changed since the last time it has been indexed.
+///
+/// Returns `false` if the file was already indexed and didn't change.
+pub async fn index_file(pool: &SqlitePool, library: &str, root: &Path, filepath: &Path) -> anyhow::Result<bool> {
+    let relative = relative_path(root, filepath);
+    let metadata = fs::metadata(filepath).await?;
+    let size = metadata.len() as i64;
+    let mtime = metadata.modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs() as i64;
+
+    let indexed: Option<IndexedRow> = sqlx::query_as(
+            "SELECT size, mtime, missing_since, taken_at FROM files
+            WHERE library = ? AND relative_path = ? AND trash_id IS NULL"
+        )
+        .bind(library)
+        .bind(&relative)
+        .fetch_optional(pool)
+        .await?;
+    if let Some((Some(indexed_size), Some(indexed_mtime), missing_since, taken_at)) = indexed {
+        if (indexed_size, indexed_mtime) == (size, mtime) {
+            // Found again, e.g. once the disk is mounted, or indexed before
+            // the capture dates were
+            if missing_since.is_some() || taken_at.is_none() {
+                let taken_at = match taken_at {
+                    Some(taken_at) => taken_at,
+                    None => read_taken_at(filepath).await
+                };
+                sqlx::query(
+                        "UPDATE files SET missing_since = NULL, taken_at = ?
+                        WHERE library = ? AND relative_path = ? AND trash_id IS NULL"
+                    )
+                    .bind(taken_at)
+                    .bind(library)
+                    .bind(&relative)
+                    .execute(pool)
+                    .await?;
+            }
+            return Ok(false);
+        }
+    }
+
+    let csum = checksum(filepath).await?;
+    register_file(pool, library, root, filepath, &csum).await?;
+    Ok(true)
+}
+
+/// Stores the file `filepath` of the library `library` in the index with the
+/// checksum `csum`, whether it has changed or not. Used when the checksum is
+/// already known, e.g. after an upload replaced the file within the same
+/// second and with the same size.
+pub async fn register_file(pool: &SqlitePool, library: &str, root: &Path, filepath: &Path, csum: &str) -> anyhow::Result<()> {
+    let relative = relative_path(root, filepath);
+    let metadata = fs::metadata(filepath).await?;
+    let size = metadata.len() as i64;
+    let mtime = metadata.modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs() as i64;
+    let mimetype = mimetype::detect(filepath).to_string();
+    let taken_at = read_taken_at(filepath).await;
+
+    sqlx::query(
+            "INSERT INTO files (id, library, relative_path, csum, mimetype, size, mtime, taken_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (library, relative_path) WHERE trash_id IS NULL DO UPDATE SET
+                csum = excluded.csum,
+                mimetype = excluded.mimetype,
+                size = excluded.size,
+                mtime = excluded.mtime,
+                taken_at = excluded.taken_at,
+                missing_since = NULL"
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(library)
+        .bind(&relative)
+        .bind(csum)
+        .bind(&mimetype)
+        .bind(size)
+        .bind(mtime)
+        .bind(taken_at)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Checks the content of the files of the library `library`, whose root
+/// folder is `root`, against the checksums stored in the index.
+/// The index isn't modified.
+pub async fn verify(pool: &SqlitePool, library: &str, root: &Path) -> anyhow::Result<VerifyStats> {
+    let indexed: Vec<(String, String, Option<i64>, Option<i64>)> = sqlx::query_as(
+            "SELECT relative_path, csum, size, mtime FROM files
+            WHERE library = ? AND trash_id IS NULL
+            ORDER BY relative_path"
+        )
+        .bind(library)
+        .fetch_all(pool)
+        .await?;
+
+    let mut stats = VerifyStats::default();
+    for (relative, csum, size, mtime) in indexed {
+        let filepath = root.join(subpath::decode_path(&relative));
+        let (actual, metadata) = match (checksum(&filepath).await, fs::metadata(&filepath).await) {
+            (Ok(actual), Ok(metadata)) => (actual, metadata),
+            _ => {
+                stats.missing.push(relative);
+                continue;
+            }
+        };
+
+        if actual == csum {
+            stats.ok += 1;
+            continue;
+        }
+
+        let current_mtime = metadata.modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_secs() as i64;
+        if size == Some(metadata.len() as i64) && mtime == Some(current_mtime) {
+            stats.corrupted.push(relative);
+        } else {
+            stats.changed.push(relative);
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Computes the SHA-256 checksum of the file `filepath` as hex string.
+pub async fn checksum(filepath: &Path) -> anyhow::Result<String> {
+    let mut file = fs::File::open(filepath).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Reads the capture date from the EXIF data of the image at `filepath`.
+/// The date is returned as a number of the form `YYYYMMDDhhmmss`,
+/// which is enough for comparing dates with each other.
+/// This reads the file, therefore it shouldn't be called from an async
+/// context.
+pub fn capture_date(filepath: &Path) -> Option<i64> {
+    let file = std::fs::File::open(filepath).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+
+    match &field.value {
+        exif::Value::Ascii(values) if !values.is_empty() => {
+            let date = exif::DateTime::from_ascii(&values[0]).ok()?;
+            Some(
+                date.year as i64 * 10_000_000_000
+                + date.month as i64 * 100_000_000
+                + date.day as i64 * 1_000_000
+                + date.hour as i64 * 10_000
+                + date.minute as i64 * 100
+                + date.second as i64
+            )
+        },
+        _ => None
+    }
+}
+
+/// Reads the capture date of `filepath` as stored in the `taken_at`
+/// column: `0` if the file has none.
+async fn read_taken_at(filepath: &Path) -> i64 {
+    let filepath = filepath.to_path_buf();
+    tokio::task::spawn_blocking(move || capture_date(&filepath)).await
+        .ok()
+        .flatten()
+        .unwrap_or(0)
+}
+
+/// Returns the path of `fullpath` relative to `root`, as stored in the
+/// `files` table: percent-encoded with [`subpath::encode_path`], so that
+/// distinct filenames which aren't valid UTF-8 are kept apart.
+pub fn relative_path(root: &Path, fullpath: &Path) -> String {
+    let relative = fullpath.strip_prefix(root).unwrap_or(fullpath);
+    subpath::encode_path(relative)
+}
+
+/// Moves the indexed file `from`, or all the indexed files beneath the
+/// folder `from`, to `to`. Both paths are relative to the root folder
+/// of the library `library`.
+/// Tags are kept, since they refer to the ids of the files.
+pub async fn move_entries(tx: &mut Transaction<'_, Sqlite>, library: &str, from: &str, to: &str) -> anyhow::Result<()> {
+    sqlx::query(
+            "UPDATE files SET relative_path = ?2 || substr(relative_path, length(?1) + 1)
+            WHERE library = ?3 AND trash_id IS NULL
+            AND (relative_path = ?1 OR substr(relative_path, 1, length(?1) + 1) = ?1 || '/')"
+        )
+        .bind(from)
+        .bind(to)
+        .bind(library)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Copies the indexed file `from`, or all the indexed files beneath the
+/// folder `from`, to `to` within the library `library`, together with their
+/// tags.
+/// The copies get new ids, and will be checked again by the next indexing.
+pub async fn copy_entries(tx: &mut Transaction<'_, Sqlite>, library: &str, from: &str, to: &str) -> anyhow::Result<()> {
+    let ids: Vec<String> = sqlx::query_scalar(
+            "SELECT id FROM files
+            WHERE library = ?2 AND trash_id IS NULL
+            AND (relative_path = ?1 OR substr(relative_path, 1, length(?1) + 1) = ?1 || '/')"
+        )
+        .bind(from)
+        .bind(library)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    for id in ids {
+        let copy = Uuid::new_v4().to_string();
+        sqlx::query(
+                "INSERT INTO files (id, library, relative_path, csum, mimetype, size, mtime, taken_at)
+                SELECT ?1, library, ?3 || substr(relative_path, length(?4) + 1), csum, mimetype, size, NULL, taken_at
+                FROM files WHERE id = ?2"
+            )
+            .bind(&copy)
+            .bind(&id)
+            .bind(to)
+            .bind(from)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+                "INSERT INTO tags (file_id, tag, created_by, created_at)
+                SELECT ?, tag, created_by, created_at FROM tags WHERE file_id = ?"
+            )
+            .bind(&copy)
+            .bind(&id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Removes the indexed file `relative`, or all the indexed files beneath
+/// the folder `relative`, of the library `library` from the index, together
+/// with their tags.
+pub async fn remove_entries(tx: &mut Transaction<'_, Sqlite>, library: &str, relative: &str) -> anyhow::Result<()> {
+    let condition = "library = ?2 AND trash_id IS NULL
+        AND (relative_path = ?1 OR substr(relative_path, 1, length(?1) + 1) = ?1 || '/')";
+
+    sqlx::query(&format!("DELETE FROM tags WHERE file_id IN (SELECT id FROM files WHERE {condition})"))
+        .bind(relative)
+        .bind(library)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(&format!("DELETE FROM files WHERE {condition}"))
+        .bind(relative)
+        .bind(library)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Marks the indexed file `relative`, or all the indexed files beneath the
+/// folder `relative`, of the library `library` as moved to the trash entry `trash_id`.
+/// They keep their path and their tags, so that they can be restored.
+pub async fn trash_entries(
+    tx: &mut Transaction<'_, Sqlite>,
+    library: &str,
+    relative: &str,
+    trash_id: &str
+) -> anyhow::Result<()> {
+    sqlx::query(
+            "UPDATE files SET trash_id = ?2
+            WHERE library = ?3 AND trash_id IS NULL
+            AND (relative_path = ?1 OR substr(relative_path, 1, length(?1) + 1) = ?1 || '/')"
+        )
+        .bind(relative)
+        .bind(trash_id)
+        .bind(library)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Puts the indexed files of the trash entry `trash_id` back in place.
+/// Files indexed at the same paths in the meanwhile are replaced.
+pub async fn restore_entries(
+    tx: &mut Transaction<'_, Sqlite>,
+    library: &str,
+    relative: &str,
+    trash_id: &str
+) -> anyhow::Result<()> {
+    remove_entries(tx, library, relative).await?;
+    sqlx::query("UPDATE files SET trash_id = NULL WHERE trash_id = ?")
+        .bind(trash_id)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Removes the indexed files of the trash entry `trash_id` from the index,
+/// together with their tags.
+pub async fn purge_entries(tx: &mut Transaction<'_, Sqlite>, trash_id: &str) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM tags WHERE file_id IN (SELECT id FROM files WHERE trash_id = ?)")
+        .bind(trash_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM files WHERE trash_id = ?")
+        .bind(trash_id)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Marks as missing the files of the library `library` beneath the folder
+/// `relative` that are not in `seen`, keeping their tags. Returns the number
+/// of missing files.
+///
+/// A missing file whose content is found in an untagged file of `seen` is
+/// taken as moved outside of the application: the tags are moved to that
+/// file instead.
+async fn mark_missing(
+    pool: &SqlitePool,
+    library: &str,
+    relative: &str,
+    seen: &HashSet<String>
+) -> anyhow::Result<usize> {
+    let prefix = if relative.is_empty() {
+        String::new()
+    } else {
+        format!("{relative}/")
+    };
+
+    let indexed: Vec<(String, String)> = sqlx::query_as(
+            "SELECT id, relative_path FROM files
+            WHERE library = ?2 AND trash_id IS NULL
+            AND substr(relative_path, 1, length(?1)) = ?1"
+        )
+        .bind(&prefix)
+        .bind(library)
+        .fetch_all(pool)
+        .await?;
+
+    let mut missing = 0;
+    let mut tx = pool.begin().await?;
+    for (id, path) in indexed {
+        if seen.contains(&path) {
+            continue;
+        }
+
+        let moved: Vec<(String, String)> = sqlx::query_as(
+                "SELECT moved.id, moved.relative_path FROM files AS missing
+                JOIN files AS moved ON moved.library = missing.library AND moved.csum = missing.csum
+                WHERE missing.id = ? AND moved.id != missing.id
+                AND moved.trash_id IS NULL AND moved.missing_since IS NULL
+                AND NOT EXISTS (SELECT 1 FROM tags WHERE tags.file_id = moved.id)"
+            )
+            .bind(&id)
+            .fetch_all(&mut tx)
+            .await?;
+        if let Some((moved_id, _)) = moved.iter().find(|(_, path)| seen.contains(path)) {
+            sqlx::query("UPDATE tags SET file_id = ? WHERE file_id = ?")
+                .bind(moved_id)
+                .bind(&id)
+                .execute(&mut tx)
+                .await?;
+            sqlx::query("DELETE FROM files WHERE id = ?")
+                .bind(&id)
+                .execute(&mut tx)
+                .await?;
+            continue;
+        }
+
+        sqlx::query("UPDATE files SET missing_since = COALESCE(missing_since, ?) WHERE id = ?")
+            .bind(auth::now())
+            .bind(&id)
+            .execute(&mut tx)
+            .await?;
+        missing += 1;
+    }
+    tx.commit().await?;
+
+    Ok(missing)
+}
+
+/// Removes from the index the missing files of the library `library`,
+/// together with their tags. Returns the number of removed files.
+pub async fn purge_missing(pool: &SqlitePool, library: &str) -> anyhow::Result<u64> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+            "DELETE FROM tags WHERE file_id IN (
+                SELECT id FROM files WHERE library = ? AND trash_id IS NULL AND missing_since IS NOT NULL
+            )"
+        )
+        .bind(library)
+        .execute(&mut tx)
+        .await?;
+    let removed = sqlx::query("DELETE FROM files WHERE library = ? AND trash_id IS NULL AND missing_since IS NOT NULL")
+        .bind(library)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+
+    Ok(removed)
+}
+
+/// Assigns the files and trash entries indexed before libraries existed
+/// to the library `library`. Returns the number of assigned files.
+pub async fn adopt_unassigned(pool: &SqlitePool, library: &str) -> anyhow::Result<u64> {
+    let mut tx = pool.begin().await?;
+    let adopted = sqlx::query("UPDATE files SET library = ? WHERE library = ''")
+        .bind(library)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    sqlx::query("UPDATE trash SET library = ? WHERE library = ''")
+        .bind(library)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(adopted)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,217 @@
+use crate::{exclude::Exclusions, test_utils::make_pool, LibraryConf};
+
+use sqlx::SqlitePool;
+use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt, path::PathBuf};
+
+/// Creates a root folder with two images and a misnamed one.
+fn make_root(name: &str) -> PathBuf {
+    let root = crate::test_utils::make_root(&format!("index-{name}"));
+    fs::create_dir_all(root.join("folder")).unwrap();
+    fs::copy("data/penguins.jpg", root.join("penguins.jpg")).unwrap();
+    fs::copy("data/folder/topolino.png", root.join("folder/topolino.png")).unwrap();
+    // a jpeg with the wrong extension
+    fs::copy("data/apollon.jpg", root.join("folder/apollon.png")).unwrap();
+    fs::write(root.join(".DS_Store"), b"").unwrap();
+    root
+}
+
+async fn indexed_files(pool: &SqlitePool) -> Vec<(String, String)> {
+    sqlx::query_as("SELECT relative_path, mimetype FROM files ORDER BY relative_path")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn index_folder_test() {
+    // the indexer stores all the files beneath the root folder
+    // with their detected mimetype
+    let pool = make_pool().await;
+    let root = make_root("folder");
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+
+    let stats = super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    assert_eq!(stats.indexed, 3);
+
+    let expected = vec![
+        ("folder/apollon.png".to_string(), "image/jpeg".to_string()),
+        ("folder/topolino.png".to_string(), "image/png".to_string()),
+        ("penguins.jpg".to_string(), "image/jpeg".to_string()),
+    ];
+    assert_eq!(indexed_files(&pool).await, expected);
+
+    let csum: String = sqlx::query_scalar("SELECT csum FROM files WHERE relative_path = 'penguins.jpg'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(csum, "382ad1abc24d92d8941a38ca3b8b3a2af9b616d13347f10361c3790d4c78c7e7");
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn non_utf8_filename_test() {
+    // filenames which only differ by their invalid UTF-8 sequences are
+    // indexed separately, by their encoded path
+    let pool = make_pool().await;
+    let root = crate::test_utils::make_root("index-non-utf8");
+    fs::copy("data/penguins.jpg", root.join(OsStr::from_bytes(b"caf\xe9 1.jpg"))).unwrap();
+    fs::copy("data/apollon.jpg", root.join(OsStr::from_bytes(b"caf\xe8 1.jpg"))).unwrap();
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+
+    let stats = super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    assert_eq!(stats.indexed, 2);
+    let paths: Vec<String> = indexed_files(&pool).await.into_iter().map(|(path, _)| path).collect();
+    assert_eq!(paths, vec!["caf%E8%201.jpg", "caf%E9%201.jpg"]);
+
+    let stats = super::verify(&pool, "photos", &root).await.unwrap();
+    assert_eq!(stats.ok, 2);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn reindex_folder_test() {
+    // unchanged files are skipped when indexing again,
+    // deleted files are marked as missing
+    let pool = make_pool().await;
+    let root = make_root("reindex");
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+
+    super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    fs::remove_file(root.join("folder/topolino.png")).unwrap();
+
+    let folder = root.join("folder");
+    let stats = super::index_folder(&pool, "photos", &root, &exclusions, &folder).await.unwrap();
+    assert_eq!(stats, super::IndexStats {
+        indexed: 0,
+        unchanged: 1,
+        missing: 1,
+        failed: 0
+    });
+    assert_eq!(indexed_files(&pool).await.len(), 3);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn missing_files_test() {
+    // the tags of the missing files are kept, even if the whole folder
+    // disappears, until they are purged
+    let pool = make_pool().await;
+    let root = make_root("missing");
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+    super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    sqlx::query("INSERT INTO tags (file_id, tag) SELECT id, 'tagged' FROM files")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let tagged = |pool: SqlitePool| async move {
+        sqlx::query_scalar::<_, String>(
+                "SELECT relative_path FROM files JOIN tags ON tags.file_id = files.id
+                WHERE missing_since IS NULL ORDER BY relative_path"
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+    };
+
+    // an empty root folder, e.g. an unmounted disk, isn't taken for deletions
+    let moved = root.with_extension("moved");
+    let _ = fs::remove_dir_all(&moved);
+    fs::rename(&root, &moved).unwrap();
+    fs::create_dir(&root).unwrap();
+    let stats = super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    assert_eq!(stats.missing, 0);
+    fs::remove_dir(&root).unwrap();
+    fs::rename(&moved, &root).unwrap();
+
+    // a file renamed outside of the application gets back its tags,
+    // a deleted one is kept with its tags
+    fs::rename(root.join("penguins.jpg"), root.join("pinguini.jpg")).unwrap();
+    fs::remove_file(root.join("folder/topolino.png")).unwrap();
+    let stats = super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    assert_eq!((stats.indexed, stats.missing), (1, 1));
+    assert_eq!(tagged(pool.clone()).await, vec!["folder/apollon.png", "pinguini.jpg"]);
+
+    fs::copy("data/folder/topolino.png", root.join("folder/topolino.png")).unwrap();
+    super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    assert_eq!(tagged(pool.clone()).await, vec!["folder/apollon.png", "folder/topolino.png", "pinguini.jpg"]);
+
+    fs::remove_file(root.join("folder/topolino.png")).unwrap();
+    super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    assert_eq!(super::purge_missing(&pool, "photos").await.unwrap(), 1);
+    let tags: i64 = sqlx::query_scalar("SELECT count(*) FROM tags").fetch_one(&pool).await.unwrap();
+    assert_eq!(tags, 2);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn libraries_test() {
+    // the same path can be indexed in several libraries, and indexing
+    // a library doesn't touch the files of the others
+    let pool = make_pool().await;
+    let root = make_root("libraries");
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+
+    super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+    fs::remove_file(root.join("penguins.jpg")).unwrap();
+    let stats = super::index_folder(&pool, "scratch", &root, &exclusions, &root).await.unwrap();
+    assert_eq!(stats.indexed, 2);
+    assert_eq!(stats.missing, 0);
+
+    let count: i64 = sqlx::query_scalar("SELECT count(*) FROM files WHERE library = 'photos'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 3);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn adopt_unassigned_test() {
+    // files indexed before libraries existed are assigned to a library
+    let pool = make_pool().await;
+    sqlx::query("INSERT INTO files (id, relative_path, csum) VALUES ('1', 'penguins.jpg', '')")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert_eq!(super::adopt_unassigned(&pool, "photos").await.unwrap(), 1);
+    let library: String = sqlx::query_scalar("SELECT library FROM files")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(library, "photos");
+}
+
+#[tokio::test]
+async fn verify_test() {
+    // files are checked against their checksums, modified files are told
+    // apart from the corrupted ones
+    let pool = make_pool().await;
+    let root = make_root("verify");
+    let exclusions = Exclusions::new(&root, &LibraryConf::default().exclude).unwrap();
+    super::index_folder(&pool, "photos", &root, &exclusions, &root).await.unwrap();
+
+    fs::remove_file(root.join("folder/apollon.png")).unwrap();
+    fs::write(root.join("penguins.jpg"), b"not penguins").unwrap();
+    // a checksum that doesn't match the unchanged file
+    sqlx::query("UPDATE files SET csum = 'bad' WHERE relative_path = 'folder/topolino.png'")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let stats = super::verify(&pool, "photos", &root).await.unwrap();
+    assert_eq!(stats, super::VerifyStats {
+        ok: 0,
+        changed: vec!["penguins.jpg".to_string()],
+        corrupted: vec!["folder/topolino.png".to_string()],
+        missing: vec!["folder/apollon.png".to_string()]
+    });
+    assert!(!stats.is_intact());
+
+    fs::remove_dir_all(&root).unwrap();
+}
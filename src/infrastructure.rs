@@ -1,14 +1,63 @@
-use sqlx::{migrate::{MigrateDatabase, Migrate}, Pool, Database};
+use crate::{DatabaseConf, Synchronous};
 
-pub async fn ensure_db<A>(url: &str) -> anyhow::Result<bool>
-    where A: MigrateDatabase
-{
-    if !A::database_exists(url).await.unwrap_or(false) {
-        A::create_database(url).await?;
-        Ok(true)
+use serde::Serialize;
+use sqlx::{
+    migrate::{AppliedMigration, MigrateDatabase, Migrate},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    Pool,
+    Database,
+    Sqlite,
+    SqlitePool
+};
+use std::{str::FromStr, time::Duration};
+
+/// Opens the connection pool to the database configured in `conf`,
+/// creating the database if it doesn't exist.
+pub async fn connect(conf: &DatabaseConf) -> anyhow::Result<SqlitePool> {
+    conf.validate()?;
+
+    let journal_mode = if conf.wal {
+        SqliteJournalMode::Wal
     } else {
-        Ok(false)
+        SqliteJournalMode::Delete
+    };
+    let synchronous = match conf.synchronous {
+        Synchronous::Off => SqliteSynchronous::Off,
+        Synchronous::Normal => SqliteSynchronous::Normal,
+        Synchronous::Full => SqliteSynchronous::Full,
+        Synchronous::Extra => SqliteSynchronous::Extra
+    };
+
+    let options = SqliteConnectOptions::from_str(&conf.url)?
+        .create_if_missing(true)
+        .journal_mode(journal_mode)
+        .synchronous(synchronous)
+        .busy_timeout(Duration::from_millis(conf.busy_timeout_ms));
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(conf.max_connections)
+        .connect_with(options)
+        .await?;
+    Ok(pool)
+}
+
+/// Opens a read-only connection pool to the database configured in `conf`,
+/// or returns `None` if the database doesn't exist. Nothing is written to
+/// the database, and it isn't created.
+pub async fn connect_read_only(conf: &DatabaseConf) -> anyhow::Result<Option<SqlitePool>> {
+    conf.validate()?;
+    if !Sqlite::database_exists(&conf.url).await? {
+        return Ok(None);
     }
+
+    let options = SqliteConnectOptions::from_str(&conf.url)?
+        .read_only(true)
+        .busy_timeout(Duration::from_millis(conf.busy_timeout_ms));
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?;
+    Ok(Some(pool))
 }
 
 pub async fn migrate<A, B>(pool: &Pool<A>) -> anyhow::Result<()>
@@ -20,3 +69,62 @@ pub async fn migrate<A, B>(pool: &Pool<A>) -> anyhow::Result<()>
     Ok(())
 }
 
+/// The state of a migration of the database.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+
+    /// Whether the migration has been applied to the database.
+    pub applied: bool,
+
+    /// Whether the migration has been modified since it has been applied.
+    /// A modified migration makes [`migrate`] fail.
+    pub modified: bool
+}
+
+/// Lists the migrations known to the application and whether they have
+/// been applied to the database of `pool`, without applying them nor
+/// writing anything. `None` is a database which doesn't exist yet.
+pub async fn migration_status(pool: Option<&SqlitePool>) -> anyhow::Result<Vec<MigrationStatus>> {
+    let applied = match pool {
+        Some(pool) => applied_migrations(pool).await?,
+        None => vec![]
+    };
+
+    let status = sqlx::migrate!()
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let checksum = applied.iter()
+                .find(|applied| applied.version == migration.version)
+                .map(|applied| &applied.checksum);
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                applied: checksum.is_some(),
+                modified: checksum.map(|checksum| *checksum != migration.checksum).unwrap_or(false)
+            }
+        })
+        .collect();
+    Ok(status)
+}
+
+/// Lists the migrations applied to the database of `pool`, none if the
+/// migrations table doesn't exist yet.
+async fn applied_migrations(pool: &SqlitePool) -> anyhow::Result<Vec<AppliedMigration>> {
+    let has_table: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')"
+        )
+        .fetch_one(pool)
+        .await?;
+    if !has_table {
+        return Ok(vec![]);
+    }
+
+    let mut conn = pool.acquire().await?;
+    Ok(conn.list_applied_migrations().await?)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,112 @@
+use crate::{DatabaseConf, Synchronous};
+
+use rstest::*;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::{env, fs};
+
+#[tokio::test]
+async fn connect_test() {
+    // the pool is created with the configured settings
+    let folder = env::temp_dir().join(format!("fotos-db-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    let conf = DatabaseConf {
+        url: format!("sqlite://{}", folder.join("fotos.db").display()),
+        max_connections: 2,
+        busy_timeout_ms: 1234,
+        wal: true,
+        synchronous: Synchronous::Normal
+    };
+
+    let pool = super::connect(&conf).await.unwrap();
+    assert!(folder.join("fotos.db").is_file());
+
+    let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(journal_mode, "wal");
+    let synchronous: i64 = sqlx::query_scalar("PRAGMA synchronous")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(synchronous, 1);
+    let busy_timeout: i64 = sqlx::query_scalar("PRAGMA busy_timeout")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(busy_timeout, 1234);
+
+    pool.close().await;
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+#[rstest]
+#[case("postgres://localhost/fotos", 5, 5000)]
+#[case("sqlite://fotos.db", 0, 5000)]
+#[case("sqlite::memory:", 5, 5000)]
+#[case("sqlite://fotos.db", 5, 0)]
+fn invalid_conf_test(#[case] url: &str, #[case] max_connections: u32, #[case] busy_timeout_ms: u64) {
+    let conf = DatabaseConf {
+        url: url.to_string(),
+        max_connections,
+        busy_timeout_ms,
+        ..Default::default()
+    };
+    assert!(conf.validate().is_err());
+}
+
+#[tokio::test]
+async fn migration_status_test() {
+    // the migrations are pending until they are applied
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    let status = super::migration_status(Some(&pool)).await.unwrap();
+    assert!(!status.is_empty());
+    assert!(status.iter().all(|migration| !migration.applied));
+
+    super::migrate(&pool).await.unwrap();
+    let status = super::migration_status(Some(&pool)).await.unwrap();
+    assert!(status.iter().all(|migration| migration.applied && !migration.modified));
+    assert_eq!(status[0].description, "initial");
+}
+
+#[tokio::test]
+async fn migration_status_read_only_test() {
+    // the status is read without creating the database nor its migrations table
+    let folder = env::temp_dir().join(format!("fotos-status-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    let path = folder.join("fotos.db");
+    let conf = DatabaseConf {
+        url: format!("sqlite://{}", path.display()),
+        max_connections: 1,
+        busy_timeout_ms: 1000,
+        wal: false,
+        synchronous: Synchronous::Normal
+    };
+
+    let pool = super::connect_read_only(&conf).await.unwrap();
+    assert!(pool.is_none());
+    let status = super::migration_status(None).await.unwrap();
+    assert!(status.iter().all(|migration| !migration.applied));
+    assert!(!path.exists());
+
+    // an empty database has no migrations table
+    super::connect(&conf).await.unwrap().close().await;
+    let pool = super::connect_read_only(&conf).await.unwrap().unwrap();
+    let status = super::migration_status(Some(&pool)).await.unwrap();
+    assert!(status.iter().all(|migration| !migration.applied));
+    let tables: i64 = sqlx::query_scalar("SELECT count(*) FROM sqlite_master WHERE type = 'table'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(tables, 0);
+
+    pool.close().await;
+    fs::remove_dir_all(&folder).unwrap();
+}
@@ -1,19 +1,68 @@
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
 use sqlx::SqlitePool;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf}
+};
 
+pub mod acl;
 pub mod api;
+pub mod auth;
+pub mod cache;
+pub mod config;
+pub mod cors;
+pub mod exclude;
+pub mod export;
+pub mod frontend;
+pub mod fsutil;
 pub mod handlers;
+pub mod index;
 pub mod infrastructure;
+pub mod listen;
+pub mod mimetype;
+pub mod renditions;
+pub mod routes;
+pub mod server;
+pub mod shares;
+pub mod throttle;
+pub mod tls;
+pub mod trash;
+pub mod users;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
+
+pub use routes::{app, app_with};
+
+/// The name of the library configured by default.
+pub const DEFAULT_LIBRARY: &str = "photos";
 
 /// The configuration of the application.
 /// Will be serialized to and deserialized from toml using the `confy` crate.
+/// Missing fields will take their default value.
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConf {
-    /// The root folder of the content that will be served through the server.
-    pub root: String,
+    /// The addresses the server will listen on: TCP addresses
+    /// (e.g. `0.0.0.0:3000` or `[::]:3000`) or Unix domain sockets
+    /// (e.g. `unix:/run/fotos/fotos.sock`). A single address can be given
+    /// as a string. The same application is served on all of them.
+    #[serde(deserialize_with = "one_or_many")]
+    pub connection: Vec<String>,
+
+    /// The permissions of the Unix domain sockets, in octal (e.g. `660`
+    /// lets the group of the server connect, e.g. a reverse proxy).
+    pub socket_mode: String,
+
+    /// The path the application is served under, e.g. `/fotos` to serve the
+    /// libraries at `/fotos/data`. Empty to serve them at the root.
+    /// The urls generated by the server use the `X-Forwarded-Prefix` header
+    /// of the request instead, if set by the reverse proxy.
+    pub base_path: String,
 
-    /// The address the server will listen on (e.g. `0.0.0.0:3000`).
-    pub connection: String,
+    /// How long the running requests and background jobs may take to
+    /// finish on shutdown, in seconds. They are interrupted afterwards.
+    pub drain_timeout_secs: u64,
 
     /// The maximum level used for logging. Can be one of the following
     /// (see the [https://docs.rs/tracing-core/latest/tracing_core/struct.Level.html#implementations](documentation)
@@ -25,20 +74,589 @@ pub struct AppConf {
     /// - `INFO`
     /// - `DEBUG`
     /// - `TRACE`
-    pub max_level: String
+    pub max_level: String,
+
+    /// The folder of the built web frontend, a single-page app served at
+    /// the root, or at `base_path`. The routes of the API take precedence,
+    /// the other paths which aren't files are served `index.html`.
+    /// The frontend isn't served if it isn't set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub static_dir: Option<String>,
+
+    /// Patterns of the files of the frontend whose name changes with their
+    /// content (e.g. `assets/**`), relative to `static_dir`. The clients
+    /// cache them for good, while the other files are revalidated.
+    pub immutable_assets: Vec<String>,
+
+    /// The database the index and the annotations are stored in.
+    pub database: DatabaseConf,
+
+    /// The authentication of the requests.
+    pub auth: AuthConf,
+
+    /// If set the server serves HTTPS instead of HTTP on the TCP addresses
+    /// of `connection`. Unix domain sockets are always served over HTTP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConf>,
+
+    /// The cache of the resized images.
+    pub cache: CacheConf,
+
+    /// The sizes the images may be resized to.
+    pub renditions: RenditionsConf,
+
+    /// The cross-origin requests allowed, e.g. from the web frontend.
+    pub cors: CorsConf,
+
+    /// The libraries served through the server, by name.
+    /// The content of a library is served at `/data/<name>/`, therefore
+    /// names may only contain ASCII letters, digits, `-` and `_`.
+    pub libraries: BTreeMap<String, LibraryConf>
+}
+
+/// The configuration of the database.
+/// Missing fields will take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConf {
+    /// The url of the SQLite database (e.g. `sqlite:///var/lib/fotos/fotos.db`).
+    /// A relative path is relative to the working directory of the process.
+    /// The database is created if it doesn't exist.
+    pub url: String,
+
+    /// The maximum number of connections of the pool.
+    pub max_connections: u32,
+
+    /// How long a connection waits for a locked database before failing,
+    /// in milliseconds.
+    pub busy_timeout_ms: u64,
+
+    /// If set to true the database uses a write-ahead log, which lets
+    /// readers work while the indexer is writing. The journal mode is
+    /// persisted in the database file.
+    pub wal: bool,
+
+    /// How carefully SQLite flushes the writes to disk: `off`, `normal`,
+    /// `full` or `extra` (see the [https://www.sqlite.org/pragma.html#pragma_synchronous](documentation)).
+    pub synchronous: Synchronous
+}
+
+/// The values of the `synchronous` pragma of SQLite.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    #[default]
+    Full,
+    Extra
+}
+
+/// The configuration of the authentication.
+/// Missing fields will take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConf {
+    /// If set to true the requests to the API need a bearer token in the
+    /// `Authorization` header, or the session cookie of a logged in user,
+    /// with the scope needed by the request. Tokens are managed with the
+    /// `token` command or at `/tokens`, users with the `user` command or at
+    /// `/users`. The web frontend is served regardless.
+    pub required: bool,
+
+    /// How long the users stay logged in.
+    pub session_ttl_hours: u64,
+
+    /// If set to true the session cookie is only sent over HTTPS. Only
+    /// disable it when testing over plain HTTP.
+    pub secure_cookie: bool,
+
+    /// Groups of users and tokens (`token:<name>`), by name, which the
+    /// access rules of the libraries can refer to as `@<name>`.
+    pub groups: BTreeMap<String, Vec<String>>
+}
+
+/// The configuration of HTTPS.
+/// Missing fields will take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConf {
+    /// The PEM file of the certificate chain.
+    pub cert: String,
+
+    /// The PEM file of the private key (PKCS#8, RSA or SEC1).
+    pub key: String,
+
+    /// The address of a plain HTTP listener redirecting all the requests
+    /// to HTTPS (e.g. `0.0.0.0:80`). No such listener is started if it
+    /// isn't set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_http: Option<String>,
+
+    /// How often the certificate and the key are checked for changes,
+    /// in seconds. Renewed certificates are used for the new connections
+    /// without restarting. `0` disables reloading.
+    pub reload_interval_secs: u64
+}
+
+/// The configuration of the cache of the resized images.
+/// Missing fields will take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConf {
+    /// The folder the resized images are stored in. The cache is disabled
+    /// if it isn't set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+
+    /// The maximum size of the cache in megabytes, the least recently used
+    /// images are removed first when pruning. `0` doesn't limit the size.
+    pub max_size_mb: u64,
+
+    /// The number of days after which unused images are removed when
+    /// pruning. `0` keeps them regardless of their age.
+    pub max_age_days: u64
+}
+
+/// The configuration of the renditions of the images, which limits how
+/// many different sizes the clients can make the server resize to.
+/// Missing fields will take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenditionsConf {
+    /// If set to true the images can only be resized to a preset, or to
+    /// a size signed with `signing_key`. The original images can still be
+    /// downloaded.
+    pub restricted: bool,
+
+    /// The key of the HMAC-SHA256 signatures of the sizes, passed in the
+    /// `signature` parameter along with `max_width`, `max_height` and
+    /// `thumbnail`. The `sign-rendition` command prints signed parameters.
+    /// No size can be signed if it isn't set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+
+    /// The sizes requested with the `preset` parameter (e.g.
+    /// `?preset=grid`), by name.
+    pub presets: BTreeMap<String, PresetConf>
+}
+
+/// A preset of the renditions of the images.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PresetConf {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+
+    /// If set to true a fast integer algorithm is used for resizing.
+    #[serde(default)]
+    pub thumbnail: bool
+}
+
+/// The configuration of the cross-origin resource sharing (CORS).
+/// Missing fields will take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConf {
+    /// The origins allowed to send requests (e.g. `http://localhost:5173`),
+    /// `*` allows any origin. Cross-origin requests are blocked if empty.
+    pub allowed_origins: Vec<String>,
+
+    /// The methods allowed in cross-origin requests, `*` allows any method.
+    pub allowed_methods: Vec<String>,
+
+    /// The request headers allowed in cross-origin requests, `*` allows
+    /// any header. The defaults include the ones of the authenticated requests
+    /// (`authorization` and `x-csrf-token`).
+    pub allowed_headers: Vec<String>,
+
+    /// The response headers readable by the frontend, e.g. `link` for
+    /// the pagination and `x-truncated` for the folder tree. `*` exposes all
+    /// of them.
+    pub exposed_headers: Vec<String>,
+
+    /// If set to true the requests may include credentials (cookies or
+    /// the `Authorization` header). Wildcards can't be used then.
+    pub allow_credentials: bool,
+
+    /// How long the browsers may cache the answer to a preflight request,
+    /// in seconds.
+    pub max_age_secs: u64
+}
+
+/// The configuration of a library.
+/// Missing fields will take their default value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConf {
+    /// The root folder of the content of the library.
+    pub root: String,
+
+    /// If set to true the content of `root` is never modified: uploads,
+    /// moves, deletions and the trash are disabled. The files can still be
+    /// annotated, since annotations are only stored in the database.
+    pub read_only: bool,
+
+    /// Patterns of files and folders that won't be served, using the same
+    /// syntax as `.gitignore` files (e.g. `.DS_Store` or `@eaDir/`).
+    /// Additional patterns can be specified per folder in `.fotosignore`
+    /// files.
+    pub exclude: Vec<String>,
+
+    /// The folder deleted files and folders are moved to.
+    /// A relative path is relative to `root`, the default `.trash` is hidden
+    /// by the default exclude list. A trash folder inside `root` is never
+    /// served nor indexed.
+    pub trash: String,
+
+    /// The number of days after which deleted files are removed from the
+    /// trash for good. `0` keeps them until they are purged explicitly.
+    pub trash_retention_days: u64,
+
+    /// The rules restricting the access to the folders of the library,
+    /// e.g. hiding a folder from the annotators. The last rule matching
+    /// both a path and the user or the token applies, therefore specific
+    /// rules go after general ones: hiding `family` then allowing
+    /// `family/shared` only shows `family/shared` in `family`.
+    pub access: Vec<AccessRule>
+}
+
+/// An access rule of a library.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccessRule {
+    /// The folder or file the rule applies to, with everything beneath it,
+    /// relative to the root folder. Empty for the whole library.
+    pub path: String,
+
+    /// Who the rule applies to: user names, `token:<name>`, `@<group>`
+    /// for the configured groups and the roles (e.g. `@annotator`), or
+    /// `*` for everyone.
+    pub subjects: Vec<String>,
+
+    /// `none` hides the path, `read`, `annotate` or `write` allow it up to
+    /// what the role of the user or the scope of the token allows.
+    pub permission: acl::Permission
+}
+
+/// A configured library, as addressed by the requests.
+#[derive(Clone, Debug)]
+pub struct Library {
+    pub name: String,
+    pub conf: LibraryConf,
+
+    /// What the request may do in the library, see [`acl::Access`].
+    pub access: acl::Access
+}
+
+impl Library {
+    /// The exclusions of the root folder, see [`LibraryConf::exclusions`],
+    /// also excluding the paths hidden by the access rules.
+    pub fn exclusions(&self) -> anyhow::Result<exclude::Exclusions> {
+        let root = PathBuf::from(&self.conf.root);
+        let access = self.access.clone();
+        Ok(self.conf.exclusions()?.hide(move |path| {
+            path.strip_prefix(&root).is_ok_and(|relative| access.is_hidden(relative))
+        }))
+    }
 }
 
 pub struct AppState {
     pub conf: AppConf,
-    pub pool: SqlitePool
+    pub pool: SqlitePool,
+
+    /// What the share links keep in memory.
+    pub shares: shares::Shares,
+
+    /// The recent wrong passwords of the logins, by user name and by
+    /// client address.
+    pub logins: throttle::Failures
+}
+
+impl AppState {
+    pub fn new(conf: AppConf, pool: SqlitePool) -> Self {
+        Self {
+            conf,
+            pool,
+            shares: shares::Shares::default(),
+            logins: throttle::Failures::default()
+        }
+    }
+}
+
+impl AppConf {
+    /// Returns the library `name`, or `None` if it isn't configured.
+    pub fn library(&self, name: &str) -> Option<Library> {
+        self.libraries.get(name).map(|conf| Library {
+            name: name.to_string(),
+            conf: conf.clone(),
+            access: acl::Access::default()
+        })
+    }
+
+    /// Returns all the configured libraries, sorted by name.
+    pub fn all_libraries(&self) -> Vec<Library> {
+        self.libraries.keys()
+            .filter_map(|name| self.library(name))
+            .collect()
+    }
+
+    /// Returns the addresses the server listens on.
+    pub fn listen_addrs(&self) -> anyhow::Result<Vec<listen::ListenAddr>> {
+        if self.connection.is_empty() {
+            anyhow::bail!("At least one connection address must be set");
+        }
+
+        self.connection.iter()
+            .map(|addr| addr.parse())
+            .collect()
+    }
+
+    /// Returns the permissions of the Unix domain sockets.
+    pub fn socket_mode(&self) -> anyhow::Result<u32> {
+        u32::from_str_radix(&self.socket_mode, 8).ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| anyhow::anyhow!("Invalid socket mode {:?}, expected octal permissions", self.socket_mode))
+    }
+
+    /// Checks that the configuration can be served.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.listen_addrs()?;
+        self.socket_mode()?;
+        if !self.base_path.is_empty() && !api::prefix::is_valid(&self.base_path) {
+            anyhow::bail!("Invalid base path {:?}, expected e.g. \"/fotos\"", self.base_path);
+        }
+        self.database.validate()?;
+        cors::layer(&self.cors)?;
+        self.renditions.validate()?;
+        frontend::glob_set(&self.immutable_assets)?;
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+        }
+
+        for (name, library) in &self.libraries {
+            let valid = !name.is_empty() && name.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                anyhow::bail!("Invalid library name {name:?}");
+            }
+            acl::validate(&library.access, &self.auth.groups)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserializes a string as a list with a single element.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>)
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values
+    })
+}
+
+impl DatabaseConf {
+    /// Checks that the settings can be applied to the pool.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.url.starts_with("sqlite:") {
+            anyhow::bail!("Invalid database url {:?}, only sqlite is supported", self.url);
+        }
+        if self.max_connections == 0 {
+            anyhow::bail!("The database pool needs at least one connection");
+        }
+        // Every connection to an in-memory database opens a new database
+        if self.url.contains(":memory:") && self.max_connections > 1 {
+            anyhow::bail!("An in-memory database can only have one connection");
+        }
+        if self.busy_timeout_ms == 0 {
+            anyhow::bail!("The database busy timeout must be positive");
+        }
+
+        Ok(())
+    }
+}
+
+impl TlsConf {
+    /// Checks that the files and the redirect address are set.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.cert.is_empty() || self.key.is_empty() {
+            anyhow::bail!("The TLS certificate and key files must be set");
+        }
+        if let Some(redirect) = &self.redirect_http {
+            redirect.parse::<std::net::SocketAddr>()
+                .map_err(|err| anyhow::anyhow!("Invalid redirect address {redirect:?}: {err}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RenditionsConf {
+    /// Checks that the presets resize the images and that the signing key
+    /// isn't empty.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.signing_key.as_ref().is_some_and(|key| key.is_empty()) {
+            anyhow::bail!("The signing key of the renditions can't be empty");
+        }
+        for (name, preset) in &self.presets {
+            let sizes = [preset.max_width, preset.max_height];
+            if sizes.iter().all(Option::is_none) || sizes.contains(&Some(0)) {
+                anyhow::bail!("The preset {name:?} must have a positive max_width and/or max_height");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl LibraryConf {
+    /// The trash folder on the local file system.
+    pub fn trash_dir(&self) -> PathBuf {
+        Path::new(&self.root).join(&self.trash)
+    }
+
+    /// The exclusions of the root folder, including the trash folder
+    /// if it is inside the root folder.
+    pub fn exclusions(&self) -> anyhow::Result<exclude::Exclusions> {
+        let mut patterns = self.exclude.clone();
+        if let Ok(relative) = self.trash_dir().strip_prefix(&self.root) {
+            if relative.components().next().is_some() {
+                patterns.push(exclude::anchored_pattern(relative));
+            }
+        }
+
+        exclude::Exclusions::new(Path::new(&self.root), &patterns)
+    }
 }
 
 impl Default for AppConf {
+    fn default() -> Self {
+        Self {
+            connection: vec!["0.0.0.0:3000".to_string()],
+            socket_mode: "660".to_string(),
+            base_path: String::new(),
+            drain_timeout_secs: 30,
+            max_level: "INFO".to_string(),
+            static_dir: None,
+            immutable_assets: vec!["assets/**".to_string()],
+            database: DatabaseConf::default(),
+            auth: AuthConf::default(),
+            tls: None,
+            cache: CacheConf::default(),
+            renditions: RenditionsConf::default(),
+            cors: CorsConf::default(),
+            libraries: BTreeMap::from([
+                (DEFAULT_LIBRARY.to_string(), LibraryConf::default())
+            ])
+        }
+    }
+}
+
+impl Default for DatabaseConf {
+    fn default() -> Self {
+        Self {
+            url: "sqlite://sqlite.db".to_string(),
+            max_connections: 5,
+            busy_timeout_ms: 5000,
+            wal: true,
+            synchronous: Synchronous::default()
+        }
+    }
+}
+
+impl Default for AuthConf {
+    fn default() -> Self {
+        Self {
+            required: true,
+            session_ttl_hours: 7 * 24,
+            secure_cookie: true,
+            groups: BTreeMap::new()
+        }
+    }
+}
+
+impl Default for TlsConf {
+    fn default() -> Self {
+        Self {
+            cert: "cert.pem".to_string(),
+            key: "key.pem".to_string(),
+            redirect_http: None,
+            reload_interval_secs: 60
+        }
+    }
+}
+
+impl Default for CacheConf {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_size_mb: 1024,
+            max_age_days: 30
+        }
+    }
+}
+
+impl Default for RenditionsConf {
+    fn default() -> Self {
+        let preset = |size, thumbnail| PresetConf {
+            max_width: Some(size),
+            max_height: Some(size),
+            thumbnail
+        };
+        Self {
+            restricted: false,
+            signing_key: None,
+            presets: BTreeMap::from([
+                ("thumb".to_string(), preset(200, true)),
+                ("grid".to_string(), preset(480, false)),
+                ("detail".to_string(), preset(1920, false))
+            ])
+        }
+    }
+}
+
+impl Default for CorsConf {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: ["GET", "PUT", "POST", "DELETE"]
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            allowed_headers: vec![
+                "content-type".to_string(),
+                "authorization".to_string(),
+                auth::CSRF_HEADER.to_string()
+            ],
+            exposed_headers: vec!["link".to_string(), "x-truncated".to_string()],
+            allow_credentials: false,
+            max_age_secs: 600
+        }
+    }
+}
+
+impl Default for LibraryConf {
     fn default() -> Self {
         Self {
             root: "./data".to_string(),
-            connection: "0.0.0.0:3000".to_string(),
-            max_level: "INFO".to_string()
+            read_only: false,
+            exclude: [".*", "Thumbs.db", "desktop.ini", "@eaDir"]
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            trash: ".trash".to_string(),
+            trash_retention_days: 30,
+            access: vec![]
         }
     }
 }
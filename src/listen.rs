@@ -0,0 +1,118 @@
+use anyhow::Context;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    fmt,
+    io,
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    str::FromStr
+};
+
+/// The prefix of the addresses of Unix domain sockets.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// An address the server listens on, as configured in `connection`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// A TCP address, e.g. `0.0.0.0:3000` or `[::]:3000`.
+    Tcp(SocketAddr),
+
+    /// The path of a Unix domain socket, e.g. `unix:/run/fotos/fotos.sock`.
+    Unix(PathBuf)
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(UNIX_PREFIX) {
+            Some("") => anyhow::bail!("Invalid listen address {s:?}, the socket path is missing"),
+            Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+            None => s.parse()
+                .map(ListenAddr::Tcp)
+                .map_err(|err| anyhow::anyhow!("Invalid listen address {s:?}: {err}"))
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddr::Unix(path) => write!(f, "{UNIX_PREFIX}{}", path.display())
+        }
+    }
+}
+
+/// A bound listener, see [`Listener::bind`].
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener, PathBuf)
+}
+
+impl Listener {
+    /// Binds `addr`. IPv6 addresses only accept IPv6 connections, so that
+    /// e.g. `0.0.0.0:3000` and `[::]:3000` can be bound together.
+    /// Unix domain sockets get the permissions `socket_mode`;
+    /// a socket left over by a previous run is replaced, any other file
+    /// at the same path is an error.
+    pub fn bind(addr: &ListenAddr, socket_mode: u32) -> anyhow::Result<Self> {
+        let listener = match addr {
+            ListenAddr::Tcp(addr) => bind_tcp(addr).map(Listener::Tcp),
+            ListenAddr::Unix(path) => bind_unix(path, socket_mode)
+        };
+
+        listener.with_context(|| format!("Cannot listen on {addr}"))
+    }
+
+    /// The address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(ListenAddr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(ListenAddr::Unix(path.clone()))
+        }
+    }
+}
+
+fn bind_tcp(addr: &SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Like the standard library, to rebind while old connections linger
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&(*addr).into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, socket_mode: u32) -> io::Result<Listener> {
+    use std::{
+        fs,
+        os::unix::{fs::{FileTypeExt, PermissionsExt}, net::UnixListener}
+    };
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "The path exists and isn't a socket")),
+        Err(_) => ()
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(socket_mode))?;
+    Ok(Listener::Unix(listener, path.to_path_buf()))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &std::path::Path, _socket_mode: u32) -> io::Result<Listener> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets aren't supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,66 @@
+use super::{ListenAddr, Listener};
+
+use rstest::*;
+use std::{env, fs, path::PathBuf};
+
+#[rstest]
+#[case("0.0.0.0:3000", Some(ListenAddr::Tcp(([0, 0, 0, 0], 3000).into())))]
+#[case("[::]:3000", Some(ListenAddr::Tcp("[::]:3000".parse().unwrap())))]
+#[case("unix:/run/fotos/fotos.sock", Some(ListenAddr::Unix(PathBuf::from("/run/fotos/fotos.sock"))))]
+#[case("unix:fotos.sock", Some(ListenAddr::Unix(PathBuf::from("fotos.sock"))))]
+#[case("unix:", None)]
+#[case("localhost:3000", None)]
+#[case("0.0.0.0", None)]
+fn parse_test(#[case] addr: &str, #[case] expected: Option<ListenAddr>) {
+    let parsed = addr.parse::<ListenAddr>().ok();
+    assert_eq!(parsed, expected);
+    if let Some(parsed) = parsed {
+        assert_eq!(parsed.to_string().parse::<ListenAddr>().unwrap(), parsed);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn bind_unix_test() {
+    // the socket gets the configured permissions, a stale socket is
+    // replaced but not another file
+    use std::os::unix::fs::PermissionsExt;
+
+    let folder = env::temp_dir().join(format!("fotos-listen-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    let addr = ListenAddr::Unix(folder.join("fotos.sock"));
+
+    let listener = Listener::bind(&addr, 0o660).unwrap();
+    assert_eq!(listener.local_addr().unwrap(), addr);
+    let mode = fs::metadata(folder.join("fotos.sock")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o660);
+    drop(listener);
+
+    let listener = Listener::bind(&addr, 0o600).unwrap();
+    let mode = fs::metadata(folder.join("fotos.sock")).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    drop(listener);
+
+    fs::write(folder.join("file.sock"), "content").unwrap();
+    assert!(Listener::bind(&ListenAddr::Unix(folder.join("file.sock")), 0o660).is_err());
+    assert_eq!(fs::read_to_string(folder.join("file.sock")).unwrap(), "content");
+
+    fs::remove_dir_all(&folder).unwrap();
+}
+
+#[test]
+fn bind_dual_stack_test() {
+    // IPv4 and IPv6 listeners can share a port
+    let ipv4 = Listener::bind(&"127.0.0.1:0".parse().unwrap(), 0o660).unwrap();
+    let ListenAddr::Tcp(addr) = ipv4.local_addr().unwrap() else {
+        panic!("Not a TCP listener");
+    };
+
+    let ipv6: ListenAddr = format!("[::]:{}", addr.port()).parse().unwrap();
+    match Listener::bind(&ipv6, 0o660) {
+        Ok(listener) => assert_eq!(listener.local_addr().unwrap(), ipv6),
+        // IPv6 may be disabled on the host
+        Err(err) => assert!(err.root_cause().to_string().contains("Address family"), "{err:?}")
+    }
+}
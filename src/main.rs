@@ -1,78 +1,624 @@
-use axum::{
-    routing::get,
-    Router
+use clap::{Parser, Subcommand};
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    io::{self, BufRead, BufWriter},
+    path::{Component, Path, PathBuf},
+    str::FromStr
 };
-use sqlx::{sqlite::SqlitePoolOptions, Sqlite};
-use std::{sync::Arc, str::FromStr};
-use tower_http::trace::{self, TraceLayer};
 use tracing::Level;
 
 use fotos_backend::{
-    handlers,
+    acl::{Access, Permission},
+    api::subpath,
+    auth::{self, Principal, Scope},
+    cache,
+    config,
+    export,
+    frontend,
+    index,
     infrastructure,
+    listen::ListenAddr,
+    renditions::{self, Rendition},
+    server,
+    users::{self, Role},
     AppConf,
-    AppState
+    AppState,
+    Library,
+    DEFAULT_LIBRARY
 };
 
 static APPNAME : &str = "foto_backend";
 
-static DB_URL: &str = "sqlite://sqlite.db";
+/// Serves photo libraries over http.
+///
+/// The configuration is read from the configuration file, then overridden by
+/// the FOTOS_* environment variables (e.g. FOTOS_DATABASE__URL), then by the
+/// options of the fields (e.g. --database-url), then by the --set options.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// The configuration file. Defaults to FOTOS_CONFIG, or to the file in
+    /// the configuration folder of the user, which is created if missing.
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+
+    /// The addresses the server listens on. Can be repeated.
+    #[arg(long, value_name = "ADDRESS", global = true)]
+    connection: Vec<String>,
+
+    /// The path the application is served under.
+    #[arg(long, value_name = "PATH", global = true)]
+    base_path: Option<String>,
+
+    /// The maximum level used for logging.
+    #[arg(long, value_name = "LEVEL", global = true)]
+    max_level: Option<String>,
+
+    /// The folder of the built web frontend.
+    #[arg(long, value_name = "DIR", global = true)]
+    static_dir: Option<String>,
+
+    /// The url of the database.
+    #[arg(long, value_name = "URL", global = true)]
+    database_url: Option<String>,
+
+    /// The root folder of the default library.
+    #[arg(long, value_name = "DIR", global = true)]
+    root: Option<String>,
+
+    /// Overrides a field of the configuration, nested fields are separated
+    /// by dots (e.g. libraries.photos.root=/mnt/photos). Can be repeated.
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    overrides: Vec<String>,
+
+    /// Prints the effective configuration and exits.
+    #[arg(long)]
+    print_config: bool,
+
+    /// The command to run, `serve` if omitted.
+    #[command(subcommand)]
+    command: Option<Command>
+}
+
+impl Cli {
+    /// The overrides of the configuration, the options of the fields first
+    /// so that --set takes precedence.
+    fn overrides(&self) -> Vec<String> {
+        let mut overrides = vec![];
+        if !self.connection.is_empty() {
+            overrides.push(format!("connection={}", toml::Value::from(self.connection.clone())));
+        }
+        let fields = [
+            ("base_path".to_string(), &self.base_path),
+            ("max_level".to_string(), &self.max_level),
+            ("static_dir".to_string(), &self.static_dir),
+            ("database.url".to_string(), &self.database_url),
+            (format!("libraries.{DEFAULT_LIBRARY}.root"), &self.root)
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                overrides.push(format!("{key}={value}"));
+            }
+        }
+
+        overrides.extend(self.overrides.iter().cloned());
+        overrides
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serves the libraries over http.
+    Serve,
+
+    /// Applies the pending migrations to the database.
+    Migrate {
+        /// Lists the migrations and whether they have been applied.
+        #[arg(long)]
+        status: bool,
+
+        /// Lists the pending migrations without applying them.
+        #[arg(long, conflicts_with = "status")]
+        dry_run: bool
+    },
+
+    /// Indexes the libraries, or a folder of a library.
+    Index {
+        /// The library to index. Defaults to all the libraries.
+        #[arg(long)]
+        library: Option<String>,
+
+        /// The folder to index, relative to the root folder of the library.
+        folder: Option<PathBuf>,
+
+        /// Removes the files that aren't found anymore from the index,
+        /// together with their tags, instead of keeping them as missing.
+        #[arg(long)]
+        purge_missing: bool
+    },
+
+    /// Checks the content of the indexed files against their checksums.
+    Verify {
+        /// The library to verify. Defaults to all the libraries.
+        #[arg(long)]
+        library: Option<String>
+    },
+
+    /// Manages the cache of the resized images.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand
+    },
+
+    /// Exports the index and the tags as newline delimited json.
+    Export {
+        /// The library to export. Defaults to all the libraries.
+        #[arg(long)]
+        library: Option<String>,
+
+        /// The file to write to. Defaults to the standard output.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+
+        /// Only exports the files the user can see, according to the access
+        /// rules of the libraries. Defaults to the files everyone can see.
+        #[arg(long, value_name = "NAME")]
+        user: Option<String>
+    },
+
+    /// Manages the tokens of the API.
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand
+    },
+
+    /// Manages the users.
+    User {
+        #[command(subcommand)]
+        command: UserCommand
+    },
+
+    /// Prints the query parameters resizing the images to a size, signed
+    /// with the signing key of the renditions.
+    SignRendition {
+        /// The maximum width of the images.
+        #[arg(long)]
+        max_width: Option<u32>,
+
+        /// The maximum height of the images.
+        #[arg(long)]
+        max_height: Option<u32>,
+
+        /// Uses a fast integer algorithm for resizing.
+        #[arg(long)]
+        thumbnail: bool
+    },
+
+    /// Checks the configuration and exits.
+    CheckConfig
+}
+
+#[derive(Subcommand)]
+enum TokenCommand {
+    /// Creates a token and prints its secret, which can't be retrieved
+    /// later on.
+    Create {
+        /// A unique name, e.g. the client using the token.
+        name: String,
+
+        /// What the token allows: read, annotate or admin. Can be repeated.
+        #[arg(long = "scope", value_name = "SCOPE", required = true)]
+        scopes: Vec<Scope>,
+
+        /// The validity of the token. It never expires if omitted.
+        #[arg(long, value_name = "DAYS")]
+        expires_in_days: Option<u32>
+    },
+
+    /// Lists the tokens.
+    List,
+
+    /// Revokes a token.
+    Revoke {
+        /// The name or the id of the token.
+        token: String
+    }
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Creates a user. The password is read from the standard input.
+    Add {
+        /// A unique name, which the tags added by the user are attributed to.
+        name: String,
+
+        /// What the user is allowed to do: viewer, annotator or admin.
+        #[arg(long, default_value = "viewer")]
+        role: Role
+    },
+
+    /// Lists the users.
+    List,
+
+    /// Changes the role of a user.
+    SetRole {
+        /// The name or the id of the user.
+        user: String,
+
+        /// viewer, annotator or admin.
+        role: Role
+    },
+
+    /// Changes the password of a user and logs them out. The password is
+    /// read from the standard input.
+    Passwd {
+        /// The name or the id of the user.
+        user: String
+    },
+
+    /// Removes a user and logs them out.
+    Remove {
+        /// The name or the id of the user.
+        user: String
+    }
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Removes the expired and the least recently used images.
+    Prune {
+        /// The maximum size of the cache, instead of the configured one.
+        /// `0` doesn't limit the size.
+        #[arg(long, value_name = "MB")]
+        max_size_mb: Option<u64>,
+
+        /// The maximum age of the images, instead of the configured one.
+        /// `0` doesn't limit the age.
+        #[arg(long, value_name = "DAYS")]
+        max_age_days: Option<u64>
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Load configuration from file
-    let cfg_path = confy::get_configuration_file_path(APPNAME, None)
-        .unwrap();
-    let app_conf : AppConf = confy::load(APPNAME, None)?;
+    let cli = Cli::parse();
+
+    // Load configuration from file, environment and command line
+    let cfg_path = match cli.config.clone().or_else(|| env::var_os(config::CONFIG_ENV).map(PathBuf::from)) {
+        Some(path) if !path.is_file() => anyhow::bail!("Configuration file {} not found", path.display()),
+        Some(path) => path,
+        None => confy::get_configuration_file_path(APPNAME, None)?
+    };
+    let app_conf: AppConf = config::load(&cfg_path, env::vars(), &cli.overrides())?;
 
-    // Set up tracing and logging
+    if cli.print_config {
+        print!("{}", toml::to_string_pretty(&app_conf)?);
+        return Ok(());
+    }
+
+    // Set up tracing and logging. The output of the other commands goes
+    // to stdout, so they log to stderr.
+    let command = cli.command.unwrap_or(Command::Serve);
     let max_level: Level = Level::from_str(app_conf.max_level.as_str())
         .unwrap_or(Level::INFO);
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_target(false)
         .with_max_level(max_level)
-        .compact()
-        .init();
+        .compact();
+    if matches!(command, Command::Serve) {
+        subscriber.init();
+    } else {
+        subscriber.with_writer(io::stderr).init();
+    }
 
     tracing::debug!("Loaded config {}", cfg_path.to_str().unwrap_or(""));
+    app_conf.validate()?;
 
-    if infrastructure::ensure_db::<Sqlite>(DB_URL).await? {
-        tracing::debug!("Created database {}", DB_URL);
-    } else {
-        tracing::debug!("Database {} already exists", DB_URL);
+    match command {
+        Command::Serve => {
+            let state = open(app_conf).await?;
+            let no_account = auth::list(&state.pool).await?.is_empty() && users::list(&state.pool).await?.is_empty();
+            if state.conf.auth.required && no_account {
+                tracing::warn!("Authentication is required but there is no token or user yet, create one with the token or the user command");
+            } else if !state.conf.auth.required {
+                tracing::warn!("Authentication isn't required, set auth.required to true once a token or a user has been created");
+            }
+            server::serve(state, server::shutdown_signal()).await
+        },
+        Command::Migrate { status, dry_run } => migrate(&app_conf, status, dry_run).await,
+        Command::Index { library, folder, purge_missing } => {
+            let libraries = select_libraries(&app_conf, library)?;
+            reindex(&open(app_conf).await?, &libraries, folder, purge_missing).await
+        },
+        Command::Verify { library } => {
+            let libraries = select_libraries(&app_conf, library)?;
+            verify(&open(app_conf).await?, &libraries).await
+        },
+        Command::Cache { command: CacheCommand::Prune { max_size_mb, max_age_days } } => {
+            let mut conf = app_conf.cache.clone();
+            conf.max_size_mb = max_size_mb.unwrap_or(conf.max_size_mb);
+            conf.max_age_days = max_age_days.unwrap_or(conf.max_age_days);
+            let stats = cache::prune_configured(&conf).await?;
+            println!("Removed {} images ({} bytes), kept {} images ({} bytes)",
+                stats.removed, stats.freed, stats.kept, stats.size);
+            Ok(())
+        },
+        Command::Export { library, output, user } => {
+            if let Some(library) = &library {
+                select_libraries(&app_conf, Some(library.clone()))?;
+            }
+            let state = open(app_conf).await?;
+            let principal = match user {
+                Some(user) => Some(Principal::User(users::get(&state.pool, &user).await?
+                    .ok_or_else(|| anyhow::anyhow!("Unknown user {user:?}"))?
+                )),
+                None => None
+            };
+            let access: HashMap<String, Access> = state.conf.all_libraries()
+                .into_iter()
+                .map(|library| {
+                    let access = Access::new(&library.conf.access, &state.conf.auth.groups, principal.as_ref());
+                    (library.name, access)
+                })
+                .collect();
+            let visible = |library: &str, path: &str| access.get(library)
+                .is_none_or(|access| access.allows(&subpath::decode_path(path), Permission::Read));
+
+            let count = match output {
+                Some(output) => export::export(&state.pool, library.as_deref(), visible, BufWriter::new(File::create(output)?)).await?,
+                None => export::export(&state.pool, library.as_deref(), visible, io::stdout().lock()).await?
+            };
+            tracing::info!("Exported {} files", count);
+            Ok(())
+        },
+        Command::Token { command } => manage_tokens(&open(app_conf).await?, command).await,
+        Command::User { command } => manage_users(&open(app_conf).await?, command).await,
+        Command::SignRendition { max_width, max_height, thumbnail } => {
+            let rendition = Rendition { max_width, max_height, thumbnail };
+            if !rendition.is_resized() {
+                anyhow::bail!("At least one of --max-width and --max-height must be set");
+            }
+            let key = app_conf.renditions.signing_key.as_ref()
+                .ok_or_else(|| anyhow::anyhow!("The signing key of the renditions isn't set"))?;
+            println!("{}", rendition.signed_query(&renditions::sign(key, &rendition)?));
+            Ok(())
+        },
+        Command::CheckConfig => check_config(&app_conf, &cfg_path)
     }
+}
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(DB_URL)
-        .await?;
+/// Connects to the database and applies the pending migrations.
+async fn open(app_conf: AppConf) -> anyhow::Result<AppState> {
+    let pool = infrastructure::connect(&app_conf.database).await?;
+    tracing::debug!("Connected to database {}", app_conf.database.url);
 
     infrastructure::migrate(&pool).await?;
     tracing::debug!("DB Migration succesful");
 
-    let app_state = AppState {
-        conf: app_conf,
-        pool
+    if let [library] = app_conf.all_libraries().as_slice() {
+        let adopted = index::adopt_unassigned(&pool, &library.name).await?;
+        if adopted > 0 {
+            tracing::info!("Assigned {} indexed files to library {}", adopted, library.name);
+        }
+    }
+
+    Ok(AppState::new(app_conf, pool))
+}
+
+/// Returns the library `name`, or all the libraries if `None`.
+fn select_libraries(app_conf: &AppConf, name: Option<String>) -> anyhow::Result<Vec<Library>> {
+    match name {
+        Some(name) => app_conf.library(&name)
+            .map(|library| vec![library])
+            .ok_or_else(|| anyhow::anyhow!("Unknown library {name:?}")),
+        None => Ok(app_conf.all_libraries())
+    }
+}
+
+async fn migrate(app_conf: &AppConf, status: bool, dry_run: bool) -> anyhow::Result<()> {
+    if status || dry_run {
+        // Only read the database, without creating it if it doesn't exist
+        let pool = infrastructure::connect_read_only(&app_conf.database).await?;
+        let migrations = infrastructure::migration_status(pool.as_ref()).await?;
+        if migrations.iter().all(|migration| !migration.applied) {
+            println!("No migrations applied");
+        }
+
+        for migration in &migrations {
+            if dry_run {
+                if !migration.applied {
+                    println!("Would apply {} {}", migration.version, migration.description);
+                }
+                continue;
+            }
+
+            let state = match (migration.applied, migration.modified) {
+                (true, true) => "modified",
+                (true, false) => "applied",
+                (false, _) => "pending"
+            };
+            println!("{} {} {}", migration.version, state, migration.description);
+        }
+        return Ok(());
+    }
+
+    let pool = infrastructure::connect(&app_conf.database).await?;
+    let pending: Vec<_> = infrastructure::migration_status(Some(&pool)).await?
+        .into_iter()
+        .filter(|migration| !migration.applied)
+        .collect();
+
+    infrastructure::migrate(&pool).await?;
+    for migration in &pending {
+        println!("Applied {} {}", migration.version, migration.description);
+    }
+    Ok(())
+}
+
+async fn reindex(state: &AppState, libraries: &[Library], folder: Option<PathBuf>, purge_missing: bool) -> anyhow::Result<()> {
+    if let Some(folder) = &folder {
+        if libraries.len() != 1 {
+            anyhow::bail!("Specify the library of the folder with --library");
+        }
+        if !folder.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+            anyhow::bail!("The folder must be relative to the root folder of the library");
+        }
+    }
+
+    for library in libraries {
+        let root = Path::new(&library.conf.root);
+        let target = folder.as_ref()
+            .map(|folder| root.join(folder))
+            .unwrap_or_else(|| root.to_path_buf());
+        if !target.is_dir() {
+            anyhow::bail!("Folder {} not found", target.display());
+        }
+
+        let exclusions = library.conf.exclusions()?;
+        let stats = index::index_folder(&state.pool, &library.name, root, &exclusions, &target).await?;
+        println!("{}: indexed {}, unchanged {}, missing {}, failed {}",
+            library.name, stats.indexed, stats.unchanged, stats.missing, stats.failed);
+        if purge_missing {
+            let removed = index::purge_missing(&state.pool, &library.name).await?;
+            println!("{}: removed {} missing files", library.name, removed);
+        }
+    }
+
+    Ok(())
+}
+
+async fn verify(state: &AppState, libraries: &[Library]) -> anyhow::Result<()> {
+    let mut intact = true;
+    for library in libraries {
+        let stats = index::verify(&state.pool, &library.name, Path::new(&library.conf.root)).await?;
+        for path in &stats.corrupted {
+            println!("{}: corrupted {}", library.name, subpath::decode_path(path).display());
+        }
+        for path in &stats.missing {
+            println!("{}: missing {}", library.name, subpath::decode_path(path).display());
+        }
+        for path in &stats.changed {
+            println!("{}: changed {}", library.name, subpath::decode_path(path).display());
+        }
+        println!("{}: ok {}, changed {}, corrupted {}, missing {}",
+            library.name, stats.ok, stats.changed.len(), stats.corrupted.len(), stats.missing.len());
+        intact &= stats.is_intact();
+    }
+
+    if !intact {
+        anyhow::bail!("Some indexed files are corrupted or missing");
+    }
+    Ok(())
+}
+
+async fn manage_tokens(state: &AppState, command: TokenCommand) -> anyhow::Result<()> {
+    match command {
+        TokenCommand::Create { name, scopes, expires_in_days } => {
+            if auth::get(&state.pool, &name).await?.is_some() {
+                anyhow::bail!("Token {name:?} already exists");
+            }
+            let expires_at = expires_in_days.map(|days| auth::now() + days as i64 * 24 * 60 * 60);
+            let (token, secret) = auth::create(&state.pool, &name, &scopes, expires_at).await?;
+            tracing::info!("Created token {} ({})", token.name, token.id);
+            println!("{secret}");
+        },
+        TokenCommand::List => {
+            for token in auth::list(&state.pool).await? {
+                let scopes: Vec<&str> = token.scopes.iter().map(Scope::as_str).collect();
+                let expiry = match token.expires_at {
+                    Some(expires_at) if expires_at <= auth::now() => "expired".to_string(),
+                    Some(expires_at) => format!("expires at {expires_at}"),
+                    None => "never expires".to_string()
+                };
+                println!("{} {} {} {}", token.id, token.name, scopes.join(","), expiry);
+            }
+        },
+        TokenCommand::Revoke { token } => {
+            let found = auth::get(&state.pool, &token).await?
+                .ok_or_else(|| anyhow::anyhow!("Unknown token {token:?}"))?;
+            auth::revoke(&state.pool, &found.id).await?;
+            println!("Revoked token {} ({})", found.name, found.id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn manage_users(state: &AppState, command: UserCommand) -> anyhow::Result<()> {
+    let find = |user: String| async move {
+        users::get(&state.pool, &user).await?
+            .ok_or_else(|| anyhow::anyhow!("Unknown user {user:?}"))
     };
 
-    // Setup routes
-    let addr = app_state.conf.connection.parse()?;
-    let shared_state = Arc::new(app_state);
-    let app = Router::new()
-        .route("/data/*subpath", get(handlers::download))
-        .route("/data", get(handlers::download))
-        .with_state(shared_state)
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
-                .on_response(trace::DefaultOnResponse::new().level(Level::INFO))
-        );
-
-    // Start server
-    tracing::info!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    match command {
+        UserCommand::Add { name, role } => {
+            if users::get(&state.pool, &name).await?.is_some() {
+                anyhow::bail!("User {name:?} already exists");
+            }
+            let password = read_password()?;
+            let user = users::create(&state.pool, &name, &password, role).await?;
+            println!("Created user {} ({})", user.name, user.id);
+        },
+        UserCommand::List => {
+            for user in users::list(&state.pool).await? {
+                println!("{} {} {}", user.id, user.name, user.role);
+            }
+        },
+        UserCommand::SetRole { user, role } => {
+            let found = find(user).await?;
+            users::set_role(&state.pool, &found.id, role).await?;
+            println!("User {} is now {}", found.name, role);
+        },
+        UserCommand::Passwd { user } => {
+            let found = find(user).await?;
+            let password = read_password()?;
+            users::set_password(&state.pool, &found.id, &password).await?;
+            println!("Changed the password of user {}", found.name);
+        },
+        UserCommand::Remove { user } => {
+            let found = find(user).await?;
+            users::remove(&state.pool, &found.id).await?;
+            println!("Removed user {} ({})", found.name, found.id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a password from the first line of the standard input.
+fn read_password() -> anyhow::Result<String> {
+    eprint!("Password: ");
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    users::check_password(&password)?;
+
+    Ok(password)
+}
+
+fn check_config(app_conf: &AppConf, cfg_path: &Path) -> anyhow::Result<()> {
+    for addr in app_conf.listen_addrs()? {
+        if let ListenAddr::Unix(path) = &addr {
+            let folder = path.parent().unwrap_or(Path::new("."));
+            if !folder.as_os_str().is_empty() && !folder.is_dir() {
+                anyhow::bail!("The folder of the socket {addr} doesn't exist");
+            }
+        }
+    }
+    if let Some(dir) = &app_conf.static_dir {
+        if !Path::new(dir).join(frontend::INDEX).is_file() {
+            anyhow::bail!("The frontend folder {dir} doesn't contain {}", frontend::INDEX);
+        }
+    }
+    for library in app_conf.all_libraries() {
+        if !Path::new(&library.conf.root).is_dir() {
+            anyhow::bail!("The root folder {} of library {} doesn't exist", library.conf.root, library.name);
+        }
+        library.conf.exclusions()?;
+    }
 
+    println!("Configuration {} is valid", cfg_path.display());
     Ok(())
 }
@@ -0,0 +1,32 @@
+use image::ImageFormat;
+use mime::Mime;
+use std::path::Path;
+
+/// Detects the mimetype of a file on the local file system.
+///
+/// The type is detected from the content of the file (its magic bytes),
+/// so that files with a wrong or missing extension are served correctly.
+/// If the content doesn't give it away, the type is guessed from the
+/// extension, falling back to `application/octet-stream`.
+pub fn detect(filepath: &Path) -> Mime {
+    let sniffed = infer::get_from_path(filepath)
+        .ok()
+        .flatten()
+        .and_then(|kind| kind.mime_type().parse().ok());
+
+    sniffed.unwrap_or_else(|| guess(filepath))
+}
+
+/// Guesses the mimetype of a file from its extension only, without reading
+/// it, falling back to `application/octet-stream`.
+pub fn guess(filepath: &Path) -> Mime {
+    mime_guess::from_path(filepath)
+        .first()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+/// Returns the image format of a file, based on its detected mimetype.
+/// Returns `None` if the file is not an image supported by the `image` crate.
+pub fn image_format(filepath: &Path) -> Option<ImageFormat> {
+    ImageFormat::from_mime_type(detect(filepath).essence_str())
+}
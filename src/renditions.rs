@@ -0,0 +1,74 @@
+use crate::{auth, PresetConf};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// The size an image is resized to. It fits within `max_width` and
+/// `max_height`, keeping its aspect ratio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rendition {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+
+    /// Whether a fast integer algorithm is used for resizing.
+    pub thumbnail: bool
+}
+
+impl Rendition {
+    /// Checks whether the rendition resizes the images.
+    pub fn is_resized(&self) -> bool {
+        self.max_width.is_some() || self.max_height.is_some()
+    }
+
+    /// The canonical form of the rendition, which identifies the resized
+    /// images in the cache and is signed by [`sign`].
+    pub fn key(&self) -> String {
+        format!("{:?}x{:?} thumbnail={}", self.max_width, self.max_height, self.thumbnail)
+    }
+
+    /// The query parameters requesting the rendition with `signature`.
+    pub fn signed_query(&self, signature: &str) -> String {
+        let mut params = vec![];
+        if let Some(max_width) = self.max_width {
+            params.push(format!("max_width={max_width}"));
+        }
+        if let Some(max_height) = self.max_height {
+            params.push(format!("max_height={max_height}"));
+        }
+        if self.thumbnail {
+            params.push("thumbnail=true".to_string());
+        }
+        params.push(format!("signature={signature}"));
+
+        params.join("&")
+    }
+}
+
+impl From<&PresetConf> for Rendition {
+    fn from(preset: &PresetConf) -> Self {
+        Self {
+            max_width: preset.max_width,
+            max_height: preset.max_height,
+            thumbnail: preset.thumbnail
+        }
+    }
+}
+
+/// Returns the signature of `rendition` with `key`, an HMAC-SHA256 of its
+/// [key](Rendition::key).
+pub fn sign(key: &str, rendition: &Rendition) -> anyhow::Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())?;
+    mac.update(rendition.key().as_bytes());
+
+    Ok(URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+}
+
+/// Checks whether `signature` is the signature of `rendition` with `key`.
+pub fn verify(key: &str, rendition: &Rendition, signature: &str) -> bool {
+    sign(key, rendition)
+        .is_ok_and(|expected| auth::constant_time_eq(expected.as_bytes(), signature.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests;
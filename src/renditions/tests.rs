@@ -0,0 +1,27 @@
+use super::Rendition;
+
+fn rendition(max_width: Option<u32>, max_height: Option<u32>, thumbnail: bool) -> Rendition {
+    Rendition { max_width, max_height, thumbnail }
+}
+
+#[test]
+fn key_test() {
+    // the key identifies the resized images in the cache
+    assert_eq!(rendition(Some(200), None, false).key(), "Some(200)xNone thumbnail=false");
+    assert_eq!(rendition(None, Some(100), true).key(), "NonexSome(100) thumbnail=true");
+}
+
+#[test]
+fn sign_test() {
+    // the signatures depend on the key and on all the parameters
+    let thumb = rendition(Some(200), Some(200), true);
+    let signature = super::sign("secret", &thumb).unwrap();
+    assert!(super::verify("secret", &thumb, &signature));
+    assert!(!super::verify("other", &thumb, &signature));
+    assert!(!super::verify("secret", &rendition(Some(200), Some(200), false), &signature));
+    assert!(!super::verify("secret", &rendition(Some(200), None, true), &signature));
+    assert!(!super::verify("secret", &thumb, ""));
+
+    assert_eq!(thumb.signed_query(&signature), format!("max_width=200&max_height=200&thumbnail=true&signature={signature}"));
+    assert_eq!(rendition(None, Some(100), false).signed_query("abc"), "max_height=100&signature=abc");
+}
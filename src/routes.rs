@@ -0,0 +1,149 @@
+use crate::{auth, cors, frontend::Frontend, handlers, AppState};
+
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post, put},
+    Router
+};
+use std::{path::Path, sync::Arc};
+use tower_http::trace::{self, TraceLayer};
+use tracing::Level;
+
+/// The first segments of the routes of the API, which are never served
+/// by the frontend.
+pub const API_PREFIXES: &[&str] = &["data", "ops", "session", "share", "shares", "tags", "tokens", "trash", "users"];
+
+/// The routes of the application, before the state is attached.
+pub type Routes = Router<Arc<AppState>>;
+
+/// Builds the application serving the libraries configured in `state`,
+/// under the configured base path, next to the web frontend if configured.
+/// The routes need a token or a user with the appropriate scope if
+/// authentication is required.
+/// The cross-origin requests are answered as configured.
+pub fn app(state: impl Into<Arc<AppState>>) -> Router {
+    app_with(state, |routes| routes)
+}
+
+/// Builds the application like [`app`], letting `extend` add routes to the
+/// ones of the application. The extra routes share the state, the base path,
+/// the authentication and the layers of the application.
+///
+/// ```no_run
+/// # use fotos_backend::{app_with, AppState};
+/// # use axum::routing::get;
+/// # fn build(state: AppState) -> axum::Router {
+/// app_with(state, |routes| routes.route("/health", get(|| async { "ok" })))
+/// # }
+/// ```
+pub fn app_with<F>(state: impl Into<Arc<AppState>>, extend: F) -> Router
+where
+    F: FnOnce(Routes) -> Routes
+{
+    let state = state.into();
+    // Only the uploads may be larger than the default limit of the body
+    let routes = Router::new()
+        .route("/data", get(handlers::list_libraries))
+        .route(
+            "/data/:library/*subpath",
+            get(handlers::download)
+                .delete(handlers::delete_entry)
+                .merge(
+                    put(handlers::upload)
+                        .post(handlers::upload_multipart)
+                        .layer(DefaultBodyLimit::disable())
+                )
+        )
+        .route(
+            "/data/:library",
+            get(handlers::download)
+                .merge(post(handlers::upload_multipart).layer(DefaultBodyLimit::disable()))
+        )
+        .route("/ops/:library/move", post(handlers::move_entry))
+        .route("/ops/:library/rename", post(handlers::rename_entry))
+        .route("/ops/:library/copy", post(handlers::copy_entry))
+        .route(
+            "/tags/:library/*subpath",
+            get(handlers::get_tags)
+                .post(handlers::add_tags)
+                .delete(handlers::remove_tags)
+        )
+        .route(
+            "/tags/:library",
+            get(handlers::get_tags)
+                .post(handlers::add_tags)
+                .delete(handlers::remove_tags)
+        )
+        .route(
+            "/shares/:library",
+            get(handlers::list_shares)
+                .post(handlers::create_share)
+        )
+        .route("/shares/:library/:id", delete(handlers::revoke_share))
+        .route("/share/:id/:signature/*subpath", get(handlers::open_share))
+        .route("/share/:id/:signature", get(handlers::open_share))
+        .route("/trash/:library", get(handlers::list_trash))
+        .route("/trash/:library/:id", delete(handlers::purge_trashed))
+        .route("/trash/:library/:id/restore", post(handlers::restore_trashed))
+        .route(
+            "/tokens",
+            get(handlers::list_tokens)
+                .post(handlers::create_token)
+        )
+        .route("/tokens/:id", delete(handlers::revoke_token))
+        .route(
+            "/session",
+            get(handlers::get_session)
+                .post(handlers::login)
+                .delete(handlers::logout)
+        )
+        .route(
+            "/users",
+            get(handlers::list_users)
+                .post(handlers::create_user)
+        )
+        .route(
+            "/users/:id",
+            put(handlers::update_user)
+                .delete(handlers::remove_user)
+        );
+
+    let mut routes = extend(routes);
+    if state.conf.auth.required {
+        routes = routes.route_layer(middleware::from_fn_with_state(state.clone(), auth::authorize));
+    }
+    let mut routes = match state.conf.base_path.as_str() {
+        "" => routes,
+        base_path => Router::new().nest(base_path, routes)
+    };
+    if let Some(dir) = &state.conf.static_dir {
+        match Frontend::new(Path::new(dir), &state.conf.base_path, &state.conf.immutable_assets) {
+            Ok(frontend) => {
+                routes = routes.fallback(move |request| frontend.clone().serve(request));
+            },
+            Err(err) => tracing::error!("The frontend isn't served: {:?}", err)
+        }
+    }
+
+    // An invalid configuration is rejected at startup, see `AppConf::validate`
+    let cors = cors::layer(&state.conf.cors).unwrap_or_else(|err| {
+        tracing::error!("Cross-origin requests are blocked: {:?}", err);
+        None
+    });
+    let app = routes.with_state(state);
+    let app = match cors {
+        Some(cors) => app.layer(cors),
+        None => app
+    };
+
+    app
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+                .on_response(trace::DefaultOnResponse::new().level(Level::INFO))
+        )
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,79 @@
+use crate::{test_utils::{make_pool, read_text}, AppConf, AppState, AuthConf};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode},
+    routing::get
+};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+async fn make_state() -> AppState {
+    let pool = make_pool().await;
+
+    let conf = AppConf {
+        auth: AuthConf { required: false, ..Default::default() },
+        ..Default::default()
+    };
+    AppState::new(conf, pool)
+}
+
+fn get_request(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn routes_test() {
+    // the libraries are listed, unknown libraries and routes are not found
+    let app = super::app(make_state().await);
+
+    let mut response = app.clone().oneshot(get_request("/data")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_text(&mut response).await, r#"[{"name":"photos","read_only":false}]"#);
+
+    let response = app.clone().oneshot(get_request("/data/missing/penguins.jpg")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app.oneshot(get_request("/health")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn extra_routes_test() {
+    // extra routes are served next to the ones of the application,
+    // with access to the state
+    let app = super::app_with(make_state().await, |routes| {
+        routes.route("/health", get(|State(state): State<Arc<AppState>>| async move {
+            state.conf.libraries.len().to_string()
+        }))
+    });
+
+    let mut response = app.clone().oneshot(get_request("/health")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_text(&mut response).await, "1");
+
+    let response = app.oneshot(get_request("/data")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn base_path_test() {
+    // the routes, including the extra ones, are served under the base path
+    let mut state = make_state().await;
+    state.conf.base_path = "/fotos".to_string();
+    let app = super::app_with(state, |routes| routes.route("/health", get(|| async { "ok" })));
+
+    let mut response = app.clone().oneshot(get_request("/fotos/data")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_text(&mut response).await, r#"[{"name":"photos","read_only":false}]"#);
+
+    let response = app.clone().oneshot(get_request("/fotos/health")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.clone().oneshot(get_request("/fotos/data/missing")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app.oneshot(get_request("/data")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
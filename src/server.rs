@@ -0,0 +1,313 @@
+use crate::{app, cache, index, listen::Listener, tls, trash, users, AppState};
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use futures_util::future;
+use sqlx::SqlitePool;
+use std::{future::Future, net::{SocketAddr, TcpListener}, path::Path, sync::Arc, time::Duration};
+use tokio::{task::JoinHandle, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Serves the application on the addresses configured in `state`
+/// until `shutdown` completes, see [`serve_on`].
+pub async fn serve<F>(state: AppState, shutdown: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()>
+{
+    let socket_mode = state.conf.socket_mode()?;
+    let listeners = state.conf.listen_addrs()?
+        .iter()
+        .map(|addr| Listener::bind(addr, socket_mode))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    serve_on(listeners, state, shutdown).await
+}
+
+/// Serves the application on `listeners`, and runs the background jobs
+/// (indexing, trash and cache purging), until `shutdown` completes.
+///
+/// If TLS is configured, the application is served over HTTPS on the TCP
+/// listeners and the certificate is reloaded when it changes. The HTTP
+/// listener redirecting to the first of them is started as well, if
+/// configured. Unix domain sockets are served over HTTP and removed on
+/// shutdown.
+///
+/// On shutdown no new connections are accepted, the running requests are
+/// drained and the background jobs stop at their next checkpoint: the files
+/// indexed so far are kept and the indexing resumes at the next start.
+/// Whatever is still running after the drain timeout is interrupted.
+/// The database pool is closed last.
+pub async fn serve_on<F>(listeners: Vec<Listener>, state: AppState, shutdown: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()>
+{
+    if listeners.is_empty() {
+        anyhow::bail!("No address to listen on");
+    }
+
+    let state = Arc::new(state);
+    let stopping = CancellationToken::new();
+    let mut jobs = spawn_indexing(&state, &stopping)?;
+    jobs.push(spawn_purging(&state, &stopping));
+
+    let tls_config = match &state.conf.tls {
+        Some(tls_conf) => {
+            let config = tls::load(tls_conf).await?;
+            jobs.push(tls::spawn_reload(config.clone(), tls_conf.clone(), stopping.clone()));
+            if let Some(redirect) = &tls_conf.redirect_http {
+                let https_port = listeners.iter()
+                    .find_map(|listener| match listener {
+                        Listener::Tcp(listener) => listener.local_addr().ok(),
+                        #[cfg(unix)]
+                        Listener::Unix(..) => None
+                    })
+                    .ok_or_else(|| anyhow::anyhow!("Cannot redirect to HTTPS without a TCP address"))?
+                    .port();
+                jobs.push(spawn_redirect(redirect, https_port, &stopping)?);
+            }
+            Some(config)
+        },
+        None => None
+    };
+
+    let app = app(state.clone());
+    let mut sockets = vec![];
+    let mut servers = vec![];
+    for listener in listeners {
+        match listener {
+            Listener::Tcp(listener) => servers.push(spawn_tcp(listener, tls_config.clone(), app.clone(), &stopping)?),
+            #[cfg(unix)]
+            Listener::Unix(listener, path) => {
+                servers.push(spawn_unix(listener, &path, app.clone(), &stopping)?);
+                sockets.push(path);
+            }
+        }
+    }
+
+    let failure = tokio::select! {
+        (result, _, _) = future::select_all(servers.iter_mut()) => Some(result),
+        _ = shutdown => None
+    };
+
+    if let Some(result) = failure {
+        // A server only stops by itself if it fails
+        stopping.cancel();
+        jobs.extend(servers);
+        let deadline = Instant::now();
+        drain(jobs, deadline).await;
+        remove_sockets(&sockets);
+        close_pool(&state.pool, deadline).await;
+        return result?;
+    }
+
+    let timeout = Duration::from_secs(state.conf.drain_timeout_secs);
+    tracing::info!("Shutting down, waiting up to {:?} for running requests and jobs", timeout);
+    stopping.cancel();
+    jobs.extend(servers);
+    let deadline = Instant::now() + timeout;
+    drain(jobs, deadline).await;
+    remove_sockets(&sockets);
+
+    close_pool(&state.pool, deadline).await;
+    tracing::info!("Shut down");
+    Ok(())
+}
+
+/// Completes when the process receives `SIGINT` or `SIGTERM`.
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Cannot listen for SIGINT: {:?}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            },
+            Err(err) => {
+                tracing::error!("Cannot listen for SIGTERM: {:?}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => (),
+        _ = terminate => ()
+    }
+}
+
+/// Makes a handle which shuts the server down gracefully, without
+/// accepting new connections, once `stopping` is cancelled.
+fn shutdown_handle(stopping: &CancellationToken) -> Handle {
+    let handle = Handle::new();
+    let watched = handle.clone();
+    let stopping = stopping.clone();
+    tokio::spawn(async move {
+        stopping.cancelled().await;
+        watched.graceful_shutdown(None);
+    });
+
+    handle
+}
+
+/// Serves `app` on the TCP `listener`, over HTTPS if `tls_config` is set,
+/// until `stopping` is cancelled.
+fn spawn_tcp(listener: TcpListener, tls_config: Option<RustlsConfig>, app: Router, stopping: &CancellationToken) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+    let handle = shutdown_handle(stopping);
+    // The address of the clients throttles their wrong passwords
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    Ok(match tls_config {
+        Some(config) => {
+            tracing::info!("listening on {} (https)", addr);
+            let server = axum_server::from_tcp_rustls(listener, config).handle(handle);
+            tokio::spawn(async move { server.serve(service).await.map_err(anyhow::Error::from) })
+        },
+        None => {
+            tracing::info!("listening on {}", addr);
+            let server = axum_server::from_tcp(listener).handle(handle);
+            tokio::spawn(async move { server.serve(service).await.map_err(anyhow::Error::from) })
+        }
+    })
+}
+
+/// Serves `app` on the Unix domain socket `listener` bound to `path`,
+/// until `stopping` is cancelled.
+#[cfg(unix)]
+fn spawn_unix(listener: std::os::unix::net::UnixListener, path: &Path, app: Router, stopping: &CancellationToken) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::UnixListener::from_std(listener)?;
+    tracing::info!("listening on unix:{}", path.display());
+
+    let accept = hyper::server::accept::poll_fn(move |cx| {
+        listener.poll_accept(cx).map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    });
+    let service = app.into_make_service();
+    let stopping = stopping.clone();
+    let server = axum::Server::builder(accept)
+        .serve(service)
+        .with_graceful_shutdown(async move { stopping.cancelled().await });
+    Ok(tokio::spawn(async move { server.await.map_err(anyhow::Error::from) }))
+}
+
+/// Removes the Unix domain sockets the server listened on.
+fn remove_sockets(sockets: &[std::path::PathBuf]) {
+    for path in sockets {
+        if let Err(err) = std::fs::remove_file(path) {
+            tracing::warn!("Cannot remove the socket {}: {:?}", path.display(), err);
+        }
+    }
+}
+
+/// Redirects the plain HTTP requests received on `addr` to HTTPS on
+/// `https_port`, until `stopping` is cancelled.
+fn spawn_redirect(addr: &str, https_port: u16, stopping: &CancellationToken) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    tracing::info!("redirecting {} to https", listener.local_addr()?);
+
+    let server = axum_server::from_tcp(listener).handle(shutdown_handle(stopping));
+    let service = tls::redirect_app(https_port).into_make_service();
+    Ok(tokio::spawn(async move { server.serve(service).await.map_err(anyhow::Error::from) }))
+}
+
+/// Waits until `deadline` for `jobs` to finish, then aborts the remaining
+/// ones.
+async fn drain<T>(jobs: Vec<JoinHandle<T>>, deadline: Instant) {
+    for mut job in jobs {
+        if tokio::time::timeout_at(deadline, &mut job).await.is_err() {
+            tracing::warn!("Drain timeout elapsed, interrupting a running task");
+            job.abort();
+            let _ = job.await;
+        }
+    }
+}
+
+/// Closes the pool, waiting until `deadline` for the connections still in
+/// use to be returned.
+/// The connections of the aborted requests can outlive their task, the pool
+/// is marked as closed regardless and they are dropped on exit.
+async fn close_pool(pool: &SqlitePool, deadline: Instant) {
+    if tokio::time::timeout_at(deadline, pool.close()).await.is_err() {
+        tracing::warn!("Drain timeout elapsed, closing the database with connections in use");
+    }
+}
+
+/// Indexes the libraries in the background, until `stopping` is cancelled.
+///
+/// Every file is stored in the index as soon as it has been indexed, so
+/// stopping in between doesn't lose the work done so far.
+fn spawn_indexing(state: &Arc<AppState>, stopping: &CancellationToken) -> anyhow::Result<Vec<JoinHandle<anyhow::Result<()>>>> {
+    let mut jobs = vec![];
+    for library in state.conf.all_libraries() {
+        let index_pool = state.pool.clone();
+        let root = Path::new(&library.conf.root).to_path_buf();
+        let exclusions = library.conf.exclusions()?;
+        let stopping = stopping.clone();
+        jobs.push(tokio::spawn(async move {
+            tokio::select! {
+                result = index::index_folder(&index_pool, &library.name, &root, &exclusions, &root) => match result {
+                    Ok(stats) => tracing::info!("Indexed {}: {:?}", library.name, stats),
+                    Err(err) => tracing::error!("Indexing {} failed: {:?}", library.name, err)
+                },
+                _ = stopping.cancelled() => tracing::info!("Indexing {} interrupted", library.name)
+            }
+            Ok(())
+        }));
+    }
+
+    Ok(jobs)
+}
+
+/// Purges the expired entries of the trash and sessions, and prunes the
+/// cache periodically, until `stopping` is cancelled. A purge that has started
+/// is completed.
+fn spawn_purging(state: &Arc<AppState>, stopping: &CancellationToken) -> JoinHandle<anyhow::Result<()>> {
+    let state = state.clone();
+    let stopping = stopping.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(trash::PURGE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = stopping.cancelled() => return Ok(())
+            }
+
+            for library in state.conf.all_libraries() {
+                if library.conf.read_only {
+                    continue;
+                }
+
+                match trash::purge_expired(&state.pool, &library).await {
+                    Ok(0) => (),
+                    Ok(purged) => tracing::info!("Purged {} entries from the trash of {}", purged, library.name),
+                    Err(err) => tracing::error!("Purging the trash of {} failed: {:?}", library.name, err)
+                }
+            }
+
+            match users::purge_expired_sessions(&state.pool).await {
+                Ok(0) => (),
+                Ok(purged) => tracing::info!("Purged {} expired sessions", purged),
+                Err(err) => tracing::error!("Purging the sessions failed: {:?}", err)
+            }
+
+            match cache::prune_configured(&state.conf.cache).await {
+                Ok(stats) if stats.removed > 0 => tracing::info!("Pruned the cache: {:?}", stats),
+                Ok(_) => (),
+                Err(err) => tracing::error!("Pruning the cache failed: {:?}", err)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests;
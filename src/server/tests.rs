@@ -0,0 +1,163 @@
+use crate::{
+    listen::Listener,
+    test_utils::{library_conf, make_pool, make_root},
+    AppConf,
+    AppState,
+    AuthConf,
+    DEFAULT_LIBRARY
+};
+
+use sqlx::SqlitePool;
+use std::{env, fs, net::{SocketAddr, TcpListener}, path::PathBuf, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot,
+    task::JoinHandle
+};
+
+/// A server started by [`start`].
+struct Running {
+    addr: SocketAddr,
+
+    /// Shuts the server down.
+    stop: oneshot::Sender<()>,
+
+    server: JoinHandle<anyhow::Result<()>>,
+    pool: SqlitePool,
+    root: PathBuf
+}
+
+/// Starts the server with an empty library, on a TCP address and on the
+/// `extra` listeners.
+async fn start(name: &str, drain_timeout_secs: u64, extra: Vec<Listener>) -> Running {
+    let root = make_root(&format!("server-{name}"));
+
+    let pool = make_pool().await;
+    let conf = AppConf {
+        auth: AuthConf { required: false, ..Default::default() },
+        drain_timeout_secs,
+        libraries: [(DEFAULT_LIBRARY.to_string(), library_conf(&root))].into(),
+        ..Default::default()
+    };
+    let state = AppState::new(conf, pool.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let mut listeners = vec![Listener::Tcp(listener)];
+    listeners.extend(extra);
+    let server = tokio::spawn(super::serve_on(listeners, state, async move {
+        let _ = stopped.await;
+    }));
+
+    Running { addr, stop, server, pool, root }
+}
+
+/// Starts an upload of `length` bytes, without sending them.
+async fn start_upload(addr: SocketAddr, length: usize) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let head = format!("PUT /data/{DEFAULT_LIBRARY}/upload.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: {length}\r\n\r\n");
+    stream.write_all(head.as_bytes()).await.unwrap();
+    // let the server start handling the request
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    stream
+}
+
+#[tokio::test]
+async fn drain_test() {
+    // on shutdown the running requests are completed, then the pool is closed
+    let Running { addr, stop, server, pool, root } = start("drain", 30, vec![]).await;
+
+    let mut stream = start_upload(addr, 5).await;
+    stop.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!server.is_finished());
+
+    stream.write_all(b"hello").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 201"), "{response}");
+    assert_eq!(fs::read_to_string(root.join("upload.txt")).unwrap(), "hello");
+
+    tokio::time::timeout(Duration::from_secs(5), server).await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(pool.is_closed());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn drain_timeout_test() {
+    // requests still running after the drain timeout are interrupted
+    let Running { addr, stop, server, pool, root } = start("timeout", 0, vec![]).await;
+
+    let _stream = start_upload(addr, 5).await;
+    stop.send(()).unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), server).await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(pool.is_closed());
+    assert!(!root.join("upload.txt").exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn pool_timeout_test() {
+    // a database connection still in use doesn't keep the server from
+    // stopping after the drain timeout
+    let Running { stop, server, pool, root, .. } = start("pool", 1, vec![]).await;
+
+    let connection = pool.acquire().await.unwrap();
+    stop.send(()).unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), server).await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(pool.is_closed());
+    drop(connection);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_test() {
+    // the application is served on all the listeners, the socket is
+    // removed on shutdown
+    use crate::listen::ListenAddr;
+    use tokio::net::UnixStream;
+
+    let socket = env::temp_dir().join(format!("fotos-server-{}.sock", std::process::id()));
+    let unix = Listener::bind(&ListenAddr::Unix(socket.clone()), 0o660).unwrap();
+    let Running { addr, stop, server, root, .. } = start("unix", 30, vec![unix]).await;
+
+    let request = b"GET /data HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let mut response = String::new();
+    let mut stream = UnixStream::connect(&socket).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains(&format!("\"name\":\"{DEFAULT_LIBRARY}\"")), "{response}");
+
+    let mut response = String::new();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), server).await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(!socket.exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
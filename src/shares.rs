@@ -0,0 +1,309 @@
+use crate::{auth, throttle::Failures, users};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+
+/// The name of the key signing the share links.
+const SIGNING_KEY: &str = "shares";
+
+/// What the share links keep in memory: the signing key, read once from
+/// the database, and the recent wrong passwords of each link.
+#[derive(Debug, Default)]
+pub struct Shares {
+    signing_key: OnceCell<String>,
+
+    /// The wrong passwords of the links, by id.
+    pub failures: Failures
+}
+
+impl Shares {
+    /// Returns the key signing the share links.
+    async fn signing_key(&self, pool: &SqlitePool) -> anyhow::Result<&str> {
+        let key = self.signing_key.get_or_try_init(|| async {
+                sqlx::query_scalar("SELECT secret FROM signing_keys WHERE name = ?")
+                    .bind(SIGNING_KEY)
+                    .fetch_one(pool)
+                    .await
+            })
+            .await?;
+
+        Ok(key)
+    }
+}
+
+/// A link sharing a folder or a file of a library with anyone knowing it,
+/// until it expires or is revoked.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    pub id: String,
+    pub library: String,
+
+    /// The percent-encoded path of the shared folder or file, relative to
+    /// the root folder of the library. Empty for the whole library.
+    pub path: String,
+
+    /// The sizes, in pixels, the images may be resized to. Empty if the
+    /// original images may be downloaded.
+    pub sizes: Vec<u32>,
+
+    /// Whether a password is needed to open the link.
+    pub protected: bool,
+
+    /// Who created the link, see [`Principal::author`](crate::auth::Principal::author).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+
+    /// When the link has been created, in seconds since the Unix epoch.
+    pub created_at: i64,
+
+    /// When the link expires, in seconds since the Unix epoch.
+    pub expires_at: i64,
+
+    /// When the link has been revoked, in seconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<i64>,
+
+    /// How many requests have been served through the link.
+    pub access_count: i64,
+
+    /// When the last request has been served, in seconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_accessed_at: Option<i64>
+}
+
+type ShareRow = (String, String, String, String, Option<String>, Option<String>, i64, i64, Option<i64>, i64, Option<i64>);
+
+const SHARE_COLUMNS: &str = "id, library, path, sizes, password_hash, created_by, created_at, expires_at,
+    revoked_at, access_count, last_accessed_at";
+
+impl Share {
+    fn from_row(row: ShareRow) -> Self {
+        let (
+            id, library, path, sizes, password_hash, created_by, created_at,
+            expires_at, revoked_at, access_count, last_accessed_at
+        ) = row;
+        Self {
+            id,
+            library,
+            path,
+            sizes: sizes.split(',').filter_map(|size| size.parse().ok()).collect(),
+            protected: password_hash.is_some(),
+            created_by,
+            created_at,
+            expires_at,
+            revoked_at,
+            access_count,
+            last_accessed_at
+        }
+    }
+
+    /// Checks whether the link can still be opened at `now`.
+    pub fn is_active(&self, now: i64) -> bool {
+        self.revoked_at.is_none() && self.expires_at > now
+    }
+
+    /// The path of the link, relative to the base path, given its
+    /// `signature`.
+    pub fn link(&self, signature: &str) -> String {
+        format!("/share/{}/{signature}", self.id)
+    }
+}
+
+/// The settings of a share link to create.
+#[derive(Clone, Debug, Default)]
+pub struct NewShare {
+    pub library: String,
+
+    /// The percent-encoded path of the folder or file to share, see [`Share::path`].
+    pub path: String,
+
+    /// How long the link lasts, in seconds.
+    pub ttl_secs: i64,
+
+    pub password: Option<String>,
+    pub sizes: Vec<u32>,
+    pub created_by: Option<String>
+}
+
+/// Creates a share link.
+pub async fn create(pool: &SqlitePool, new: NewShare) -> anyhow::Result<Share> {
+    check(&new)?;
+
+    let password_hash = match &new.password {
+        Some(password) => Some(users::hash_password(password).await?),
+        None => None
+    };
+    let mut sizes = new.sizes;
+    sizes.sort();
+    sizes.dedup();
+    let created_at = auth::now();
+    let share = Share {
+        id: Uuid::new_v4().to_string(),
+        library: new.library,
+        path: new.path,
+        sizes,
+        protected: password_hash.is_some(),
+        created_by: new.created_by,
+        created_at,
+        expires_at: created_at + new.ttl_secs,
+        revoked_at: None,
+        access_count: 0,
+        last_accessed_at: None
+    };
+
+    let sizes: Vec<String> = share.sizes.iter().map(u32::to_string).collect();
+    sqlx::query(
+            "INSERT INTO shares (id, library, path, sizes, password_hash, created_by, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&share.id)
+        .bind(&share.library)
+        .bind(&share.path)
+        .bind(sizes.join(","))
+        .bind(password_hash)
+        .bind(&share.created_by)
+        .bind(share.created_at)
+        .bind(share.expires_at)
+        .execute(pool)
+        .await?;
+
+    Ok(share)
+}
+
+/// Checks that the share link `new` can be created.
+pub fn check(new: &NewShare) -> anyhow::Result<()> {
+    if new.ttl_secs <= 0 {
+        anyhow::bail!("A share link must expire in the future");
+    }
+    if new.password.as_ref().is_some_and(|password| password.is_empty()) {
+        anyhow::bail!("The password of a share link can't be empty");
+    }
+    if new.sizes.contains(&0) {
+        anyhow::bail!("The sizes of the images must be positive");
+    }
+
+    Ok(())
+}
+
+/// Lists the share links of the library `library`, including the expired
+/// and revoked ones, the most recent first.
+pub async fn list(pool: &SqlitePool, library: &str) -> anyhow::Result<Vec<Share>> {
+    let rows: Vec<ShareRow> = sqlx::query_as(&format!(
+            "SELECT {SHARE_COLUMNS} FROM shares WHERE library = ? ORDER BY created_at DESC, id"
+        ))
+        .bind(library)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(Share::from_row).collect())
+}
+
+/// Returns the share link `id`, or `None` if it doesn't exist.
+pub async fn get(pool: &SqlitePool, id: &str) -> anyhow::Result<Option<Share>> {
+    let row: Option<ShareRow> = sqlx::query_as(&format!("SELECT {SHARE_COLUMNS} FROM shares WHERE id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(Share::from_row))
+}
+
+/// Revokes the share link `id` of the library `library`, which is kept with
+/// its access count. Returns whether it exists.
+pub async fn revoke(pool: &SqlitePool, library: &str, id: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+            "UPDATE shares SET revoked_at = COALESCE(revoked_at, ?) WHERE id = ? AND library = ?"
+        )
+        .bind(auth::now())
+        .bind(id)
+        .bind(library)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Counts a request served through the share link `id`.
+pub async fn record_access(pool: &SqlitePool, id: &str) -> anyhow::Result<()> {
+    sqlx::query("UPDATE shares SET access_count = access_count + 1, last_accessed_at = ? WHERE id = ?")
+        .bind(auth::now())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the share link `id` if `signature` is its signature, or `None`
+/// otherwise. The link may have expired or been revoked.
+pub async fn verify(pool: &SqlitePool, shares: &Shares, id: &str, signature: &str) -> anyhow::Result<Option<Share>> {
+    let Some(share) = get(pool, id).await? else {
+        return Ok(None);
+    };
+    let expected = sign(pool, shares, &share).await?;
+    if !auth::constant_time_eq(expected.as_bytes(), signature.as_bytes()) {
+        return Ok(None);
+    }
+
+    Ok(Some(share))
+}
+
+/// Checks whether `password` opens the share link `id`. Links without
+/// password are opened by any password.
+pub async fn check_password(pool: &SqlitePool, id: &str, password: &str) -> anyhow::Result<bool> {
+    let password_hash: Option<String> = sqlx::query_scalar("SELECT password_hash FROM shares WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+
+    match password_hash {
+        Some(password_hash) => users::verify_password(password, password_hash).await,
+        None => Ok(true)
+    }
+}
+
+/// Returns the signature of the link of `share`, an HMAC-SHA256 of what it
+/// shares and until when.
+pub async fn sign(pool: &SqlitePool, shares: &Shares, share: &Share) -> anyhow::Result<String> {
+    let message = format!("{}\n{}\n{}\n{}", share.id, share.library, share.path, share.expires_at);
+    mac(pool, shares, &message).await
+}
+
+/// Returns the token proving that the password of `share` has been given,
+/// until `expires_at`, so that it isn't checked again on every request.
+pub async fn open_session(pool: &SqlitePool, shares: &Shares, share: &Share, expires_at: i64) -> anyhow::Result<String> {
+    let signature = mac(pool, shares, &format!("session\n{}\n{expires_at}", share.id)).await?;
+    Ok(format!("{expires_at}.{signature}"))
+}
+
+/// Checks whether `token` has been returned by [`open_session`] for `share`,
+/// and is still valid at `now`.
+pub async fn verify_session(pool: &SqlitePool, shares: &Shares, share: &Share, token: &str, now: i64) -> anyhow::Result<bool> {
+    let Some((expires_at, _)) = token.split_once('.') else {
+        return Ok(false);
+    };
+    let Ok(expires_at) = expires_at.parse::<i64>() else {
+        return Ok(false);
+    };
+    let expected = open_session(pool, shares, share, expires_at).await?;
+
+    Ok(expires_at > now && auth::constant_time_eq(expected.as_bytes(), token.as_bytes()))
+}
+
+/// Returns the HMAC-SHA256 of `message` with the signing key.
+async fn mac(pool: &SqlitePool, shares: &Shares, message: &str) -> anyhow::Result<String> {
+    let key = shares.signing_key(pool).await?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())?;
+    mac.update(message.as_bytes());
+
+    Ok(URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,99 @@
+use super::{NewShare, Shares};
+use crate::{auth, test_utils::make_pool};
+
+fn new_share(path: &str) -> NewShare {
+    NewShare {
+        library: "photos".to_string(),
+        path: path.to_string(),
+        ttl_secs: 3600,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn share_test() {
+    // the links are verified by their signature, and can be revoked
+    let pool = make_pool().await;
+    let shares = Shares::default();
+    let holidays = super::create(&pool, NewShare { sizes: vec![1280, 320, 320], ..new_share("holidays") }).await.unwrap();
+    let family = super::create(&pool, new_share("family")).await.unwrap();
+    assert_eq!(holidays.sizes, vec![320, 1280]);
+    assert_eq!(super::get(&pool, &holidays.id).await.unwrap(), Some(holidays.clone()));
+    assert_eq!(super::list(&pool, "photos").await.unwrap().len(), 2);
+    assert!(super::list(&pool, "other").await.unwrap().is_empty());
+
+    let signature = super::sign(&pool, &shares, &holidays).await.unwrap();
+    assert_eq!(super::sign(&pool, &shares, &holidays).await.unwrap(), signature);
+    assert_ne!(super::sign(&pool, &shares, &family).await.unwrap(), signature);
+    assert_eq!(super::verify(&pool, &shares, &holidays.id, &signature).await.unwrap(), Some(holidays.clone()));
+    assert_eq!(super::verify(&pool, &shares, &family.id, &signature).await.unwrap(), None);
+    assert_eq!(super::verify(&pool, &shares, "unknown", &signature).await.unwrap(), None);
+
+    // the signature covers the expiry
+    let extended = super::Share { expires_at: holidays.expires_at + 1, ..holidays.clone() };
+    assert_ne!(super::sign(&pool, &shares, &extended).await.unwrap(), signature);
+
+    super::record_access(&pool, &holidays.id).await.unwrap();
+    super::record_access(&pool, &holidays.id).await.unwrap();
+    let accessed = super::get(&pool, &holidays.id).await.unwrap().unwrap();
+    assert_eq!(accessed.access_count, 2);
+    assert!(accessed.last_accessed_at.is_some());
+
+    assert!(accessed.is_active(auth::now()));
+    assert!(!super::revoke(&pool, "other", &holidays.id).await.unwrap());
+    assert!(super::revoke(&pool, "photos", &holidays.id).await.unwrap());
+    let revoked = super::get(&pool, &holidays.id).await.unwrap().unwrap();
+    assert!(!revoked.is_active(auth::now()));
+    assert_eq!(revoked.access_count, 2);
+    assert!(!super::revoke(&pool, "photos", "unknown").await.unwrap());
+}
+
+#[tokio::test]
+async fn password_test() {
+    // the password of a link is only stored hashed
+    let pool = make_pool().await;
+    let share = super::create(&pool, NewShare { password: Some("hunter22".to_string()), ..new_share("") }).await.unwrap();
+    let open = super::create(&pool, new_share("")).await.unwrap();
+    assert!(share.protected);
+    assert!(!open.protected);
+
+    assert!(super::check_password(&pool, &share.id, "hunter22").await.unwrap());
+    assert!(!super::check_password(&pool, &share.id, "hunter2").await.unwrap());
+    assert!(super::check_password(&pool, &open.id, "").await.unwrap());
+
+    let stored: Vec<(Option<String>,)> = sqlx::query_as("SELECT password_hash FROM shares").fetch_all(&pool).await.unwrap();
+    assert!(stored.iter().flat_map(|(hash,)| hash).all(|hash| hash.starts_with("$argon2id$")));
+}
+
+#[test]
+fn check_test() {
+    assert!(super::check(&new_share("holidays")).is_ok());
+    assert!(super::check(&NewShare { ttl_secs: 0, ..new_share("holidays") }).is_err());
+    assert!(super::check(&NewShare { password: Some(String::new()), ..new_share("holidays") }).is_err());
+    assert!(super::check(&NewShare { sizes: vec![320, 0], ..new_share("holidays") }).is_err());
+}
+
+#[tokio::test]
+async fn session_test() {
+    // the password of a link is remembered by a token bound to the link,
+    // until it expires
+    let pool = make_pool().await;
+    let shares = Shares::default();
+    let share = super::create(&pool, NewShare { password: Some("hunter22".to_string()), ..new_share("") }).await.unwrap();
+    let other = super::create(&pool, new_share("")).await.unwrap();
+    let now = auth::now();
+
+    let token = super::open_session(&pool, &shares, &share, now + 60).await.unwrap();
+    assert!(super::verify_session(&pool, &shares, &share, &token, now).await.unwrap());
+    assert!(!super::verify_session(&pool, &shares, &share, &token, now + 60).await.unwrap());
+    assert!(!super::verify_session(&pool, &shares, &other, &token, now).await.unwrap());
+
+    let (_, signature) = token.split_once('.').unwrap();
+    let extended = format!("{}.{signature}", now + 3600);
+    assert!(!super::verify_session(&pool, &shares, &share, &extended, now).await.unwrap());
+    assert!(!super::verify_session(&pool, &shares, &share, "garbage", now).await.unwrap());
+
+    // the signing key is the one created by the migrations, for all the states
+    let signature = super::sign(&pool, &shares, &share).await.unwrap();
+    assert_eq!(super::sign(&pool, &Shares::default(), &share).await.unwrap(), signature);
+}
@@ -0,0 +1,110 @@
+//! Helpers shared by the tests of the modules.
+
+use crate::{infrastructure, AppConf, AppState, AuthConf, LibraryConf, DEFAULT_LIBRARY};
+
+use axum::{
+    body::{Body, HttpBody},
+    http::{header, Method, Request},
+    response::Response,
+    Router
+};
+use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::{env, fs, path::{Path, PathBuf}, sync::Arc};
+use tower::ServiceExt;
+
+/// Opens a new in-memory database, with the migrations applied.
+pub async fn make_pool() -> SqlitePool {
+    // Every connection to an in-memory database opens a new database
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("Cannot open database");
+    infrastructure::migrate(&pool).await
+        .expect("Database migration failed");
+    pool
+}
+
+/// Creates the empty folder `fotos-<name>-<pid>` in the temporary folder,
+/// removing the one left by a previous run.
+pub fn make_root(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("fotos-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+/// The configuration serving `library` as the default library, without
+/// authentication.
+pub fn make_conf(library: LibraryConf) -> AppConf {
+    AppConf {
+        auth: AuthConf { required: false, ..Default::default() },
+        libraries: [(DEFAULT_LIBRARY.to_string(), library)].into(),
+        ..Default::default()
+    }
+}
+
+/// The configuration of a library whose root folder is `root`.
+pub fn library_conf(root: &Path) -> LibraryConf {
+    LibraryConf {
+        root: root.to_str().unwrap().to_string(),
+        ..Default::default()
+    }
+}
+
+/// Makes the state serving `root` as the default library, without
+/// authentication, with a new in-memory database.
+pub async fn make_state(root: &Path) -> Arc<AppState> {
+    Arc::new(AppState::new(make_conf(library_conf(root)), make_pool().await))
+}
+
+/// Sends `request` through the application serving `state`.
+pub async fn send(state: &Arc<AppState>, request: Request<Body>) -> Response {
+    crate::app(state.clone()).oneshot(request).await.unwrap()
+}
+
+/// Sends a request to `uri` through `app`, with the `Authorization` header
+/// `authorization` and the json `body` if set.
+pub async fn call(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    authorization: Option<String>,
+    body: Option<serde_json::Value>
+) -> Response {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri);
+    if let Some(authorization) = authorization {
+        request = request.header(header::AUTHORIZATION, authorization);
+    }
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty())
+    };
+
+    app.clone().oneshot(request.unwrap()).await.unwrap()
+}
+
+/// The `Authorization` header of the requests using the token `secret`.
+pub fn bearer(secret: &str) -> Option<String> {
+    Some(format!("Bearer {secret}"))
+}
+
+pub async fn read_body(response: &mut Response) -> Vec<u8> {
+    let mut buf = vec![];
+    while let Some(bytes) = response.body_mut().data().await {
+        buf.extend_from_slice(bytes.unwrap().as_ref());
+    }
+    buf
+}
+
+pub async fn read_json<T: serde::de::DeserializeOwned>(response: &mut Response) -> T {
+    serde_json::from_slice(&read_body(response).await).unwrap()
+}
+
+pub async fn read_text(response: &mut Response) -> String {
+    String::from_utf8_lossy(&read_body(response).await).to_string()
+}
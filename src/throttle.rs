@@ -0,0 +1,47 @@
+use std::{collections::HashMap, sync::Mutex};
+
+/// How many wrong passwords can be given for a key (e.g. a share link or
+/// a user name) within [`FAILURE_WINDOW_SECS`], before refusing to check
+/// more.
+pub const MAX_FAILURES: u32 = 5;
+
+/// The period over which the wrong passwords are counted, in seconds.
+pub const FAILURE_WINDOW_SECS: i64 = 60;
+
+/// The recent wrong passwords, by key.
+#[derive(Debug, Default)]
+pub struct Failures {
+    /// The number of wrong passwords, by key, with the start of the period
+    /// they are counted over.
+    failures: Mutex<HashMap<String, (u32, i64)>>
+}
+
+impl Failures {
+    /// Returns how long, in seconds, the passwords for `key` can't be
+    /// checked at `now`, after too many wrong ones. `None` if they can.
+    pub fn throttled(&self, key: &str, now: i64) -> Option<i64> {
+        let failures = self.failures.lock().unwrap_or_else(|err| err.into_inner());
+        match failures.get(key) {
+            Some(&(count, since)) if count >= MAX_FAILURES && now < since + FAILURE_WINDOW_SECS => {
+                Some(since + FAILURE_WINDOW_SECS - now)
+            },
+            _ => None
+        }
+    }
+
+    /// Counts a wrong password given at `now` for `key`.
+    pub fn record(&self, key: &str, now: i64) {
+        let mut failures = self.failures.lock().unwrap_or_else(|err| err.into_inner());
+        failures.retain(|_, (_, since)| now < *since + FAILURE_WINDOW_SECS);
+        failures.entry(key.to_string()).or_insert((0, now)).0 += 1;
+    }
+
+    /// Forgets the wrong passwords for `key`, once the right one has been
+    /// given.
+    pub fn clear(&self, key: &str) {
+        self.failures.lock().unwrap_or_else(|err| err.into_inner()).remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,18 @@
+use super::{Failures, FAILURE_WINDOW_SECS, MAX_FAILURES};
+
+#[test]
+fn throttle_test() {
+    // the passwords for a key aren't checked for a while after too many
+    // wrong ones, the other keys aren't affected
+    let failures = Failures::default();
+    for _ in 0..MAX_FAILURES {
+        assert_eq!(failures.throttled("holidays", 100), None);
+        failures.record("holidays", 100);
+    }
+    assert_eq!(failures.throttled("holidays", 110), Some(FAILURE_WINDOW_SECS - 10));
+    assert_eq!(failures.throttled("family", 110), None);
+    assert_eq!(failures.throttled("holidays", 100 + FAILURE_WINDOW_SECS), None);
+
+    failures.clear("holidays");
+    assert_eq!(failures.throttled("holidays", 110), None);
+}
@@ -0,0 +1,101 @@
+use crate::TlsConf;
+
+use anyhow::Context;
+use axum::{
+    http::{header, uri::PathAndQuery, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router
+};
+use axum_server::tls_rustls::RustlsConfig;
+use std::time::{Duration, SystemTime};
+use tokio::{fs, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+/// Loads the certificate and the key configured in `conf`.
+pub async fn load(conf: &TlsConf) -> anyhow::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&conf.cert, &conf.key).await
+        .with_context(|| format!("Cannot load the TLS certificate {} and key {}", conf.cert, conf.key))
+}
+
+/// Reloads `config` whenever the certificate or the key configured in
+/// `conf` change on disk, until `stopping` is cancelled.
+///
+/// If the new files can't be loaded, e.g. because only one of them has
+/// been replaced yet, the current certificate is kept and loading is
+/// attempted again at the next check.
+pub fn spawn_reload(config: RustlsConfig, conf: TlsConf, stopping: CancellationToken) -> JoinHandle<anyhow::Result<()>> {
+    tokio::spawn(async move {
+        if conf.reload_interval_secs == 0 {
+            return Ok(());
+        }
+
+        let mut loaded = modified(&conf).await;
+        let mut interval = tokio::time::interval(Duration::from_secs(conf.reload_interval_secs));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = stopping.cancelled() => return Ok(())
+            }
+
+            let current = modified(&conf).await;
+            if current == loaded {
+                continue;
+            }
+
+            match config.reload_from_pem_file(&conf.cert, &conf.key).await {
+                Ok(()) => {
+                    tracing::info!("Reloaded the TLS certificate {}", conf.cert);
+                    loaded = current;
+                },
+                Err(err) => tracing::warn!("Cannot reload the TLS certificate {}: {:?}", conf.cert, err)
+            }
+        }
+    })
+}
+
+/// The modification times of the certificate and the key.
+async fn modified(conf: &TlsConf) -> Option<(SystemTime, SystemTime)> {
+    let cert = fs::metadata(&conf.cert).await.ok()?.modified().ok()?;
+    let key = fs::metadata(&conf.key).await.ok()?.modified().ok()?;
+    Some((cert, key))
+}
+
+/// Builds the application redirecting all the requests to the same
+/// location on HTTPS, served on `https_port`.
+pub fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        redirect(&headers, &uri, https_port)
+    })
+}
+
+fn redirect(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let host = headers.get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| uri.host());
+    let path = uri.path_and_query()
+        .map(PathAndQuery::as_str)
+        .unwrap_or("/");
+
+    match host.and_then(|host| https_uri(host, path, https_port)) {
+        Some(location) => Redirect::permanent(&location).into_response(),
+        None => (StatusCode::BAD_REQUEST, "Missing or invalid host").into_response()
+    }
+}
+
+/// Returns the HTTPS uri of `path` on `host`, whose port is replaced by
+/// `https_port`.
+fn https_uri(host: &str, path: &str, https_port: u16) -> Option<String> {
+    let authority = host.parse::<axum::http::uri::Authority>().ok()?;
+    let hostname = authority.host();
+    let location = if https_port == 443 {
+        format!("https://{hostname}{path}")
+    } else {
+        format!("https://{hostname}:{https_port}{path}")
+    };
+
+    location.parse::<Uri>().ok()?;
+    Some(location)
+}
+
+#[cfg(test)]
+mod tests;
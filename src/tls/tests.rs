@@ -0,0 +1,157 @@
+use crate::{test_utils::make_pool, listen::Listener, AppConf, AppState, AuthConf, TlsConf};
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode}
+};
+use rstest::*;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate,
+    ClientConfig,
+    ServerName
+};
+use std::{
+    env,
+    fs,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime}
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot
+};
+use tokio_rustls::TlsConnector;
+use tower::ServiceExt;
+
+static FIXTURES: &str = "tests/fixtures/tls";
+
+#[rstest]
+#[case(Some("photos.home:8080"), "/data/photos?limit=1", 3443, Some("https://photos.home:3443/data/photos?limit=1"))]
+#[case(Some("photos.home"), "/", 443, Some("https://photos.home/"))]
+#[case(Some("[::1]:80"), "/data", 3443, Some("https://[::1]:3443/data"))]
+#[case(Some("not a host"), "/", 443, None)]
+#[case(None, "/", 443, None)]
+#[tokio::test]
+async fn redirect_test(
+    #[case] host: Option<&str>,
+    #[case] path: &str,
+    #[case] https_port: u16,
+    #[case] expected: Option<&str>
+) {
+    // plain HTTP requests are redirected to the same location on HTTPS
+    let mut request = Request::get(path);
+    if let Some(host) = host {
+        request = request.header(header::HOST, host);
+    }
+    let request = request.body(Body::empty()).unwrap();
+
+    let response = super::redirect_app(https_port).oneshot(request).await.unwrap();
+    match expected {
+        Some(location) => {
+            assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(response.headers()[header::LOCATION], location);
+        },
+        None => assert_eq!(response.status(), StatusCode::BAD_REQUEST)
+    }
+}
+
+/// Accepts any certificate, the tests check which one is presented.
+struct AcceptAll;
+
+impl ServerCertVerifier for AcceptAll {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn read_cert(path: &Path) -> Certificate {
+    let pem = fs::read(path).unwrap();
+    let der = rustls_pemfile::certs(&mut pem.as_slice()).unwrap().remove(0);
+    Certificate(der)
+}
+
+/// Lists the libraries over HTTPS, returns the status line of the response
+/// and the certificate presented by the server.
+async fn fetch(addr: SocketAddr) -> (String, Certificate) {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AcceptAll))
+        .with_no_client_auth();
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+
+    stream.write_all(b"GET /data HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = vec![];
+    // the server may close the connection without notifying the client
+    let _ = stream.read_to_end(&mut response).await;
+
+    let cert = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+    let response = String::from_utf8_lossy(&response).to_string();
+    (response.lines().next().unwrap_or_default().to_string(), cert)
+}
+
+#[tokio::test]
+async fn reload_test() {
+    // the application is served over HTTPS, renewed certificates are used
+    // for the new connections
+    let folder = env::temp_dir().join(format!("fotos-tls-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    let fixtures = Path::new(FIXTURES);
+    fs::copy(fixtures.join("first-cert.pem"), folder.join("cert.pem")).unwrap();
+    fs::copy(fixtures.join("first-key.pem"), folder.join("key.pem")).unwrap();
+
+    let pool = make_pool().await;
+    let conf = AppConf {
+        auth: AuthConf { required: false, ..Default::default() },
+        tls: Some(TlsConf {
+            cert: folder.join("cert.pem").to_string_lossy().to_string(),
+            key: folder.join("key.pem").to_string_lossy().to_string(),
+            redirect_http: None,
+            reload_interval_secs: 1
+        }),
+        libraries: Default::default(),
+        ..Default::default()
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(crate::server::serve_on(vec![Listener::Tcp(listener)], AppState::new(conf, pool), async move {
+        let _ = stopped.await;
+    }));
+
+    let (status, cert) = fetch(addr).await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(cert, read_cert(&fixtures.join("first-cert.pem")));
+
+    fs::copy(fixtures.join("second-key.pem"), folder.join("key.pem")).unwrap();
+    fs::copy(fixtures.join("second-cert.pem"), folder.join("cert.pem")).unwrap();
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    let (status, cert) = fetch(addr).await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(cert, read_cert(&fixtures.join("second-cert.pem")));
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), server).await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    fs::remove_dir_all(&folder).unwrap();
+}
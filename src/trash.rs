@@ -0,0 +1,249 @@
+use crate::{api::subpath, fsutil, index, Library, LibraryConf};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+use tokio::fs;
+use uuid::Uuid;
+
+/// How often the expired entries are purged from the trash.
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A file or folder in the trash.
+///
+/// On the local file system it is stored as `<trash>/<id>/<name>`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TrashEntry {
+    pub id: String,
+
+    /// The name of the entry, for display purposes.
+    pub filename: String,
+
+    /// The percent-encoded path the entry has been deleted from,
+    /// relative to the root folder.
+    pub path: String,
+
+    pub is_dir: bool,
+
+    /// When the entry has been deleted, in seconds since the Unix epoch.
+    pub deleted_at: i64,
+
+    /// When the entry will be purged, in seconds since the Unix epoch.
+    /// `None` if the entries are kept until they are purged explicitly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>
+}
+
+impl TrashEntry {
+    fn from_row(conf: &LibraryConf, row: (String, String, bool, i64)) -> Self {
+        let (id, path, is_dir, deleted_at) = row;
+        Self {
+            id,
+            filename: subpath::decode_name(path.rsplit('/').next().unwrap_or_default())
+                .to_string_lossy()
+                .to_string(),
+            path,
+            is_dir,
+            deleted_at,
+            expires_at: (conf.trash_retention_days > 0)
+                .then(|| deleted_at + conf.trash_retention_days as i64 * SECONDS_PER_DAY)
+        }
+    }
+
+    /// The path the entry has been deleted from, relative to the root folder.
+    pub fn original_path(&self) -> PathBuf {
+        subpath::decode_path(&self.path)
+    }
+
+    /// Where the entry is stored in the trash folder.
+    fn location(&self, conf: &LibraryConf) -> PathBuf {
+        let name = self.path.rsplit('/').next().unwrap_or_default();
+        conf.trash_dir().join(&self.id).join(subpath::decode_name(name))
+    }
+}
+
+/// Lists the entries of the trash of `library`, the most recently deleted
+/// first.
+pub async fn list(pool: &SqlitePool, library: &Library) -> anyhow::Result<Vec<TrashEntry>> {
+    let rows: Vec<(String, String, bool, i64)> = sqlx::query_as(
+            "SELECT id, path, is_dir, deleted_at FROM trash
+            WHERE library = ?
+            ORDER BY deleted_at DESC, path"
+        )
+        .bind(&library.name)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|row| TrashEntry::from_row(&library.conf, row)).collect())
+}
+
+/// Returns the trash entry `id` of `library`, or `None` if it doesn't exist.
+pub async fn get(pool: &SqlitePool, library: &Library, id: &str) -> anyhow::Result<Option<TrashEntry>> {
+    let row: Option<(String, String, bool, i64)> = sqlx::query_as(
+            "SELECT id, path, is_dir, deleted_at FROM trash
+            WHERE id = ? AND library = ?"
+        )
+        .bind(id)
+        .bind(&library.name)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|row| TrashEntry::from_row(&library.conf, row)))
+}
+
+/// Moves the file or folder `fullpath`, which must be beneath the root
+/// folder of `library`, to its trash together with its metadata in the index.
+pub async fn move_to_trash(pool: &SqlitePool, library: &Library, fullpath: &Path) -> anyhow::Result<TrashEntry> {
+    let conf = &library.conf;
+    let root = Path::new(&conf.root);
+    let relative = fullpath.strip_prefix(root)?;
+    let deleted_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let row = (
+        Uuid::new_v4().to_string(),
+        subpath::encode_path(relative),
+        fs::symlink_metadata(fullpath).await?.is_dir(),
+        deleted_at
+    );
+    let entry = TrashEntry::from_row(conf, row);
+
+    let folder = conf.trash_dir().join(&entry.id);
+    fs::create_dir_all(&folder).await?;
+    if let Err(err) = store(pool, &library.name, &entry, fullpath, &entry.location(conf)).await {
+        let _ = fs::remove_dir(&folder).await;
+        return Err(err);
+    }
+
+    Ok(entry)
+}
+
+/// Moves `fullpath` to `location` in the trash, then records the entry.
+/// The move can take a while if the trash is on another file system, the
+/// database is only locked once it is done.
+async fn store(
+    pool: &SqlitePool,
+    library: &str,
+    entry: &TrashEntry,
+    fullpath: &Path,
+    location: &Path
+) -> anyhow::Result<()> {
+    fsutil::move_path(fullpath, location).await?;
+
+    let recorded = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query(
+                "INSERT INTO trash (id, library, path, is_dir, deleted_at)
+                VALUES (?, ?, ?, ?, ?)"
+            )
+            .bind(&entry.id)
+            .bind(library)
+            .bind(&entry.path)
+            .bind(entry.is_dir)
+            .bind(entry.deleted_at)
+            .execute(&mut *tx)
+            .await?;
+        index::trash_entries(&mut tx, library, &entry.path, &entry.id).await?;
+        tx.commit().await?;
+        anyhow::Ok(())
+    };
+    if let Err(err) = recorded.await {
+        // Keep the file system consistent with the database
+        let _ = fsutil::move_path(location, fullpath).await;
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Moves the trash entry back to the path it has been deleted from,
+/// which must not exist, together with its metadata in the index.
+/// Missing parent folders are created. Returns the restored path.
+pub async fn restore(pool: &SqlitePool, library: &Library, entry: &TrashEntry) -> anyhow::Result<PathBuf> {
+    let conf = &library.conf;
+    let target = Path::new(&conf.root).join(entry.original_path());
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let location = entry.location(conf);
+    fsutil::move_path(&location, &target).await?;
+
+    let restored = async {
+        let mut tx = pool.begin().await?;
+        index::restore_entries(&mut tx, &library.name, &entry.path, &entry.id).await?;
+        sqlx::query("DELETE FROM trash WHERE id = ?")
+            .bind(&entry.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        anyhow::Ok(())
+    };
+    if let Err(err) = restored.await {
+        let _ = fsutil::move_path(&target, &location).await;
+        return Err(err);
+    }
+
+    let _ = fs::remove_dir(conf.trash_dir().join(&entry.id)).await;
+    Ok(target)
+}
+
+/// Removes the trash entry `id` of `library` for good, together with its
+/// metadata in the index. Returns `false` if the entry doesn't exist.
+pub async fn purge(pool: &SqlitePool, library: &Library, id: &str) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+    let exists: Option<i64> = sqlx::query_scalar("SELECT 1 FROM trash WHERE id = ? AND library = ?")
+        .bind(id)
+        .bind(&library.name)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if exists.is_none() {
+        return Ok(false);
+    }
+
+    index::purge_entries(&mut tx, id).await?;
+    sqlx::query("DELETE FROM trash WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    // Only ids coming from the database end up in a path
+    match fs::remove_dir_all(library.conf.trash_dir().join(id)).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(true)
+    }
+}
+
+/// Purges the entries which have been in the trash of `library` for longer
+/// than its retention period. Returns the number of purged entries.
+pub async fn purge_expired(pool: &SqlitePool, library: &Library) -> anyhow::Result<usize> {
+    let conf = &library.conf;
+    if conf.trash_retention_days == 0 {
+        return Ok(0);
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let cutoff = now - conf.trash_retention_days as i64 * SECONDS_PER_DAY;
+    let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM trash WHERE library = ? AND deleted_at <= ?")
+        .bind(&library.name)
+        .bind(cutoff)
+        .fetch_all(pool)
+        .await?;
+
+    let mut purged = 0;
+    for id in ids {
+        if purge(pool, library, &id).await? {
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests;
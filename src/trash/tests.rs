@@ -0,0 +1,79 @@
+use crate::{test_utils::{make_pool, make_root}, Library, LibraryConf};
+
+use std::fs;
+
+#[tokio::test]
+async fn purge_expired_test() {
+    // entries are purged once the retention period is over, also from
+    // a trash folder outside the root folder
+    let base = make_root("purge");
+    fs::create_dir_all(base.join("root")).unwrap();
+    fs::write(base.join("root/old.txt"), b"old").unwrap();
+    fs::write(base.join("root/new.txt"), b"new").unwrap();
+
+    let pool = make_pool().await;
+    let mut library = Library {
+        name: "archive".to_string(),
+        conf: LibraryConf {
+            root: base.join("root").to_str().unwrap().to_string(),
+            trash: base.join("trash").to_str().unwrap().to_string(),
+            trash_retention_days: 0,
+            ..Default::default()
+        },
+        access: Default::default()
+    };
+
+    let old = super::move_to_trash(&pool, &library, &base.join("root/old.txt")).await.unwrap();
+    let new = super::move_to_trash(&pool, &library, &base.join("root/new.txt")).await.unwrap();
+    assert!(base.join("trash").join(&old.id).join("old.txt").is_file());
+    sqlx::query("UPDATE trash SET deleted_at = deleted_at - 10 * 24 * 60 * 60 WHERE id = ?")
+        .bind(&old.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // without retention period nothing is purged
+    assert_eq!(super::purge_expired(&pool, &library).await.unwrap(), 0);
+
+    library.conf.trash_retention_days = 7;
+    assert_eq!(super::purge_expired(&pool, &library).await.unwrap(), 1);
+    assert!(!base.join("trash").join(&old.id).exists());
+
+    let remaining: Vec<_> = super::list(&pool, &library).await.unwrap()
+        .into_iter()
+        .map(|entry| entry.id)
+        .collect();
+    assert_eq!(remaining, vec![new.id]);
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[tokio::test]
+async fn database_failure_test() {
+    // the files are left where they were if the database can't be updated
+    let root = make_root("trash-db-failure");
+    fs::write(root.join("trashed.txt"), b"trashed").unwrap();
+    fs::write(root.join("kept.txt"), b"kept").unwrap();
+
+    let pool = make_pool().await;
+    let library = Library {
+        name: "archive".to_string(),
+        conf: LibraryConf {
+            root: root.to_str().unwrap().to_string(),
+            ..Default::default()
+        },
+        access: Default::default()
+    };
+    let trashed = super::move_to_trash(&pool, &library, &root.join("trashed.txt")).await.unwrap();
+    pool.close().await;
+
+    assert!(super::move_to_trash(&pool, &library, &root.join("kept.txt")).await.is_err());
+    assert!(root.join("kept.txt").is_file());
+    assert_eq!(fs::read_dir(library.conf.trash_dir()).unwrap().count(), 1);
+
+    assert!(super::restore(&pool, &library, &trashed).await.is_err());
+    assert!(!root.join("trashed.txt").exists());
+    assert!(trashed.location(&library.conf).is_file());
+
+    fs::remove_dir_all(&root).unwrap();
+}
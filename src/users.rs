@@ -0,0 +1,351 @@
+use crate::auth::{self, Scope};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2
+};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::{fmt, str::FromStr};
+use uuid::Uuid;
+
+/// The minimum length of a password.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// What a user is allowed to do. Every role includes the previous ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Browsing and downloading the libraries, like [`Scope::Read`].
+    Viewer,
+
+    /// Adding and removing tags, like [`Scope::Annotate`].
+    Annotator,
+
+    /// Anything else, including managing the users, like [`Scope::Admin`].
+    Admin
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Annotator => "annotator",
+            Role::Admin => "admin"
+        }
+    }
+
+    /// The scope of the tokens with the same rights.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Role::Viewer => Scope::Read,
+            Role::Annotator => Scope::Annotate,
+            Role::Admin => Scope::Admin
+        }
+    }
+}
+
+impl From<Scope> for Role {
+    fn from(scope: Scope) -> Self {
+        match scope {
+            Scope::Read => Role::Viewer,
+            Scope::Annotate => Role::Annotator,
+            Scope::Admin => Role::Admin
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "annotator" => Ok(Role::Annotator),
+            "admin" => Ok(Role::Admin),
+            _ => anyhow::bail!("Unknown role {s:?}, expected viewer, annotator or admin")
+        }
+    }
+}
+
+/// A user account. The password is only stored hashed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+
+    /// When the user has been created, in seconds since the Unix epoch.
+    pub created_at: i64
+}
+
+type UserRow = (String, String, String, i64);
+
+impl User {
+    fn from_row(row: UserRow) -> Self {
+        let (id, name, role, created_at) = row;
+        Self {
+            id,
+            name,
+            // Only valid roles are stored
+            role: role.parse().unwrap_or(Role::Viewer),
+            created_at
+        }
+    }
+
+    /// Checks whether the role of the user allows what `scope` allows.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.role.scope() >= scope
+    }
+}
+
+/// A session of a user logged in with a password. The secret identifying
+/// the session is only known when it is opened.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub user: User,
+
+    /// The token to send in the `X-CSRF-Token` header of the requests
+    /// modifying anything.
+    pub csrf_token: String,
+
+    /// When the session expires, in seconds since the Unix epoch.
+    pub expires_at: i64
+}
+
+/// Creates the user `name` with `password` and `role`.
+pub async fn create(pool: &SqlitePool, name: &str, password: &str, role: Role) -> anyhow::Result<User> {
+    check(name, password)?;
+
+    let user = User {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        role,
+        created_at: auth::now()
+    };
+    sqlx::query(
+            "INSERT INTO users (id, name, password_hash, role, created_at)
+            VALUES (?, ?, ?, ?, ?)"
+        )
+        .bind(&user.id)
+        .bind(&user.name)
+        .bind(hash_password(password).await?)
+        .bind(user.role.as_str())
+        .bind(user.created_at)
+        .execute(pool)
+        .await?;
+
+    Ok(user)
+}
+
+/// Checks that a user named `name` with `password` can be created.
+///
+/// The names can't contain `:`, which sets the tokens apart in the records
+/// attributed to their author.
+pub fn check(name: &str, password: &str) -> anyhow::Result<()> {
+    if name.trim().is_empty() || name.len() > 100 || name.contains(':') {
+        anyhow::bail!("The name of a user must have between 1 and 100 characters, without ':'");
+    }
+    check_password(password)
+}
+
+/// Checks that `password` is long enough.
+pub fn check_password(password: &str) -> anyhow::Result<()> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        anyhow::bail!("A password must have at least {MIN_PASSWORD_LENGTH} characters");
+    }
+
+    Ok(())
+}
+
+/// Lists the users, sorted by name.
+pub async fn list(pool: &SqlitePool) -> anyhow::Result<Vec<User>> {
+    let rows: Vec<UserRow> = sqlx::query_as(
+            "SELECT id, name, role, created_at FROM users ORDER BY name"
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(User::from_row).collect())
+}
+
+/// Returns the user with the id or the name `key`, or `None` if it
+/// doesn't exist.
+pub async fn get(pool: &SqlitePool, key: &str) -> anyhow::Result<Option<User>> {
+    let row: Option<UserRow> = sqlx::query_as(
+            "SELECT id, name, role, created_at FROM users WHERE id = ?1 OR name = ?1"
+        )
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(User::from_row))
+}
+
+/// Changes the role of the user `id`. Returns whether it exists.
+pub async fn set_role(pool: &SqlitePool, id: &str, role: Role) -> anyhow::Result<bool> {
+    let result = sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+        .bind(role.as_str())
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Changes the password of the user `id` and closes their sessions.
+/// Returns whether the user exists.
+pub async fn set_password(pool: &SqlitePool, id: &str, password: &str) -> anyhow::Result<bool> {
+    check_password(password)?;
+
+    let password_hash = hash_password(password).await?;
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+        .bind(password_hash)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Removes the user `id` and their sessions. Returns whether it existed.
+pub async fn remove(pool: &SqlitePool, id: &str) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    let result = sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the user `name` if `password` is theirs, or `None` otherwise.
+///
+/// A password is hashed even if the user doesn't exist, so that the time
+/// taken doesn't tell which users exist.
+pub async fn authenticate(pool: &SqlitePool, name: &str, password: &str) -> anyhow::Result<Option<User>> {
+    let row: Option<(String, String, String, i64, String)> = sqlx::query_as(
+            "SELECT id, name, role, created_at, password_hash FROM users WHERE name = ?"
+        )
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+
+    let Some((id, name, role, created_at, password_hash)) = row else {
+        hash_password(password).await?;
+        return Ok(None);
+    };
+    if !verify_password(password, password_hash).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(User::from_row((id, name, role, created_at))))
+}
+
+/// Opens a session of `user` lasting `ttl_secs`. Returns the session and
+/// its secret, which isn't stored.
+pub async fn open_session(pool: &SqlitePool, user: &User, ttl_secs: i64) -> anyhow::Result<(Session, String)> {
+    let secret = auth::generate_secret();
+    let created_at = auth::now();
+    let session = Session {
+        user: user.clone(),
+        csrf_token: auth::generate_secret(),
+        expires_at: created_at + ttl_secs
+    };
+
+    sqlx::query(
+            "INSERT INTO sessions (id, user_id, csrf_token, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)"
+        )
+        .bind(auth::hash(&secret))
+        .bind(&user.id)
+        .bind(&session.csrf_token)
+        .bind(created_at)
+        .bind(session.expires_at)
+        .execute(pool)
+        .await?;
+
+    Ok((session, secret))
+}
+
+/// Returns the session whose secret is `secret`, or `None` if there is none
+/// or if it has expired.
+pub async fn find_session(pool: &SqlitePool, secret: &str) -> anyhow::Result<Option<Session>> {
+    let row: Option<(String, String, String, i64, String, i64)> = sqlx::query_as(
+            "SELECT users.id, users.name, users.role, users.created_at, sessions.csrf_token, sessions.expires_at
+            FROM sessions JOIN users ON users.id = sessions.user_id
+            WHERE sessions.id = ? AND sessions.expires_at > ?"
+        )
+        .bind(auth::hash(secret))
+        .bind(auth::now())
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|(id, name, role, created_at, csrf_token, expires_at)| Session {
+        user: User::from_row((id, name, role, created_at)),
+        csrf_token,
+        expires_at
+    }))
+}
+
+/// Closes the session whose secret is `secret`. Returns whether it existed.
+pub async fn close_session(pool: &SqlitePool, secret: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query("DELETE FROM sessions WHERE id = ?")
+        .bind(auth::hash(secret))
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Removes the expired sessions. Returns how many have been removed.
+pub async fn purge_expired_sessions(pool: &SqlitePool) -> anyhow::Result<u64> {
+    let result = sqlx::query("DELETE FROM sessions WHERE expires_at <= ?")
+        .bind(auth::now())
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Hashes `password` with argon2 and a random salt, off the async runtime
+/// since it is meant to be slow.
+pub(crate) async fn hash_password(password: &str) -> anyhow::Result<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes())
+            .map_err(|err| anyhow::anyhow!("Cannot generate a salt: {err}"))?;
+        let hash = Argon2::default().hash_password(password.as_bytes(), &salt)
+            .map_err(|err| anyhow::anyhow!("Cannot hash the password: {err}"))?;
+        Ok(hash.to_string())
+    }).await?
+}
+
+pub(crate) async fn verify_password(password: &str, password_hash: String) -> anyhow::Result<bool> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let parsed = PasswordHash::new(&password_hash)
+            .map_err(|err| anyhow::anyhow!("Invalid password hash: {err}"))?;
+        Ok(Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+    }).await?
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,60 @@
+use super::Role;
+use crate::{auth::Scope, test_utils::make_pool};
+
+#[tokio::test]
+async fn user_test() {
+    // users are authenticated by their password, which isn't stored
+    let pool = make_pool().await;
+    let alice = super::create(&pool, "alice", "correct horse", Role::Annotator).await.unwrap();
+    let bob = super::create(&pool, "bob", "battery staple", Role::Viewer).await.unwrap();
+
+    assert_eq!(super::authenticate(&pool, "alice", "correct horse").await.unwrap(), Some(alice.clone()));
+    assert_eq!(super::authenticate(&pool, "alice", "battery staple").await.unwrap(), None);
+    assert_eq!(super::authenticate(&pool, "carol", "correct horse").await.unwrap(), None);
+    assert_eq!(super::list(&pool).await.unwrap(), vec![alice.clone(), bob.clone()]);
+    assert_eq!(super::get(&pool, &bob.id).await.unwrap(), Some(bob.clone()));
+
+    let stored: Vec<(String,)> = sqlx::query_as("SELECT password_hash FROM users").fetch_all(&pool).await.unwrap();
+    assert!(stored.iter().all(|(hash,)| hash.starts_with("$argon2id$") && !hash.contains("correct horse")));
+
+    assert!(alice.allows(Scope::Annotate));
+    assert!(!alice.allows(Scope::Admin));
+    assert!(super::set_role(&pool, &alice.id, Role::Admin).await.unwrap());
+    assert_eq!(super::get(&pool, "alice").await.unwrap().unwrap().role, Role::Admin);
+
+    assert!(super::set_password(&pool, &bob.id, "new password").await.unwrap());
+    assert_eq!(super::authenticate(&pool, "bob", "battery staple").await.unwrap(), None);
+    assert!(super::authenticate(&pool, "bob", "new password").await.unwrap().is_some());
+
+    assert!(super::remove(&pool, &bob.id).await.unwrap());
+    assert!(!super::remove(&pool, &bob.id).await.unwrap());
+    assert_eq!(super::authenticate(&pool, "bob", "new password").await.unwrap(), None);
+
+    assert!(super::create(&pool, "", "long enough", Role::Viewer).await.is_err());
+    assert!(super::create(&pool, "token:admin", "long enough", Role::Viewer).await.is_err());
+    assert!(super::create(&pool, "carol", "short", Role::Viewer).await.is_err());
+    assert!(super::create(&pool, "alice", "long enough", Role::Viewer).await.is_err());
+}
+
+#[tokio::test]
+async fn session_test() {
+    // sessions are found by their secret until they expire, are closed,
+    // or the password of the user changes
+    let pool = make_pool().await;
+    let alice = super::create(&pool, "alice", "correct horse", Role::Viewer).await.unwrap();
+    let (session, secret) = super::open_session(&pool, &alice, 60).await.unwrap();
+    let (_, expired_secret) = super::open_session(&pool, &alice, 0).await.unwrap();
+    let (_, other_secret) = super::open_session(&pool, &alice, 60).await.unwrap();
+
+    assert_ne!(secret, session.csrf_token);
+    assert_eq!(super::find_session(&pool, &secret).await.unwrap(), Some(session.clone()));
+    assert_eq!(super::find_session(&pool, &expired_secret).await.unwrap(), None);
+    assert_eq!(super::purge_expired_sessions(&pool).await.unwrap(), 1);
+
+    assert!(super::close_session(&pool, &secret).await.unwrap());
+    assert!(!super::close_session(&pool, &secret).await.unwrap());
+    assert_eq!(super::find_session(&pool, &secret).await.unwrap(), None);
+
+    super::set_password(&pool, &alice.id, "new password").await.unwrap();
+    assert_eq!(super::find_session(&pool, &other_secret).await.unwrap(), None);
+}